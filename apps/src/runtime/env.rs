@@ -1,4 +1,15 @@
-use sel4_common::{bootinfo_common::BootInfo, structures_common::seL4_CapInitThreadTCB};
+use sel4_common::{
+    bit,
+    boot_handoff::BootMeasurement,
+    bootinfo_common::{BootInfo, UntypedDesc},
+    constants::{BI_FRAME_SIZE_BITS, CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS},
+    extra_bootinfo::{
+        ExtraBootInfoChunks, UserImageDesc, SEL4_BOOTINFO_HEADER_FDT,
+        SEL4_BOOTINFO_HEADER_MEASUREMENT, SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW,
+        SEL4_BOOTINFO_HEADER_USER_IMAGES,
+    },
+    structures_common::seL4_CapInitThreadTCB,
+};
 use spin::{Lazy, Mutex};
 
 pub struct Env {
@@ -33,3 +44,45 @@ pub fn get_env() -> &'static mut Env {
 pub fn get_bootinfo() -> &'static mut BootInfo {
     unsafe { &mut *(get_env().bootinfo as *mut BootInfo) }
 }
+
+/// 遍历extra bootinfo中的chunk，extra bootinfo紧跟在bootinfo页之后
+pub fn get_extra_bootinfo() -> ExtraBootInfoChunks<'static> {
+    let extra_bi = get_env().bootinfo + bit!(BI_FRAME_SIZE_BITS);
+    let extra_len = get_bootinfo().extra_len;
+    ExtraBootInfoChunks::new(unsafe {
+        core::slice::from_raw_parts(extra_bi as *const u8, extra_len)
+    })
+}
+
+/// 内核通过extra bootinfo传递的设备树
+pub fn get_fdt() -> Option<&'static [u8]> {
+    get_extra_bootinfo()
+        .find(|chunk| chunk.id == SEL4_BOOTINFO_HEADER_FDT)
+        .map(|chunk| chunk.content)
+}
+
+/// 遍历bootinfo.untyped中每一个untyped cap的描述符，包括放在extra bootinfo中的部分
+pub fn get_untyped_list() -> impl Iterator<Item = &'static UntypedDesc> {
+    let bootinfo = get_bootinfo();
+    let num = bootinfo.untyped.end - bootinfo.untyped.start;
+    let overflow = get_extra_bootinfo()
+        .find(|chunk| chunk.id == SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW)
+        .map_or(&[][..], |chunk| chunk.untyped_list());
+    bootinfo.untyped_list[..num.min(CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS)]
+        .iter()
+        .chain(overflow.iter())
+}
+
+/// elfloader加载的rootserver之外的用户镜像，可以通过它们的页面cap映射或启动它们
+pub fn get_user_images() -> &'static [UserImageDesc] {
+    get_extra_bootinfo()
+        .find(|chunk| chunk.id == SEL4_BOOTINFO_HEADER_USER_IMAGES)
+        .map_or(&[], |chunk| chunk.user_images())
+}
+
+/// elfloader对启动镜像的度量，可以作为证明值报告给远端
+pub fn get_boot_measurement() -> Option<BootMeasurement> {
+    get_extra_bootinfo()
+        .find(|chunk| chunk.id == SEL4_BOOTINFO_HEADER_MEASUREMENT)
+        .and_then(|chunk| chunk.measurement())
+}
@@ -1,11 +1,20 @@
 use sel4_common::{
-    invocation::{LABEL_CNODE_COPY, LABEL_NO_ERROR},
-    shared_types::MessageInfo,
-    structures_common::CapRights,
-    syscall_ids::{seL4_SysDebugDumpScheduler, seL4_SysDebugPutChar},
+    invocation::{
+        LABEL_CNODE_COPY, LABEL_NO_ERROR, LABEL_RISCV_ASID_CONTROL_MAKE_POOL,
+        LABEL_RISCV_ASID_POOL_ASSIGN, LABEL_RISCV_PAGE_GET_ADDRESS, LABEL_RISCV_PAGE_MAP,
+        LABEL_RISCV_PAGE_TABLE_MAP, LABEL_RISCV_PAGE_TABLE_UNMAP, LABEL_RISCV_PAGE_UNMAP,
+        LABEL_TCB_CONFIGURE, LABEL_TCB_READ_REGISTERS, LABEL_TCB_RESUME, LABEL_TCB_SET_PRIORITY,
+        LABEL_TCB_SUSPEND, LABEL_TCB_WRITE_REGISTERS, LABEL_UNTYPED_RETYPE,
+    },
+    shared_types::{seL4_UserContextWords, MessageInfo, UserContext},
+    structures_common::{CapRights, VMAttributes},
+    syscall_ids::{
+        seL4_SysBenchmarkFinalizeLog, seL4_SysBenchmarkResetLog, seL4_SysBenchmarkSetLogBuffer,
+        seL4_SysDebugDumpScheduler, seL4_SysDebugPutChar,
+    },
 };
 
-use crate::syscalls::{call_with_mrs, sys_send_recv};
+use crate::syscalls::{call_with_mrs, sys_null, sys_send_recv};
 use sel4_common::shared_types::IPCBuffer;
 
 use super::get_bootinfo;
@@ -62,6 +71,273 @@ pub fn sel4_cnode_copy(
     result
 }
 
+pub fn sel4_untyped_retype(
+    service: usize,
+    object_type: usize,
+    size_bits: usize,
+    root: usize,
+    node_index: usize,
+    node_depth: usize,
+    node_offset: usize,
+    num_objects: usize,
+) -> usize {
+    let tag = MessageInfo::new(LABEL_UNTYPED_RETYPE, 0, 1, 6);
+
+    /* Setup input capabilities. */
+    sel4_setcap(0, root);
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = object_type;
+    let mut mr1 = size_bits;
+    let mut mr2 = node_index;
+    let mut mr3 = node_depth;
+    sel4_setmr(4, node_offset);
+    sel4_setmr(5, num_objects);
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_riscv_page_table_map(
+    service: usize,
+    vspace: usize,
+    vaddr: usize,
+    attr: VMAttributes,
+) -> usize {
+    let tag = MessageInfo::new(LABEL_RISCV_PAGE_TABLE_MAP, 0, 1, 2);
+
+    /* Setup input capabilities. */
+    sel4_setcap(0, vspace);
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = vaddr;
+    let mut mr1 = attr.bits();
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_riscv_page_table_unmap(service: usize) -> usize {
+    let tag = MessageInfo::new(LABEL_RISCV_PAGE_TABLE_UNMAP, 0, 0, 0);
+    let mut mr0 = 0;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_riscv_page_map(
+    service: usize,
+    vspace: usize,
+    vaddr: usize,
+    rights: CapRights,
+    attr: VMAttributes,
+) -> usize {
+    let tag = MessageInfo::new(LABEL_RISCV_PAGE_MAP, 0, 1, 3);
+
+    /* Setup input capabilities. */
+    sel4_setcap(0, vspace);
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = vaddr;
+    let mut mr1 = rights.bits();
+    let mut mr2 = attr.bits();
+    let mut mr3 = 0;
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_riscv_page_unmap(service: usize) -> usize {
+    let tag = MessageInfo::new(LABEL_RISCV_PAGE_UNMAP, 0, 0, 0);
+    let mut mr0 = 0;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub struct RiscvPageGetAddress {
+    pub error: usize,
+    pub paddr: usize,
+}
+
+pub fn sel4_riscv_page_get_address(service: usize) -> RiscvPageGetAddress {
+    let tag = MessageInfo::new(LABEL_RISCV_PAGE_GET_ADDRESS, 0, 0, 0);
+    let mut mr0 = 0;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    RiscvPageGetAddress {
+        error: output_tag.label(),
+        /* Unmarshal result. */
+        paddr: mr0,
+    }
+}
+
+pub fn sel4_riscv_asid_control_make_pool(
+    service: usize,
+    untyped: usize,
+    root: usize,
+    index: usize,
+    depth: usize,
+) -> usize {
+    let tag = MessageInfo::new(LABEL_RISCV_ASID_CONTROL_MAKE_POOL, 0, 2, 2);
+
+    /* Setup input capabilities. */
+    sel4_setcap(0, untyped);
+    sel4_setcap(1, root);
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = index;
+    let mut mr1 = depth & 0xff;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_riscv_asid_pool_assign(service: usize, vspace: usize) -> usize {
+    let tag = MessageInfo::new(LABEL_RISCV_ASID_POOL_ASSIGN, 0, 1, 0);
+
+    /* Setup input capabilities. */
+    sel4_setcap(0, vspace);
+
+    let mut mr0 = 0;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+/// 读取线程的前count个寄存器，包括浮点寄存器，顺序见UserContext
+pub fn sel4_tcb_read_registers(
+    service: usize,
+    suspend_source: bool,
+    arch_flags: usize,
+    count: usize,
+    regs: &mut UserContext,
+) -> usize {
+    let tag = MessageInfo::new(LABEL_TCB_READ_REGISTERS, 0, 0, 2);
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = (suspend_source as usize & 0x1) | (arch_flags & 0xff) << 8;
+    let mut mr1 = count;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    let result = output_tag.label();
+
+    /* Unmarshal result. */
+    if result == LABEL_NO_ERROR {
+        let words = regs.as_words_mut();
+        let in_registers = [mr0, mr1, mr2, mr3];
+        for i in 0..count.min(seL4_UserContextWords) {
+            words[i] = match in_registers.get(i) {
+                Some(&mr) => mr,
+                None => sel4_getmr(i),
+            };
+        }
+    }
+    result
+}
+
+/// 写入线程的前count个寄存器，包括浮点寄存器，顺序见UserContext
+pub fn sel4_tcb_write_registers(
+    service: usize,
+    resume_target: bool,
+    arch_flags: usize,
+    count: usize,
+    regs: &UserContext,
+) -> usize {
+    let count = count.min(seL4_UserContextWords);
+    let tag = MessageInfo::new(LABEL_TCB_WRITE_REGISTERS, 0, 0, count + 2);
+    let words = regs.as_words();
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = (resume_target as usize & 0x1) | (arch_flags & 0xff) << 8;
+    let mut mr1 = count;
+    let mut mr2 = words[0];
+    let mut mr3 = words[1];
+    for i in 2..count {
+        sel4_setmr(i + 2, words[i]);
+    }
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+/// 设置线程的cspace、vspace和IPC buffer，buffer_frame为0（null cap）时线程没有IPC buffer
+pub fn sel4_tcb_configure(
+    service: usize,
+    cspace_root: usize,
+    vspace_root: usize,
+    buffer: usize,
+    buffer_frame: usize,
+) -> usize {
+    let tag = MessageInfo::new(LABEL_TCB_CONFIGURE, 0, 3, 1);
+
+    /* Setup input capabilities. */
+    sel4_setcap(0, cspace_root);
+    sel4_setcap(1, vspace_root);
+    sel4_setcap(2, buffer_frame);
+
+    /* Marshal and initialise parameters. */
+    let mut mr0 = buffer;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+
+    /* Perform the call, passing in-register arguments directly. */
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+/// 优先级不能高于调用者自己的优先级
+pub fn sel4_tcb_set_priority(service: usize, priority: usize) -> usize {
+    let tag = MessageInfo::new(LABEL_TCB_SET_PRIORITY, 0, 0, 1);
+    let mut mr0 = priority;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_tcb_suspend(service: usize) -> usize {
+    let tag = MessageInfo::new(LABEL_TCB_SUSPEND, 0, 0, 0);
+    let mut mr0 = 0;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
+pub fn sel4_tcb_resume(service: usize) -> usize {
+    let tag = MessageInfo::new(LABEL_TCB_RESUME, 0, 0, 0);
+    let mut mr0 = 0;
+    let mut mr1 = 0;
+    let mut mr2 = 0;
+    let mut mr3 = 0;
+    let output_tag = call_with_mrs(service, tag, &mut mr0, &mut mr1, &mut mr2, &mut mr3);
+    output_tag.label()
+}
+
 pub fn sel4_debug_dump_scheduler() {
     let mut unused0: usize = 0;
     let mut unused1: usize = 0;
@@ -80,6 +356,23 @@ pub fn sel4_debug_dump_scheduler() {
     );
 }
 
+/* the kernel trace log, only present when the kernel is built with the trace feature */
+
+/// 用frame作为内核日志，日志格式见sel4_common::trace，返回错误类型
+pub fn sel4_benchmark_set_log_buffer(frame_cptr: usize) -> usize {
+    sys_null(seL4_SysBenchmarkSetLogBuffer, frame_cptr)
+}
+
+/// 清空日志并开始记录
+pub fn sel4_benchmark_reset_log() -> usize {
+    sys_null(seL4_SysBenchmarkResetLog, 0)
+}
+
+/// 停止记录，返回写入过的条数，超过容量时只保留最新的
+pub fn sel4_benchmark_finalize_log() -> usize {
+    sys_null(seL4_SysBenchmarkFinalizeLog, 0)
+}
+
 pub fn sel4_setcap(i: usize, cptr: usize) {
     sel4_get_ipcbuffer().caps_or_badges[i] = cptr;
 }
@@ -88,6 +381,10 @@ pub fn sel4_setmr(i: usize, mr: usize) {
     sel4_get_ipcbuffer().msg[i] = mr;
 }
 
+pub fn sel4_getmr(i: usize) -> usize {
+    sel4_get_ipcbuffer().msg[i]
+}
+
 pub fn sel4_get_ipcbuffer() -> &'static mut IPCBuffer {
     unsafe { &mut *(get_bootinfo().ipc_buffer as *mut IPCBuffer) }
 }
@@ -1,6 +1,9 @@
 use core::arch::asm;
 
-use sel4_common::{shared_types::MessageInfo, syscall_ids::seL4_SysCall};
+use sel4_common::{
+    shared_types::MessageInfo,
+    syscall_ids::{seL4_SysCall, seL4_SysRecv, seL4_SysReply, seL4_SysReplyRecv, seL4_SysSend},
+};
 
 fn syscall(
     id: usize,
@@ -36,6 +39,7 @@ fn syscall(
     )
 }
 
+/// 返回a0，接收消息时为发送者的badge
 pub fn sys_send_recv(
     sys: usize,
     dest: usize,
@@ -45,7 +49,7 @@ pub fn sys_send_recv(
     mr2: &mut usize,
     mr3: &mut usize,
     out_info: &mut MessageInfo,
-) {
+) -> usize {
     let r = syscall(
         sys, dest, info_arg, *mr0 as _, *mr1 as _, *mr2 as _, *mr3 as _,
     );
@@ -54,6 +58,7 @@ pub fn sys_send_recv(
     *mr2 = r.4;
     *mr3 = r.5;
     *out_info = r.1;
+    r.0
 }
 
 pub fn call_with_mrs(
@@ -68,3 +73,62 @@ pub fn call_with_mrs(
     sys_send_recv(seL4_SysCall, _service, tag.0, mr0, mr1, mr2, mr3, &mut info);
     info
 }
+
+/* endpoint IPC with the message in registers only, Call and ReplyRecv take the
+ * kernel fastpath when the message is at most 4 words and carries no caps */
+
+pub fn send_with_mrs(
+    dest: usize,
+    tag: MessageInfo,
+    mr0: &mut usize,
+    mr1: &mut usize,
+    mr2: &mut usize,
+    mr3: &mut usize,
+) {
+    let mut info = MessageInfo(0);
+    sys_send_recv(seL4_SysSend, dest, tag.0, mr0, mr1, mr2, mr3, &mut info);
+}
+
+/// 返回收到的消息头和badge
+pub fn recv_with_mrs(
+    src: usize,
+    mr0: &mut usize,
+    mr1: &mut usize,
+    mr2: &mut usize,
+    mr3: &mut usize,
+) -> (MessageInfo, usize) {
+    let mut info = MessageInfo(0);
+    let badge = sys_send_recv(seL4_SysRecv, src, 0, mr0, mr1, mr2, mr3, &mut info);
+    (info, badge)
+}
+
+/// 通过最近一次接收得到的reply cap回复
+pub fn reply_with_mrs(
+    tag: MessageInfo,
+    mr0: &mut usize,
+    mr1: &mut usize,
+    mr2: &mut usize,
+    mr3: &mut usize,
+) {
+    let mut info = MessageInfo(0);
+    sys_send_recv(seL4_SysReply, 0, tag.0, mr0, mr1, mr2, mr3, &mut info);
+}
+
+/// 回复后在src上等待下一条消息，返回收到的消息头和badge
+pub fn reply_recv_with_mrs(
+    src: usize,
+    tag: MessageInfo,
+    mr0: &mut usize,
+    mr1: &mut usize,
+    mr2: &mut usize,
+    mr3: &mut usize,
+) -> (MessageInfo, usize) {
+    let mut info = MessageInfo(0);
+    let badge = sys_send_recv(seL4_SysReplyRecv, src, tag.0, mr0, mr1, mr2, mr3, &mut info);
+    (info, badge)
+}
+
+/// 不带消息的系统调用，参数和结果都在a0中，见seL4的riscv_sys_null
+pub fn sys_null(sys: usize, arg: usize) -> usize {
+    syscall(sys, arg, 0, 0, 0, 0, 0).0
+}
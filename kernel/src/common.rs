@@ -1,5 +1,6 @@
 use sel4_common::{
     bit,
+    boot_handoff::BOOT_HANDOFF_MAX_USER_IMAGES,
     constants::{seL4_PageBits, seL4_TCBBits},
 };
 
@@ -17,22 +18,66 @@ macro_rules! is_aligned {
     };
 }
 
-pub const KERNEL_HEAP_SIZE: usize = 0x4000;
+#[cfg(all(feature = "sv48", feature = "sv57"))]
+compile_error!("features sv48 and sv57 are mutually exclusive");
 
-pub const KERNEL_ELF_BASE: usize = 0x84000000;
+/* paging mode: Sv39 by default, Sv48 or Sv57 with the matching feature */
+#[cfg(not(any(feature = "sv48", feature = "sv57")))]
+pub const CONFIG_PT_LEVELS: usize = 3;
+#[cfg(feature = "sv48")]
+pub const CONFIG_PT_LEVELS: usize = 4;
+#[cfg(feature = "sv57")]
+pub const CONFIG_PT_LEVELS: usize = 5;
+
+/* satp.MODE: Sv39 = 8, Sv48 = 9, Sv57 = 10 */
+pub const SATP_MODE: usize = CONFIG_PT_LEVELS + 5;
+
+/* the kernel window maps physical memory starting at PADDR_BASE to PPTR_BASE,
+ * it takes the upper half of the address space except for the last root entry */
+pub const PADDR_BASE: usize = 0x0;
+#[cfg(not(any(feature = "sv48", feature = "sv57")))]
+pub const PPTR_BASE: usize = 0xFFFFFFC000000000;
+#[cfg(not(any(feature = "sv48", feature = "sv57")))]
+pub const PPTR_TOP: usize = 0xFFFFFFFF80000000;
+#[cfg(feature = "sv48")]
+pub const PPTR_BASE: usize = 0xFFFF800000000000;
+#[cfg(feature = "sv48")]
+pub const PPTR_TOP: usize = 0xFFFFFF8000000000;
+#[cfg(feature = "sv57")]
+pub const PPTR_BASE: usize = 0xFF00000000000000;
+#[cfg(feature = "sv57")]
+pub const PPTR_TOP: usize = 0xFFFF000000000000;
+pub const PPTR_BASE_OFFSET: usize = PPTR_BASE - PADDR_BASE;
+pub const PADDR_TOP: usize = PPTR_TOP - PPTR_BASE_OFFSET;
+
+/* the kernel image is linked into the last 2GiB of the address space in every
+ * mode, the elfloader picks its physical address, see kpptr_to_paddr */
+pub const KERNEL_ELF_BASE: usize = 0xFFFFFFFF84000000;
 pub const PAGE_PTES: usize = PAGE_SIZE / 8;
 pub const PTE_FLAG_BITS: usize = 10;
 
-pub const USER_TOP: usize = 0x3ffffff000;
+/* user addresses are the lower half of the address space, minus the last page */
+pub const USER_TOP: usize = bit!(PT_INDEX_BITS * CONFIG_PT_LEVELS + seL4_PageBits - 1) - PAGE_SIZE;
 pub const CONFIG_PADDR_USER_DEVICE_TOP: usize = 0x8000000000;
 
+/* physical memory used when the bootloader does not pass a device tree */
 pub const AVAIL_REGION_START: usize = 0x80200000;
 pub const AVAIL_REGION_END: usize = 0x90000000;
 
+/* boot time region lists are fixed arrays, a device tree with more regions
+ * than these is rejected at boot */
+pub const MAX_NUM_AVAIL_REG: usize = 16;
+pub const MAX_NUM_DT_RESV_REG: usize = 16;
+/* the device tree's, the kernel, the user images, the DTB and the elfloader's park page */
+pub const MAX_NUM_RESV_REG: usize = MAX_NUM_DT_RESV_REG + BOOT_HANDOFF_MAX_USER_IMAGES + 3;
+/* every reserved region can split an available one, plus the root server objects */
+pub const MAX_NUM_FREEMEM_REG: usize = MAX_NUM_AVAIL_REG + MAX_NUM_RESV_REG + 1;
+
 pub const CONFIG_ROOT_CNODE_SIZE_BITS: usize = 13;
 
 pub const seL4_MinUntypedBits: usize = 4;
 pub const seL4_MaxUntypedBits: usize = 38;
+pub const CONFIG_RETYPE_FAN_OUT_LIMIT: usize = 256;
 pub const WORD_BITS: usize = 64;
 
 pub const PAGE_SIZE: usize = bit!(seL4_PageBits);
@@ -40,8 +85,12 @@ pub const PT_INDEX_BITS: usize = 9;
 
 pub const TCB_SIZE_BITS: usize = seL4_TCBBits - 1;
 pub const TCB_OFFSET: usize = bit!(TCB_SIZE_BITS);
+/* thread name kept in the TCB for debugging, padded with 0 */
+pub const TCB_NAME_LENGTH: usize = 40;
 
 // threads
 pub const CONFIG_NUM_PRIORITIES: usize = 256;
 pub const seL4_MinPrio: usize = 0;
 pub const seL4_MaxPrio: usize = CONFIG_NUM_PRIORITIES - 1;
+/* one bit per priority in the ready queue bitmap */
+pub const L2_BITMAP_SIZE: usize = (CONFIG_NUM_PRIORITIES + WORD_BITS - 1) / WORD_BITS;
@@ -1,21 +1,117 @@
-use crate::println;
+use crate::{
+    machine::{Paddr, PLATFORM},
+    println,
+};
+
+/* register layout of the SiFive PLIC, see the riscv-plic-spec */
+const PLIC_PRIO_OFFSET: usize = 0x0;
+const PLIC_EN_OFFSET: usize = 0x2000;
+const PLIC_EN_PER_CONTEXT: usize = 0x80;
+const PLIC_THRES_OFFSET: usize = 0x200000;
+const PLIC_THRES_PER_CONTEXT: usize = 0x1000;
+const PLIC_CLAIM_OFFSET: usize = 0x200004;
+
+/* every hart has an M-mode context followed by an S-mode context */
+const PLIC_SVC_CONTEXT: usize = 1;
 
 fn plic_get_current_hart_id() -> usize {
     0
 }
 
+fn plic_context(hart_id: usize) -> usize {
+    hart_id * 2 + PLIC_SVC_CONTEXT
+}
+
+fn plic_write(base: Paddr, offset: usize, val: u32) {
+    unsafe {
+        Paddr(base.0 + offset)
+            .as_raw_ptr_mut::<u32>()
+            .write_volatile(val)
+    };
+}
+
+fn plic_read(base: Paddr, offset: usize) -> u32 {
+    unsafe {
+        Paddr(base.0 + offset)
+            .as_raw_ptr_mut::<u32>()
+            .read_volatile()
+    }
+}
+
+/// 取得当前hart上等待处理的中断号，没有时返回0
+pub fn plic_get_claim() -> usize {
+    let plic = match PLATFORM.lock().plic {
+        Some(plic) => plic,
+        None => return 0,
+    };
+    let ctx = plic_context(plic_get_current_hart_id());
+    plic_read(
+        plic.reg.start,
+        PLIC_CLAIM_OFFSET + ctx * PLIC_THRES_PER_CONTEXT,
+    ) as usize
+}
+
+/// 通知PLIC中断irq已经处理完
+pub fn plic_complete_claim(irq: usize) {
+    let plic = match PLATFORM.lock().plic {
+        Some(plic) => plic,
+        None => return,
+    };
+    let ctx = plic_context(plic_get_current_hart_id());
+    plic_write(
+        plic.reg.start,
+        PLIC_CLAIM_OFFSET + ctx * PLIC_THRES_PER_CONTEXT,
+        irq as u32,
+    );
+}
+
+/// 屏蔽或打开当前hart S态上下文中的某个中断
+pub fn plic_mask_irq(disable: bool, irq: usize) {
+    let plic = match PLATFORM.lock().plic {
+        Some(plic) => plic,
+        None => return,
+    };
+    let ctx = plic_context(plic_get_current_hart_id());
+    let addr =
+        Paddr(plic.reg.start.0 + PLIC_EN_OFFSET + ctx * PLIC_EN_PER_CONTEXT + (irq / 32) * 4);
+    unsafe {
+        let ptr = addr.as_raw_ptr_mut::<u32>();
+        let val = ptr.read_volatile();
+        let bit = 1 << (irq % 32);
+        ptr.write_volatile(if disable { val & !bit } else { val | bit });
+    }
+}
+
 pub fn plic_init_hart() {
-    println!("no PLIC present, skip platform specific initialisation");
-    // let hart_id = plic_get_current_hart_id();
-    // let mut i = 1;
-    // let PLIC_NUM_INTERRUPTS = 0;
-
-    // while i <= PLIC_NUM_INTERRUPTS {
-    //     /* Disable interrupts */
-    //     plic_mask_irq(true, i);
-    //     i += 1;
-    // }
-
-    // /* Set threshold to zero */
-    // writel(0, (PLIC_PPTR_BASE + plic_thres_offset(hart_id, PLIC_SVC_CONTEXT)));
+    let plic = match PLATFORM.lock().plic {
+        Some(plic) => plic,
+        None => {
+            println!("no PLIC present, skip platform specific initialisation");
+            return;
+        }
+    };
+    let ctx = plic_context(plic_get_current_hart_id());
+
+    /* Disable interrupts, source 0 does not exist */
+    for i in 1..=plic.ndev {
+        plic_mask_irq(true, i);
+    }
+
+    /* Set threshold to zero */
+    plic_write(
+        plic.reg.start,
+        PLIC_THRES_OFFSET + ctx * PLIC_THRES_PER_CONTEXT,
+        0,
+    );
+}
+
+pub fn plic_init_controller() {
+    let plic = match PLATFORM.lock().plic {
+        Some(plic) => plic,
+        None => return,
+    };
+    /* give every source the lowest non-zero priority so it can be delivered */
+    for i in 1..=plic.ndev {
+        plic_write(plic.reg.start, PLIC_PRIO_OFFSET + i * 4, 1);
+    }
 }
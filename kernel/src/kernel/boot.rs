@@ -1,75 +1,131 @@
-use core::cmp::max;
+use core::{
+    cmp::max,
+    mem::{align_of, size_of},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use alloc::vec::Vec;
 use riscv::register::{sie, stvec};
 use sel4_common::{
     bit,
+    boot_handoff::{
+        BootHandoff, BootMeasurement, ImageRegion, ImageSegment, BOOT_HANDOFF_VERSION,
+        MEASUREMENT_SIGNED, MEASUREMENT_VERIFIED, SEGMENT_W, SEGMENT_X,
+    },
     bootinfo_common::{BootInfo, SlotRegion, UntypedDesc},
     constants::{
         seL4_ASIDPoolBits, seL4_PageBits, seL4_PageTableBits, seL4_SlotBits, seL4_TCBBits,
         seL4_VSpaceBits, BI_FRAME_SIZE_BITS, CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS,
     },
-    round_down,
+    extra_bootinfo::{
+        BootInfoHeader, UserImageDesc, BOOTINFO_HEADER_SIZE, SEL4_BOOTINFO_HEADER_FDT,
+        SEL4_BOOTINFO_HEADER_MEASUREMENT, SEL4_BOOTINFO_HEADER_PADDING,
+        SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW, SEL4_BOOTINFO_HEADER_USER_IMAGES,
+    },
+    round_down, round_up,
     structures_common::*,
 };
 use spin::{Lazy, Mutex};
 
 use crate::{
     common::*,
-    drivers::plic_init_hart,
+    drivers::{plic_init_controller, plic_init_hart},
     get_level_pgbits, get_level_pgsize, is_aligned,
     kernel::bootinfo::debug_print_bi_info,
-    machine::{clear_memory, registerset::Rv64Reg, Paddr, Pregion, Vaddr, Vregion},
-    max_free_index,
+    machine::{
+        clear_memory,
+        fdt::{self, DeviceTree, PlatformDevices},
+        kpptr_to_paddr,
+        registerset::Rv64Reg,
+        set_kernel_elf_paddr_base, Paddr, Pregion, Vaddr, Vregion, PLATFORM,
+    },
     object::cnode::{cte_insert, derive_cap},
-    println,
+    print, println,
 };
 
 use super::{
-    heap::init_heap,
+    array_vec::ArrayVec,
+    kobj::{kernel_lock, KObj},
     statedata::{ksCurThread, ksIdleThread, ksSchedulerAction, SchedulerAction},
-    structures::{CapSlot, Capability},
+    structures::Capability,
     thread::{
-        activate_thread, schedule, TCBInner, ThreadPointer, ThreadState_IdleThreadState,
-        ThreadState_Running, IDLE_THREAD_TCB, TCB, THREAD_LIST,
+        activate_thread, configure_idle_thread, schedule, Tcb, ThreadPointer,
+        ThreadState_IdleThreadState, ThreadState_Running, IDLE_THREAD_TCB,
     },
     vspace::*,
 };
 
+/* worst case of untyped_regions: a gap before and after every RAM region,
+ * the reused boot memory and every free region */
+const MAX_NUM_UNTYPED_REG: usize = MAX_NUM_AVAIL_REG + 2 + MAX_NUM_FREEMEM_REG;
+
 struct BootState<'a> {
     slot_pos_cur: usize,
-    reserved: Vec<Pregion>,
-    freemem: Vec<Pregion>,
+    reserved: ArrayVec<Pregion, MAX_NUM_AVAIL_REG>,
+    freemem: ArrayVec<Pregion, MAX_NUM_FREEMEM_REG>,
     bi_frame: Option<&'a mut BootInfo>,
+    /* extra bootinfo chunk for the untypeds that do not fit into bi_frame */
+    untyped_overflow: Paddr,
+    untyped_overflow_max: usize,
+    /* extra bootinfo chunk describing the user images besides the root server */
+    user_images: Paddr,
 }
 
 static BOOT_STATE: Lazy<Mutex<BootState>> = Lazy::new(|| {
     Mutex::new(BootState {
         slot_pos_cur: 0,
-        reserved: Vec::new(),
-        freemem: Vec::new(),
+        reserved: ArrayVec::new(),
+        freemem: ArrayVec::new(),
         bi_frame: None,
+        untyped_overflow: Paddr(0),
+        untyped_overflow_max: 0,
+        user_images: Paddr(0),
     })
 });
 
+/// extra bootinfo中FDT chunk的长度，dtb只按4字节对齐，补齐到下一个chunk头部的对齐
+#[link_section = ".boot.text"]
+fn fdt_chunk_len(dtb_size: usize) -> usize {
+    let align = align_of::<BootInfoHeader>();
+    (BOOTINFO_HEADER_SIZE + dtb_size + align - 1) & !(align - 1)
+}
+
 #[link_section = ".boot.text"]
 fn clear_bss() {
     extern "C" {
         fn sbss();
         fn ebss();
     }
-    clear_memory(Paddr(sbss as usize), ebss as usize - sbss as usize);
+    /* sbss is a kernel image address, not a physical one */
+    unsafe { core::ptr::write_bytes(sbss as *mut u8, 0, ebss as usize - sbss as usize) };
 }
 
 #[link_section = ".boot.text"]
-fn check_available_memory(avail_reg: Pregion) -> bool {
-    println!("available phys memory regions: {}", 1);
-    println!("  {:#x?}", avail_reg);
+fn check_available_memory(avail_regs: &[Pregion]) -> bool {
+    println!("available phys memory regions: {}", avail_regs.len());
+    if avail_regs.is_empty() {
+        println!("ERROR: no available physical memory\n");
+        return false;
+    }
+    for i in 0..avail_regs.len() {
+        println!("  {:#x?}", avail_regs[i]);
+        if avail_regs[i].start.0 > avail_regs[i].end.0 {
+            println!("ERROR: memory region {:#x?} has start > end\n", i + 1);
+            return false;
+        }
+        if avail_regs[i].is_empty() {
+            println!("ERROR: memory region {:#x?} empty\n", i + 1);
+            return false;
+        }
+        if i > 0 && avail_regs[i - 1].end.0 > avail_regs[i].start.0 {
+            println!("ERROR: memory region {:#x?} in wrong order\n", i + 1);
+            return false;
+        }
+    }
     true
 }
 
 #[link_section = ".boot.text"]
-fn check_reserved_memory(reserved: &Vec<Pregion>) -> bool {
+fn check_reserved_memory(reserved: &[Pregion]) -> bool {
     println!("reserved virt address space regions: {}", reserved.len());
     for i in 0..reserved.len() {
         println!("  {:#x?}", reserved[i]);
@@ -86,21 +142,28 @@ fn check_reserved_memory(reserved: &Vec<Pregion>) -> bool {
 }
 
 #[link_section = ".boot.text"]
-fn merge_regions(regs: &Vec<Pregion>) -> Vec<Pregion> {
-    let mut merged = Vec::<Pregion>::new();
+fn merge_regions<const N: usize>(regs: &[Pregion]) -> ArrayVec<Pregion, N> {
+    let mut merged = ArrayVec::<Pregion, N>::new();
     for r in regs {
-        if merged.is_empty() || merged.last().unwrap().end.0 != r.start.0 {
+        /* regions are sorted by start, so overlapping or adjacent ones are neighbours */
+        if merged.is_empty() || merged.last().unwrap().end.0 < r.start.0 {
             merged.push(*r);
         } else {
-            merged.last_mut().unwrap().end = r.end;
+            let last = merged.last_mut().unwrap();
+            last.end = Paddr(last.end.0.max(r.end.0));
         }
     }
     merged
 }
 
 #[link_section = ".boot.text"]
-fn remove_empty_regions(regs: &Vec<Pregion>) -> Vec<Pregion> {
-    let mut ret = Vec::<Pregion>::new();
+fn sort_regions(regs: &mut [Pregion]) {
+    regs.sort_unstable_by_key(|r| r.start.0);
+}
+
+#[link_section = ".boot.text"]
+fn remove_empty_regions<const N: usize>(regs: &[Pregion]) -> ArrayVec<Pregion, N> {
+    let mut ret = ArrayVec::<Pregion, N>::new();
     for r in regs {
         if !r.is_empty() {
             ret.push(*r);
@@ -137,11 +200,16 @@ fn calculate_rootserver_size(it_v_reg: Vregion, extra_bi_size_bits: usize) -> us
     size + riscv_get_n_paging(it_v_reg) * bit!(seL4_PageTableBits)
 }
 
-/// 分配大小为extra_bi_size_bits的extra_bootinfo
+/// 当extra_bootinfo不小于接下来要分配的对象时分配它，以保证所有对象都是对齐的
 #[link_section = ".boot.text"]
-fn alloc_extra_bi(extra_bi_size_bits: usize) {
-    if extra_bi_size_bits != 0 {
-        unimplemented!();
+fn maybe_alloc_extra_bi(
+    rootserver_mem: &mut Pregion,
+    extra_bi: &mut Paddr,
+    cmp_size_bits: usize,
+    extra_bi_size_bits: usize,
+) {
+    if extra_bi_size_bits >= cmp_size_bits && extra_bi.0 == 0 {
+        *extra_bi = alloc_rootserver_obj(rootserver_mem, extra_bi_size_bits, 1);
     }
 }
 
@@ -183,13 +251,29 @@ fn create_rootserver_objects(
 ) -> RootServer {
     let end = start + size;
     let mut rootserver_mem = Pregion::new(Paddr(start), Paddr(start + size));
-    alloc_extra_bi(extra_bi_size_bits);
+    let mut extra_bi = Paddr(0);
+    maybe_alloc_extra_bi(&mut rootserver_mem, &mut extra_bi, max, extra_bi_size_bits);
 
     // /* the root cnode is at least 4k, so it could be larger or smaller than a pd. */
     let cnode_size_bits = CONFIG_ROOT_CNODE_SIZE_BITS + seL4_SlotBits;
     let cnode = alloc_rootserver_obj(&mut rootserver_mem, cnode_size_bits, 1);
+    maybe_alloc_extra_bi(
+        &mut rootserver_mem,
+        &mut extra_bi,
+        seL4_VSpaceBits,
+        extra_bi_size_bits,
+    );
     let vspace = alloc_rootserver_obj(&mut rootserver_mem, seL4_VSpaceBits, 1);
 
+    /* at this point we are up to creating 4k objects - which is the min size of
+     * extra_bi so this is the last chance to allocate it */
+    maybe_alloc_extra_bi(
+        &mut rootserver_mem,
+        &mut extra_bi,
+        seL4_PageBits,
+        extra_bi_size_bits,
+    );
+
     assert_eq!(seL4_ASIDPoolBits, seL4_PageBits);
     let asid_pool = alloc_rootserver_obj(&mut rootserver_mem, seL4_ASIDPoolBits, 1);
     let ipc_buf = alloc_rootserver_obj(&mut rootserver_mem, seL4_PageBits, 1);
@@ -212,40 +296,51 @@ fn create_rootserver_objects(
         asid_pool,
         ipc_buf,
         boot_info,
-        extra_bi: Paddr(0),
+        extra_bi,
         tcb,
         paging: Pregion::new(paging_start, paging_end),
     }
 }
 
-/// 初始化freemem，如果成功返回一个rootserver结构体 + freemem vector
-///
-/// rootserver struct记录rootserver各个对象的起始地址，freemem vector记录哪些空闲内存可用
+/// 初始化freemem，如果成功返回freemem vector，记录哪些空闲内存可用
 #[link_section = ".boot.text"]
 fn init_freemem(
-    reserved: &Vec<Pregion>,
-    avail_reg: Pregion,
-    it_v_reg: Vregion,
-    extra_bi_size_bits: usize,
-) -> Option<(RootServer, Vec<Pregion>)> {
-    if !check_available_memory(avail_reg) {
+    reserved: &[Pregion],
+    avail_regs: &[Pregion],
+) -> Option<ArrayVec<Pregion, MAX_NUM_FREEMEM_REG>> {
+    if !check_available_memory(avail_regs) {
         return None;
     }
     if !check_reserved_memory(reserved) {
         return None;
     }
-    let reserved = merge_regions(reserved);
-    let mut freemem = Vec::<Pregion>::new();
-
-    let mut a = avail_reg;
-    for r in reserved.iter() {
-        assert!(r.start.0 >= a.start.0 && r.end.0 <= a.end.0);
-        freemem.push(Pregion::new(a.start, r.start));
-        a.start = r.end;
+    let reserved: ArrayVec<Pregion, MAX_NUM_RESV_REG> = merge_regions(reserved);
+    let mut freemem = ArrayVec::<Pregion, MAX_NUM_FREEMEM_REG>::new();
+
+    /* freemem = avail_regs - reserved, both are sorted and disjoint */
+    for &avail in avail_regs.iter() {
+        let mut a = avail;
+        for r in reserved.iter() {
+            if r.end.0 <= a.start.0 || r.start.0 >= a.end.0 {
+                continue;
+            }
+            freemem.push(Pregion::new(a.start, Paddr(r.start.0.max(a.start.0))));
+            a.start = Paddr(r.end.0.min(a.end.0));
+        }
+        freemem.push(a);
     }
-    freemem.push(a);
-    freemem = remove_empty_regions(&freemem);
+    Some(remove_empty_regions(&freemem))
+}
 
+/// 在freemem中为rootserver对象找到空间，如果成功返回一个rootserver结构体
+///
+/// rootserver struct记录rootserver各个对象的起始地址
+#[link_section = ".boot.text"]
+fn alloc_rootserver(
+    freemem: &mut ArrayVec<Pregion, MAX_NUM_FREEMEM_REG>,
+    it_v_reg: Vregion,
+    extra_bi_size_bits: usize,
+) -> Option<RootServer> {
     // /* now try to fit the root server objects into a region */
     let size = calculate_rootserver_size(it_v_reg, extra_bi_size_bits);
     let max = rootserver_max_size_bits(extra_bi_size_bits);
@@ -268,7 +363,7 @@ fn init_freemem(
             println!("final freemem = {:#x?}", freemem);
             /* Regions i and (i + 1) are now well defined, ordered, disjoint,
              * and unallocated, so we can return successfully. */
-            return Some((rootserver, freemem));
+            return Some(rootserver);
         }
     }
 
@@ -281,33 +376,179 @@ fn init_freemem(
 }
 
 #[link_section = ".boot.text"]
-fn riscv_init_freemem(ui_reg: Pregion, it_v_reg: Vregion) -> RootServer {
-    let mut res_reg = Vec::new(); // reserved region
+fn riscv_init_freemem(
+    ui_reg: Pregion,
+    extra_images: &[ImageRegion],
+    dtb_reg: Pregion,
+    park_reg: Pregion,
+    dt: &DeviceTree,
+) {
+    let mut res_reg = ArrayVec::<Pregion, MAX_NUM_RESV_REG>::new(); // reserved region
+    res_reg.extend(dt.reserved.iter().copied());
     extern "C" {
         fn ki_end();
     }
-    let kernel_reg = Pregion::new(Paddr(KERNEL_ELF_BASE), Paddr(ki_end as _));
+    let kernel_reg = Pregion::new(
+        Paddr(kpptr_to_paddr(KERNEL_ELF_BASE)),
+        Paddr(kpptr_to_paddr(ki_end as _)),
+    );
     res_reg.push(kernel_reg);
     res_reg.push(ui_reg);
-
-    let avail_reg = Pregion::new(Paddr(AVAIL_REGION_START), Paddr(AVAIL_REGION_END));
-    let (rootserver, freemem) = init_freemem(&res_reg, avail_reg, it_v_reg, 0).unwrap();
+    for image in extra_images {
+        res_reg.push(Pregion::new(Paddr(image.phys_start), Paddr(image.phys_end)));
+    }
+    if !dtb_reg.is_empty() {
+        res_reg.push(dtb_reg);
+    }
+    /* harts the elfloader did not hand to the kernel may be waiting there */
+    if !park_reg.is_empty() {
+        res_reg.push(park_reg);
+    }
+    sort_regions(&mut res_reg);
+    let res_reg: ArrayVec<Pregion, MAX_NUM_RESV_REG> = merge_regions(&res_reg);
+
+    /* only memory covered by the kernel window can be used by the kernel */
+    let mut avail_regs = ArrayVec::<Pregion, MAX_NUM_AVAIL_REG>::new();
+    for r in dt.memory.iter() {
+        let reg = Pregion::new(
+            Paddr(r.start.0.max(PADDR_BASE)),
+            Paddr(r.end.0.min(PADDR_TOP)),
+        );
+        if reg.start.0 < reg.end.0 {
+            avail_regs.push(reg);
+        } else {
+            println!("WARNING: memory region {:#x?} outside the kernel window", r);
+        }
+    }
+    sort_regions(&mut avail_regs);
+    let avail_regs: ArrayVec<Pregion, MAX_NUM_AVAIL_REG> = merge_regions(&avail_regs);
+    let freemem = init_freemem(&res_reg, &avail_regs).unwrap();
 
     let mut bs = BOOT_STATE.lock();
     bs.freemem = freemem;
-    bs.reserved = Vec::from([avail_reg]);
+    /* all of RAM counts as reserved, so that no device untypeds cover it */
+    bs.reserved = avail_regs;
+}
+
+/// 从freemem中分配rootserver对象
+#[link_section = ".boot.text"]
+fn create_rootserver(it_v_reg: Vregion, extra_bi_size_bits: usize) -> RootServer {
+    let mut bs = BOOT_STATE.lock();
+    alloc_rootserver(&mut bs.freemem, it_v_reg, extra_bi_size_bits)
+        .expect("no memory for the root server objects")
+}
 
-    rootserver
+/// 将一块内存按大小和对齐切分成若干untyped，每一项为(起始地址, size_bits)
+struct UntypedSplit {
+    reg: Pregion,
+}
+
+impl Iterator for UntypedSplit {
+    type Item = (Paddr, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.reg.is_empty() {
+            let mut size_bits = usize::BITS as usize
+                - 1
+                - (self.reg.end.0 - self.reg.start.0).leading_zeros() as usize;
+            size_bits = size_bits.min(seL4_MaxUntypedBits);
+            if self.reg.start.0 != 0 {
+                size_bits = size_bits.min(self.reg.start.0.trailing_zeros() as _);
+            }
+            let start = self.reg.start;
+            self.reg.start.0 += bit!(size_bits);
+            if size_bits >= seL4_MinUntypedBits {
+                return Some((start, size_bits));
+            }
+        }
+        None
+    }
+}
+
+/// 需要创建untyped的所有区域，每一项为(是否为设备内存, 区域)
+///
+/// 设备内存为RAM之间的空隙，相邻的空隙合并为一个区域，以减少untyped的数量
+#[link_section = ".boot.text"]
+fn untyped_regions(boot_mem_reuse_reg: Pregion) -> ArrayVec<(bool, Pregion), MAX_NUM_UNTYPED_REG> {
+    let bs = BOOT_STATE.lock();
+    let mut regs = ArrayVec::new();
+    let mut start = 0;
+    for res in bs.reserved.iter() {
+        if start < res.start.0 {
+            regs.push((true, Pregion::new(Paddr(start), res.start)));
+        }
+        start = start.max(res.end.0);
+    }
+    if start < CONFIG_PADDR_USER_DEVICE_TOP {
+        regs.push((
+            true,
+            Pregion::new(Paddr(start), Paddr(CONFIG_PADDR_USER_DEVICE_TOP)),
+        ));
+    }
+    regs.push((false, boot_mem_reuse_reg));
+    for &reg in bs.freemem.iter() {
+        regs.push((false, reg));
+    }
+    regs
+}
+
+/// bootinfo中放不下的untyped描述符个数的上界
+///
+/// 在分配rootserver对象之前调用，此时还不知道它们会把哪个空闲区域切开
+#[link_section = ".boot.text"]
+fn calculate_untyped_overflow(boot_mem_reuse_reg: Pregion) -> usize {
+    let mut n = 0;
+    for &(_, reg) in untyped_regions(boot_mem_reuse_reg).iter() {
+        n += UntypedSplit { reg }.count();
+    }
+    /* the root server objects split one free region, each of the two new
+     * boundaries adds at most one untyped per size */
+    n += 2 * (seL4_MaxUntypedBits - seL4_MinUntypedBits + 1);
+    n.saturating_sub(CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS)
 }
 
 #[link_section = ".boot.text"]
 fn init_cpu() {
     activate_kernel_vspace();
+    /* also flushes the TLB entries left over from the elfloader */
+    probe_hw_asid_bits();
     extern "C" {
         fn trap_entry();
     }
     unsafe { stvec::write(trap_entry as _, stvec::TrapMode::Direct) };
-    init_local_irq_controller();
+}
+
+/// 读取设备树得到物理内存、保留内存和平台设备，没有设备树时使用默认的内存区域
+///
+/// 设备树通过内核窗口访问，必须在init_cpu之后调用
+#[link_section = ".boot.text"]
+fn init_plat(dtb_addr_p: Paddr, dtb_size: usize) -> DeviceTree {
+    let dt = if dtb_addr_p.0 != 0 && dtb_size != 0 {
+        let dtb = unsafe { core::slice::from_raw_parts(dtb_addr_p.as_raw_ptr::<u8>(), dtb_size) };
+        fdt::parse(dtb)
+    } else {
+        None
+    };
+    let dt = match dt {
+        Some(dt) if !dt.memory.is_empty() => dt,
+        _ => {
+            println!("no usable device tree, fall back to the default memory region");
+            let mut memory = ArrayVec::new();
+            memory.push(Pregion::new(
+                Paddr(AVAIL_REGION_START),
+                Paddr(AVAIL_REGION_END),
+            ));
+            DeviceTree {
+                memory,
+                reserved: ArrayVec::new(),
+                devices: PlatformDevices::empty(),
+            }
+        }
+    };
+    println!("platform devices: {:#x?}", dt.devices);
+    *PLATFORM.lock() = dt.devices;
+    plic_init_controller();
+    dt
 }
 
 #[link_section = ".boot.text"]
@@ -325,10 +566,20 @@ fn init_local_irq_controller() {
     }
 }
 
-// #[link_section = ".boot.text"]
-// fn calculate_extra_bi_size_bits() -> usize {
-//     0
-// }
+/// extra_bootinfo占用的内存大小（以2的幂计），至少为一页
+#[link_section = ".boot.text"]
+fn calculate_extra_bi_size_bits(extra_size: usize) -> usize {
+    if extra_size == 0 {
+        return 0;
+    }
+    let clzl_ret = (round_up!(extra_size, seL4_PageBits) as usize).leading_zeros() as usize;
+    let mut msb = WORD_BITS - 1 - clzl_ret;
+    /* If region is bigger than a page, make sure we overallocate rather than underallocate */
+    if extra_size > bit!(msb) {
+        msb += 1;
+    }
+    msb
+}
 
 impl RootServer {
     #[link_section = ".boot.text"]
@@ -365,18 +616,18 @@ impl RootServer {
         //  cap_t      lvl1pt_cap;
         //  vptr_t     pt_vptr;
 
-        unsafe { self.vspace.as_mut::<PageTable>().map_kernel_window() }
-
         let root_pt_cap = Capability::cap_page_table_cap_new(
             IT_ASID,            /* capPTMappedASID    */
             self.vspace.0 as _, /* capPTBasePtr       */
             true,               /* capPTIsMapped      */
             self.vspace.0 as _, /* capPTMappedAddress */
         );
+        copy_global_mappings(&KObj::<PageTable>::from_cap(&root_pt_cap).unwrap());
         root_cnode_cap.cnode_write_slot_at(seL4_CapInitThreadVSpace, root_pt_cap);
 
-        //  /* create all n level PT caps necessary to cover userland image in 4KiB pages */
-        for i in 0..2usize {
+        /* create all n level PT caps necessary to cover userland image in 4KiB pages */
+        let slot_pos_before = BOOT_STATE.lock().slot_pos_cur;
+        for i in 0..CONFIG_PT_LEVELS - 1 {
             let mut pt_vptr = round_down!(it_v_reg.start.0, get_level_pgbits!(i));
             while pt_vptr < it_v_reg.end.0 {
                 provide_cap(
@@ -387,10 +638,10 @@ impl RootServer {
             }
         }
 
-        //  seL4_SlotPos slot_pos_after = ndks_boot.slot_pos_cur;
-        //  ndks_boot.bi_frame->userImagePaging = (seL4_SlotRegion) {
-        //      slot_pos_before, slot_pos_after
-        //  };
+        let mut bs = BOOT_STATE.lock();
+        let slot_pos_after = bs.slot_pos_cur;
+        bs.bi_frame.as_mut().unwrap().user_image_paging =
+            SlotRegion::new(slot_pos_before, slot_pos_after);
 
         root_pt_cap
     }
@@ -418,17 +669,116 @@ impl RootServer {
         bi.num_io_pt_levels = 0;
         bi.ipc_buffer = ipcbuf_vptr.0;
         bi.it_cnode_size_bits = CONFIG_ROOT_CNODE_SIZE_BITS;
-        // bi->initThreadDomain = ksDomSchedule[ksDomScheduleIdx].domain;
+        /* there is no domain scheduler, the initial thread runs in domain 0 */
+        bi.init_thread_domain = 0;
         bi.extra_len = extra_bi_size;
 
         bs.slot_pos_cur = seL4_NumInitialCaps;
         bs.bi_frame = Some(bi);
     }
 
+    /// 填充extra_bootinfo：untyped溢出chunk、用户镜像chunk、度量chunk和设备树chunk，
+    /// 剩余部分用padding chunk补齐
+    ///
+    /// untyped溢出chunk放在最前面，以保证其中的描述符是对齐的
+    #[link_section = ".boot.text"]
+    pub fn populate_extra_bi(
+        &self,
+        untyped_overflow: usize,
+        extra_images: &[ImageRegion],
+        measurement: &BootMeasurement,
+        dtb_addr_p: Paddr,
+        dtb_size: usize,
+        extra_bi_size: usize,
+    ) {
+        let mut extra_bi_offset = 0;
+        if untyped_overflow != 0 {
+            let header = BootInfoHeader {
+                id: SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW,
+                len: BOOTINFO_HEADER_SIZE + untyped_overflow * size_of::<UntypedDesc>(),
+            };
+            let mut bs = BOOT_STATE.lock();
+            bs.untyped_overflow = Paddr(self.extra_bi.0 + extra_bi_offset);
+            bs.untyped_overflow_max = untyped_overflow;
+            unsafe { *bs.untyped_overflow.as_mut::<BootInfoHeader>() = header };
+            extra_bi_offset += header.len;
+        }
+        if !extra_images.is_empty() {
+            let header = BootInfoHeader {
+                id: SEL4_BOOTINFO_HEADER_USER_IMAGES,
+                len: BOOTINFO_HEADER_SIZE + extra_images.len() * size_of::<UserImageDesc>(),
+            };
+            let chunk = Paddr(self.extra_bi.0 + extra_bi_offset);
+            let descs = Paddr(chunk.0 + BOOTINFO_HEADER_SIZE);
+            unsafe { *chunk.as_mut::<BootInfoHeader>() = header };
+            for (i, image) in extra_images.iter().enumerate() {
+                /* the frame caps are filled in by create_user_image_frames */
+                let desc = UserImageDesc {
+                    name: image.name,
+                    frames: SlotRegion::new(0, 0),
+                    virt_start: image.virt_start,
+                    virt_end: image.virt_end,
+                    virt_entry: image.virt_entry,
+                };
+                unsafe {
+                    *Paddr(descs.0 + i * size_of::<UserImageDesc>()).as_mut::<UserImageDesc>() =
+                        desc
+                };
+            }
+            BOOT_STATE.lock().user_images = descs;
+            extra_bi_offset += header.len;
+        }
+        {
+            let header = BootInfoHeader {
+                id: SEL4_BOOTINFO_HEADER_MEASUREMENT,
+                len: BOOTINFO_HEADER_SIZE + size_of::<BootMeasurement>(),
+            };
+            let chunk = Paddr(self.extra_bi.0 + extra_bi_offset);
+            unsafe {
+                *chunk.as_mut::<BootInfoHeader>() = header;
+                *Paddr(chunk.0 + BOOTINFO_HEADER_SIZE).as_mut::<BootMeasurement>() = *measurement;
+            }
+            extra_bi_offset += header.len;
+        }
+        if dtb_size != 0 {
+            let header = BootInfoHeader {
+                id: SEL4_BOOTINFO_HEADER_FDT,
+                len: fdt_chunk_len(dtb_size),
+            };
+            let chunk = Paddr(self.extra_bi.0 + extra_bi_offset);
+            unsafe {
+                *chunk.as_mut::<BootInfoHeader>() = header;
+                core::ptr::copy_nonoverlapping(
+                    dtb_addr_p.as_raw_ptr::<u8>(),
+                    Paddr(chunk.0 + BOOTINFO_HEADER_SIZE).as_raw_ptr_mut::<u8>(),
+                    dtb_size,
+                );
+            }
+            extra_bi_offset += header.len;
+        }
+        if extra_bi_size > extra_bi_offset {
+            /* provide a chunk for any leftover padding in the extended boot info */
+            let header = BootInfoHeader {
+                id: SEL4_BOOTINFO_HEADER_PADDING,
+                len: extra_bi_size - extra_bi_offset,
+            };
+            unsafe {
+                *Paddr(self.extra_bi.0 + extra_bi_offset).as_mut::<BootInfoHeader>() = header
+            };
+        }
+    }
+
     #[link_section = ".boot.text"]
     fn create_bi_frame_cap(&self, root_cnode_cap: Capability, pd_cap: Capability, vptr: Vaddr) {
         /* create a cap of it and write it into the root CNode */
-        let cap = create_mapped_it_frame_cap(pd_cap, self.boot_info, vptr, IT_ASID, false);
+        let cap = create_mapped_it_frame_cap(
+            pd_cap,
+            self.boot_info,
+            vptr,
+            IT_ASID,
+            VmRights::VMReadWrite,
+            false,
+        );
         root_cnode_cap.cnode_write_slot_at(seL4_CapBootInfoFrame, cap);
     }
 
@@ -441,7 +791,14 @@ impl RootServer {
     ) -> Capability {
         clear_memory(self.ipc_buf, PAGE_SIZE);
         /* create a cap of it and write it into the root CNode */
-        let cap = create_mapped_it_frame_cap(pd_cap, self.ipc_buf, vptr, IT_ASID, false);
+        let cap = create_mapped_it_frame_cap(
+            pd_cap,
+            self.ipc_buf,
+            vptr,
+            IT_ASID,
+            VmRights::VMReadWrite,
+            false,
+        );
         root_cnode_cap.cnode_write_slot_at(seL4_CapInitThreadIPCBuffer, cap);
         cap
     }
@@ -454,16 +811,20 @@ impl RootServer {
         reg: Pregion,
         do_map: bool,
         pv_offset: usize,
-    ) {
-        // pptr_t     f;
-        // cap_t      frame_cap;
-        // seL4_SlotPos slot_pos_before;
-        // seL4_SlotPos slot_pos_after;
+    ) -> SlotRegion {
+        let slot_pos_before = BOOT_STATE.lock().slot_pos_cur;
 
         let mut pa = reg.start;
         while pa.0 < reg.end.0 {
             let frame_cap = if do_map {
-                create_mapped_it_frame_cap(pd_cap, pa, pa.to_va(pv_offset), IT_ASID, true)
+                create_mapped_it_frame_cap(
+                    pd_cap,
+                    pa,
+                    pa.to_va(pv_offset),
+                    IT_ASID,
+                    VmRights::VMReadWrite,
+                    false,
+                )
             } else {
                 create_unmapped_it_frame_cap(pa)
             };
@@ -471,15 +832,75 @@ impl RootServer {
             pa.0 += PAGE_SIZE;
         }
 
-        // slot_pos_after = ndks_boot.slot_pos_cur;
+        let slot_pos_after = BOOT_STATE.lock().slot_pos_cur;
+        SlotRegion::new(slot_pos_before, slot_pos_after)
+    }
+
+    /// 映射rootserver镜像的页面，每页的权限由覆盖它的ELF段决定：
+    /// 代码段只读可执行，数据段和bss可写不可执行
+    #[link_section = ".boot.text"]
+    fn create_it_image_frames(
+        &self,
+        root_cnode_cap: Capability,
+        pd_cap: Capability,
+        reg: Pregion,
+        pv_offset: usize,
+        segments: &[ImageSegment],
+    ) -> SlotRegion {
+        let slot_pos_before = BOOT_STATE.lock().slot_pos_cur;
+
+        let mut pa = reg.start;
+        while pa.0 < reg.end.0 {
+            let va = pa.to_va(pv_offset);
+            let flags = segments
+                .iter()
+                .filter(|seg| seg.virt_start < va.0 + PAGE_SIZE && va.0 < seg.virt_end)
+                .fold(0, |flags, seg| flags | seg.flags);
+            if flags & SEGMENT_W != 0 && flags & SEGMENT_X != 0 {
+                println!(
+                    "WARNING: rootserver page {:#x?} is both writable and executable",
+                    va
+                );
+            }
+            let vm_rights = if flags & SEGMENT_W != 0 {
+                VmRights::VMReadWrite
+            } else {
+                VmRights::VMReadOnly
+            };
+            let frame_cap = create_mapped_it_frame_cap(
+                pd_cap,
+                pa,
+                va,
+                IT_ASID,
+                vm_rights,
+                flags & SEGMENT_X != 0,
+            );
+            provide_cap(root_cnode_cap, frame_cap);
+            pa.0 += PAGE_SIZE;
+        }
+
+        let slot_pos_after = BOOT_STATE.lock().slot_pos_cur;
+        SlotRegion::new(slot_pos_before, slot_pos_after)
+    }
 
-        // return (create_frames_of_region_ret_t) {
-        //     .region = (seL4_SlotRegion) {
-        //         .start = slot_pos_before,
-        //         .end   = slot_pos_after
-        //     },
-        //     .success = true
-        // };
+    /// 为rootserver之外的每个用户镜像创建未映射的页面cap，并记录在extra bootinfo中
+    #[link_section = ".boot.text"]
+    fn create_user_image_frames(
+        &self,
+        root_cnode_cap: Capability,
+        pd_cap: Capability,
+        extra_images: &[ImageRegion],
+    ) {
+        let descs = BOOT_STATE.lock().user_images;
+        for (i, image) in extra_images.iter().enumerate() {
+            let reg = Pregion::new(Paddr(image.phys_start), Paddr(image.phys_end));
+            let frames = self.create_frames_of_region(root_cnode_cap, pd_cap, reg, false, 0);
+            let desc = unsafe {
+                Paddr(descs.0 + i * size_of::<UserImageDesc>()).as_mut::<UserImageDesc>()
+            };
+            desc.frames = frames;
+            println!("user image {} frames {:?}", image.name(), desc.frames);
+        }
     }
 
     #[link_section = ".boot.text"]
@@ -503,46 +924,53 @@ impl RootServer {
         ipcbuf_vptr: Vaddr,
         ipcbuf_cap: Capability,
     ) -> ThreadPointer {
-        let tcb_inner = unsafe { self.tcb.as_ref::<TCB>().inner_mut() };
-        *tcb_inner = TCBInner::new_empty();
+        /* the TCB block was allocated from the rootserver region above */
+        let tcb = unsafe {
+            KObj::init(
+                Paddr(self.tcb.0 + TCB_OFFSET),
+                TCB_SIZE_BITS,
+                Tcb::new_empty(),
+            )
+        };
 
-        tcb_inner.init_context();
+        tcb.init_context();
 
         // todo: derive ipc buffer cap
         let dc_cap = derive_cap(ipcbuf_cap);
 
         cte_insert(
             root_cnode_cap,
-            &root_cnode_cap.cnode_slot_at(seL4_CapInitThreadCNode),
-            CapSlot::slot_ref(self.tcb, tcbCTable),
+            root_cnode_cap.cnode_slot_at(seL4_CapInitThreadCNode),
+            tcb.tcb_cte_slot(tcbCTable),
         );
 
         cte_insert(
             it_pd_cap,
-            &root_cnode_cap.cnode_slot_at(seL4_CapInitThreadVSpace),
-            CapSlot::slot_ref(self.tcb, tcbVTable),
+            root_cnode_cap.cnode_slot_at(seL4_CapInitThreadVSpace),
+            tcb.tcb_cte_slot(tcbVTable),
         );
 
         cte_insert(
             dc_cap,
-            &root_cnode_cap.cnode_slot_at(seL4_CapInitThreadIPCBuffer),
-            CapSlot::slot_ref(self.tcb, tcbBuffer),
+            root_cnode_cap.cnode_slot_at(seL4_CapInitThreadIPCBuffer),
+            tcb.tcb_cte_slot(tcbBuffer),
         );
         // todo: cte insert ipc_buf cap
         // todo: set tcbIPCBuffer, tcbMCP, tcbDomain
-        tcb_inner.registers[Rv64Reg::a0 as usize] = bi_frame_vptr.0;
-        tcb_inner.registers[Rv64Reg::NextIP as usize] = ui_v_entry.0;
-        tcb_inner.tcb_priority = seL4_MaxPrio;
-        tcb_inner.tcb_ipc_buffer = ipcbuf_vptr;
-        tcb_inner.set_thread_state(ThreadState_Running);
+        tcb.set_reg(Rv64Reg::a0, bi_frame_vptr.0);
+        tcb.set_reg(Rv64Reg::NextIP, ui_v_entry.0);
+        tcb.set_priority(seL4_MaxPrio);
+        tcb.set_ipc_buffer(ipcbuf_vptr);
+        tcb.set_thread_state(ThreadState_Running);
         // todo: set Cur_domain
 
         /* create initial thread's TCB cap */
-        let cap = Capability::cap_thread_cap_new(tcb_inner as *mut _ as _);
+        let cap = Capability::cap_thread_cap_new(tcb.pptr().0);
         root_cnode_cap.cnode_write_slot_at(seL4_CapInitThreadTCB, cap);
 
-        tcb_inner.set_thread_name("rootserver");
-        tcb_inner.pointer()
+        tcb.set_thread_name("rootserver");
+        tcb.debug_append();
+        tcb.pointer()
     }
 
     #[link_section = ".boot.text"]
@@ -550,80 +978,42 @@ impl RootServer {
         &self,
         root_cnode_cap: Capability,
         device_memory: bool,
-        mut reg: Pregion,
+        reg: Pregion,
         first_untyped_slot: usize,
     ) -> bool {
-        while !reg.is_empty() {
-            let mut size_bits =
-                usize::BITS as usize - 1 - (reg.end.0 - reg.start.0).leading_zeros() as usize;
-            size_bits = size_bits.min(seL4_MaxUntypedBits);
-            if reg.start.0 != 0 {
-                size_bits = size_bits.min(reg.start.0.trailing_zeros() as _);
-            }
-            if size_bits >= seL4_MinUntypedBits {
-                if !provide_untyped_cap(
-                    root_cnode_cap,
-                    device_memory,
-                    reg.start,
-                    size_bits,
-                    first_untyped_slot,
-                ) {
-                    return false;
-                }
+        for (start, size_bits) in (UntypedSplit { reg }) {
+            if !provide_untyped_cap(
+                root_cnode_cap,
+                device_memory,
+                start,
+                size_bits,
+                first_untyped_slot,
+            ) {
+                return false;
             }
-            reg.start.0 += bit!(size_bits);
         }
         true
     }
 
     #[link_section = ".boot.text"]
     pub fn create_untypeds(&self, root_cnode_cap: Capability, boot_mem_reuse_reg: Pregion) -> bool {
-        let bs = BOOT_STATE.lock();
-        let first_untyped_slot = bs.slot_pos_cur;
-        let mut start = 0;
-        let reserved = bs.reserved.clone();
-        let freemem = bs.freemem.clone();
-        drop(bs);
-        for res in reserved.iter() {
-            if start < res.start.0 {
-                let reg = Pregion::new(Paddr(start), res.start);
-                if !self.create_untypeds_for_region(root_cnode_cap, true, reg, first_untyped_slot) {
-                    println!(
-                        "ERROR: creation of untypeds for device region {:#x?} failed\n",
-                        reg
-                    );
-                    return false;
-                }
-            }
-            start = res.end.0;
-        }
-        if start < CONFIG_PADDR_USER_DEVICE_TOP {
-            let reg = Pregion::new(Paddr(start), Paddr(CONFIG_PADDR_USER_DEVICE_TOP));
-            if !self.create_untypeds_for_region(root_cnode_cap, true, reg, first_untyped_slot) {
+        let first_untyped_slot = BOOT_STATE.lock().slot_pos_cur;
+        /* device regions come first, then recycled boot memory, then the
+         * remaining freemem */
+        for &(device_memory, reg) in untyped_regions(boot_mem_reuse_reg).iter() {
+            if !self.create_untypeds_for_region(
+                root_cnode_cap,
+                device_memory,
+                reg,
+                first_untyped_slot,
+            ) {
                 println!(
-                    "ERROR: creation of untypeds for top device region {:#x?} failed\n",
-                    reg
-                );
-                return false;
-            }
-        }
-        if !self.create_untypeds_for_region(
-            root_cnode_cap,
-            false,
-            boot_mem_reuse_reg,
-            first_untyped_slot,
-        ) {
-            println!(
-                "ERROR: creation of untypeds for recycled boot memory {:#x?} failed\n",
-                boot_mem_reuse_reg
-            );
-            return false;
-        }
-        /* convert remaining freemem into UT objects and provide the caps */
-        for &reg in freemem.iter() {
-            if !self.create_untypeds_for_region(root_cnode_cap, false, reg, first_untyped_slot) {
-                println!(
-                    "ERROR: creation of untypeds for free memory region {:#x?} failed\n",
+                    "ERROR: creation of untypeds for {} region {:#x?} failed\n",
+                    if device_memory {
+                        "device"
+                    } else {
+                        "free memory"
+                    },
                     reg
                 );
                 return false;
@@ -634,10 +1024,38 @@ impl RootServer {
             start: first_untyped_slot,
             end: bs.slot_pos_cur,
         };
+        let num_untypeds = bs.slot_pos_cur - first_untyped_slot;
+        drop(bs);
+        finalise_untyped_overflow(num_untypeds);
         true
     }
 }
 
+/// 将untyped溢出chunk缩小到实际使用的大小，剩余部分改为padding chunk
+#[link_section = ".boot.text"]
+fn finalise_untyped_overflow(num_untypeds: usize) {
+    let bs = BOOT_STATE.lock();
+    if bs.untyped_overflow.0 == 0 {
+        return;
+    }
+    let used = num_untypeds.saturating_sub(CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS);
+    let header = unsafe { bs.untyped_overflow.as_mut::<BootInfoHeader>() };
+    let unused = header.len - BOOTINFO_HEADER_SIZE - used * size_of::<UntypedDesc>();
+    if used == 0 {
+        /* nothing spilled, the whole chunk becomes padding */
+        header.id = SEL4_BOOTINFO_HEADER_PADDING;
+    } else if unused != 0 {
+        header.len -= unused;
+        let padding = Paddr(bs.untyped_overflow.0 + header.len);
+        unsafe {
+            *padding.as_mut::<BootInfoHeader>() = BootInfoHeader {
+                id: SEL4_BOOTINFO_HEADER_PADDING,
+                len: unused,
+            }
+        };
+    }
+}
+
 #[link_section = ".boot.text"]
 /// 向root_cnode_cap指向的cnode的空闲cap区域中填写一个cap
 fn provide_cap(root_cnode_cap: Capability, cap: Capability) -> bool {
@@ -665,25 +1083,31 @@ fn provide_untyped_cap(
 ) -> bool {
     let mut bs = BOOT_STATE.lock();
     let i = bs.slot_pos_cur - first_untyped_slot;
+    let desc = UntypedDesc {
+        paddr: pptr.0,
+        _padding: [0; 6],
+        size_bits: size_bits as _,
+        is_device: device_memory as _,
+    };
     if i < CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS {
-        let bi_frame = bs.bi_frame.as_mut().unwrap();
-        bi_frame.untyped_list[i] = UntypedDesc {
-            paddr: pptr.0,
-            _padding: [0; 6],
-            size_bits: size_bits as _,
-            is_device: device_memory as _,
-        };
-        let ut_cap = Capability::cap_untyped_cap_new(
-            max_free_index!(size_bits),
-            device_memory,
-            size_bits,
-            pptr.0,
+        bs.bi_frame.as_mut().unwrap().untyped_list[i] = desc;
+    } else if i - CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS < bs.untyped_overflow_max {
+        /* spill into the extra bootinfo, the descriptors directly follow the chunk header */
+        let offset = BOOTINFO_HEADER_SIZE
+            + (i - CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS) * size_of::<UntypedDesc>();
+        unsafe { *Paddr(bs.untyped_overflow.0 + offset).as_mut::<UntypedDesc>() = desc };
+    } else {
+        println!(
+            "Kernel init: Too many untyped regions for boot info, dropping {:#x?}\n",
+            Pregion::new(pptr, Paddr(pptr.0 + bit!(size_bits)))
         );
-        drop(bs);
-        return provide_cap(root_cnode_cap, ut_cap);
+        return true;
     }
-    println!("Kernel init: Too many untyped regions for boot info\n");
-    true
+    /* there is no MDB to reset the untyped on its first retype yet,
+     * so hand it out with all of its memory free */
+    let ut_cap = Capability::cap_untyped_cap_new(0, device_memory, size_bits, pptr.0);
+    drop(bs);
+    provide_cap(root_cnode_cap, ut_cap)
 }
 
 #[link_section = ".boot.text"]
@@ -701,12 +1125,12 @@ fn init_irqs(root_cnode_cap: Capability) {
 #[link_section = ".boot.text"]
 pub fn create_idle_thread() -> bool {
     let idle = IDLE_THREAD_TCB.lock();
-    let tcb = ThreadPointer(idle.inner_pptr());
-    *ksIdleThread.lock() = tcb;
-    let t = tcb.get().unwrap();
+    let t = unsafe { KObj::init(idle.inner_pptr(), TCB_SIZE_BITS, Tcb::new_empty()) };
+    *ksIdleThread.lock() = t.pointer();
     t.set_thread_name("idle_thread");
     t.set_thread_state(ThreadState_IdleThreadState);
-    //         configureIdleThread(NODE_STATE_ON_CORE(ksIdleThread, i));
+    t.debug_append();
+    configure_idle_thread(&t);
     true
 }
 
@@ -714,9 +1138,6 @@ pub fn create_idle_thread() -> bool {
 pub fn init_core_state(scheduler_action: ThreadPointer) {
     *ksSchedulerAction.lock() = SchedulerAction::SwitchToThread(scheduler_action);
     *ksCurThread.lock() = *ksIdleThread.lock();
-    let mut list = THREAD_LIST.lock();
-    list.push(scheduler_action);
-    list.push(*ksCurThread.lock());
 }
 
 #[link_section = ".boot.text"]
@@ -732,13 +1153,20 @@ fn try_init_kernel(
     ui_p_reg_end: Paddr,
     pv_offset: usize,
     v_entry: Vaddr,
+    ui_segments: &[ImageSegment],
+    extra_images: &[ImageRegion],
+    measurement: &BootMeasurement,
     dtb_addr_p: Paddr,
     dtb_size: usize,
+    park_reg: Pregion,
 ) {
     extern "C" {
         fn ki_boot_end();
     }
-    let boot_mem_reuse_reg = Pregion::new(Paddr(KERNEL_ELF_BASE), Paddr(ki_boot_end as _));
+    let boot_mem_reuse_reg = Pregion::new(
+        Paddr(kpptr_to_paddr(KERNEL_ELF_BASE)),
+        Paddr(kpptr_to_paddr(ki_boot_end as _)),
+    );
     let ui_reg = Pregion::new(ui_p_reg_start, ui_p_reg_end);
     let ui_v_reg = Vregion::new(
         ui_p_reg_start.to_va(pv_offset),
@@ -751,11 +1179,35 @@ fn try_init_kernel(
 
     map_kernel_window();
     init_cpu();
+    let dt = init_plat(dtb_addr_p, dtb_size);
+    /* the PLIC is only known after the device tree is parsed */
+    init_local_irq_controller();
     println!("Bootstrapping kernel");
-    // let extra_bi_size_bits = calculate_extra_bi_size_bits();
+    let dtb_reg = Pregion::new(dtb_addr_p, Paddr(dtb_addr_p.0 + dtb_size));
+    riscv_init_freemem(ui_reg, extra_images, dtb_reg, park_reg, &dt);
+
+    /* untyped descriptors that do not fit into the bootinfo frame, the other
+     * user images, the boot measurement and the dtb are passed to the root
+     * server in the extra bootinfo */
+    let mut extra_bi_size = 0;
+    let untyped_overflow = calculate_untyped_overflow(boot_mem_reuse_reg);
+    if untyped_overflow != 0 {
+        extra_bi_size += BOOTINFO_HEADER_SIZE + untyped_overflow * size_of::<UntypedDesc>();
+    }
+    if !extra_images.is_empty() {
+        extra_bi_size += BOOTINFO_HEADER_SIZE + extra_images.len() * size_of::<UserImageDesc>();
+    }
+    extra_bi_size += BOOTINFO_HEADER_SIZE + size_of::<BootMeasurement>();
+    if dtb_size != 0 {
+        extra_bi_size += fdt_chunk_len(dtb_size);
+    }
+    let extra_bi_size_bits = calculate_extra_bi_size_bits(extra_bi_size);
 
     /* init thread virt region */
-    let it_v_reg = Vregion::new(ui_v_reg.start, extra_bi_frame_vptr);
+    let mut it_v_reg = Vregion::new(ui_v_reg.start, extra_bi_frame_vptr);
+    if extra_bi_size_bits != 0 {
+        it_v_reg.end.0 += bit!(extra_bi_size_bits);
+    }
     if it_v_reg.end.0 >= USER_TOP {
         panic!(
             "ERROR: userland image virt region exceeds USER_TOP({:#x?})",
@@ -763,7 +1215,7 @@ fn try_init_kernel(
         );
     }
 
-    let mut rootserver = riscv_init_freemem(ui_reg, it_v_reg);
+    let mut rootserver = create_rootserver(it_v_reg, extra_bi_size_bits);
     println!("rootserver = {:#x?}", rootserver);
 
     /* create the root cnode */
@@ -773,16 +1225,53 @@ fn try_init_kernel(
     init_irqs(root_cnode_cap);
 
     /* create the bootinfo frame */
-    rootserver.populate_bi_frame(0, 1, ipcbuf_vptr, 0);
+    /* the secondary cores only idle until there is a multicore scheduler, so
+     * the root server sees a single node */
+    rootserver.populate_bi_frame(0, 1, ipcbuf_vptr, extra_bi_size);
+    rootserver.populate_extra_bi(
+        untyped_overflow,
+        extra_images,
+        measurement,
+        dtb_addr_p,
+        dtb_size,
+        extra_bi_size,
+    );
 
     let root_pt_cap = rootserver.create_it_address_space(root_cnode_cap, it_v_reg);
 
     /* Create and map bootinfo frame cap */
     rootserver.create_bi_frame_cap(root_cnode_cap, root_pt_cap, bi_frame_vptr);
     let ipcbuf_cap = rootserver.create_ipcbuf_frame_cap(root_cnode_cap, root_pt_cap, ipcbuf_vptr);
-    rootserver.create_frames_of_region(root_cnode_cap, root_pt_cap, ui_reg, true, pv_offset);
+    if extra_bi_size > 0 {
+        let extra_bi_reg = Pregion::new(
+            rootserver.extra_bi,
+            Paddr(rootserver.extra_bi.0 + extra_bi_size),
+        );
+        let extra_bi_pages = rootserver.create_frames_of_region(
+            root_cnode_cap,
+            root_pt_cap,
+            extra_bi_reg,
+            true,
+            rootserver.extra_bi.0.wrapping_sub(extra_bi_frame_vptr.0),
+        );
+        BOOT_STATE.lock().bi_frame.as_mut().unwrap().extra_bi_pages = extra_bi_pages;
+    }
+    let user_image_frames = rootserver.create_it_image_frames(
+        root_cnode_cap,
+        root_pt_cap,
+        ui_reg,
+        pv_offset,
+        ui_segments,
+    );
+    BOOT_STATE
+        .lock()
+        .bi_frame
+        .as_mut()
+        .unwrap()
+        .user_image_frames = user_image_frames;
+    rootserver.create_user_image_frames(root_cnode_cap, root_pt_cap, extra_images);
     let it_ap_cap = rootserver.create_it_asid_pool(root_cnode_cap);
-    // write_it_asid_pool(it_ap_cap, root_pt_cap);
+    write_it_asid_pool(it_ap_cap, root_pt_cap);
     create_idle_thread();
     let initial = rootserver.create_initial_thread(
         root_cnode_cap,
@@ -805,26 +1294,87 @@ fn try_init_kernel(
     println!("Booting all finished, dropped to user space");
 }
 
+/* set by the boot core once the kernel page table and the kernel state are ready */
+static NODE_BOOT_DONE: AtomicBool = AtomicBool::new(false);
+
+/// 从核等待主核完成初始化后切换到内核页表，目前只在这里空转
+///
+/// 不能放在.boot.text中，启动结束后那段内存会作为untyped交给rootserver
+fn init_secondary_core(hart_id: usize, core_id: usize) -> ! {
+    while !NODE_BOOT_DONE.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    activate_kernel_vspace();
+    extern "C" {
+        fn trap_entry();
+    }
+    unsafe { stvec::write(trap_entry as _, stvec::TrapMode::Direct) };
+    println!("Core {} (hart {}) up", core_id, hart_id);
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
+
 #[link_section = ".boot.text"]
 #[no_mangle]
-pub fn init_kernel(
-    ui_p_reg_start: Paddr,
-    ui_p_reg_end: Paddr,
-    pv_offset: usize,
-    v_entry: Vaddr,
-    dtb_addr_p: Paddr,
-    dtb_size: usize,
-) {
+pub fn init_kernel(handoff: *const BootHandoff, hart_id: usize, core_id: usize) {
+    if core_id != 0 {
+        /* the boot core may be clearing the bss or reading the handoff */
+        init_secondary_core(hart_id, core_id);
+    }
+    /* the handoff lives in the elfloader, which is only identity mapped until
+     * the kernel switches to its own page table, so take a copy first */
+    let handoff = unsafe { handoff.read() };
     clear_bss();
-    init_heap();
+    if let Err(err) = handoff.validate() {
+        panic!(
+            "boot handoff from the elfloader rejected: {:x?}, kernel expects version {}",
+            err, BOOT_HANDOFF_VERSION
+        );
+    }
+    if handoff.kernel.virt_start != KERNEL_ELF_BASE {
+        panic!(
+            "kernel image at {:#x}, but linked at {:#x}",
+            handoff.kernel.virt_start, KERNEL_ELF_BASE
+        );
+    }
+    /* the elfloader places the kernel wherever there is free memory */
+    set_kernel_elf_paddr_base(Paddr(handoff.kernel.phys_start));
+    println!(
+        "Boot handoff version {} on hart {}, {} node(s), harts {:?}",
+        handoff.version,
+        hart_id,
+        handoff.num_nodes,
+        handoff.hart_ids()
+    );
+    print!("Boot measurement ");
+    for b in handoff.measurement.digest {
+        print!("{:02x}", b);
+    }
+    println!(
+        " (verified: {}, signed: {})",
+        handoff.measurement.flags & MEASUREMENT_VERIFIED != 0,
+        handoff.measurement.flags & MEASUREMENT_SIGNED != 0
+    );
+    /* held until init_kernel returns to head.S, which drops to user space */
+    let _guard = kernel_lock();
+    let rootserver = &handoff.user_images()[0];
     let result = try_init_kernel(
-        ui_p_reg_start,
-        ui_p_reg_end,
-        pv_offset,
-        v_entry,
-        dtb_addr_p,
-        dtb_size,
+        Paddr(rootserver.phys_start),
+        Paddr(rootserver.phys_end),
+        rootserver.phys_virt_offset(),
+        Vaddr(rootserver.virt_entry),
+        rootserver.segments(),
+        &handoff.user_images()[1..],
+        &handoff.measurement,
+        Paddr(handoff.dtb_paddr),
+        handoff.dtb_size,
+        Pregion::new(
+            Paddr(handoff.park_paddr),
+            Paddr(handoff.park_paddr + handoff.park_size),
+        ),
     );
+    NODE_BOOT_DONE.store(true, Ordering::Release);
 
     schedule();
     activate_thread();
@@ -9,6 +9,13 @@ pub fn debug_print_bi_info(bootinfo: &BootInfo) {
     println!("num_io_pt_levels = {}", bootinfo.num_io_pt_levels);
     println!("ipc_buffer = {:#x?}", bootinfo.ipc_buffer);
     println!("empty = {:?}", bootinfo.empty);
+    println!("shared_frames = {:?}", bootinfo.shared_frames);
+    println!("user_image_frames = {:?}", bootinfo.user_image_frames);
+    println!("user_image_paging = {:?}", bootinfo.user_image_paging);
+    println!("io_space_caps = {:?}", bootinfo.io_space_caps);
+    println!("extra_bi_pages = {:?}", bootinfo.extra_bi_pages);
+    println!("it_cnode_size_bits = {}", bootinfo.it_cnode_size_bits);
+    println!("init_thread_domain = {}", bootinfo.init_thread_domain);
     println!("untyped = {:?}", bootinfo.untyped);
     let mut i = 0;
     for desc in bootinfo.untyped_list.iter() {
@@ -1,12 +1,61 @@
 use sel4_common::structures_common::tcbCTable;
 
-use super::{structures::CapSlot, thread::TCBInner};
+use crate::{
+    common::WORD_BITS,
+    println,
+    traps::syscalls::{seL4_FailedLookup, SyscallError},
+};
 
-pub fn lookup_slot(thread: &TCBInner, cptr: usize) -> &'static mut CapSlot {
+use super::{
+    kobj::{CNode, KObj, SlotRef},
+    structures::Capability,
+    thread::Tcb,
+};
+
+pub fn lookup_slot(thread: &KObj<Tcb>, cptr: usize) -> SlotRef {
     // cap_t threadRoot;
     // resolveAddressBits_ret_t res_ret;
     // lookupSlot_raw_ret_t ret;
 
-    let thread_root = thread.tcb_cte_slot(tcbCTable).cap;
+    let thread_root = thread.tcb_cte_slot(tcbCTable).cap();
     thread_root.cnode_slot_at(cptr)
 }
+
+/// 查找cptr处的cap，cptr超出cnode时返回null cap而不是panic，见seL4的lookup_fp
+pub fn lookup_cap(thread: &KObj<Tcb>, cptr: usize) -> Capability {
+    let slot = KObj::<CNode>::from_cap(&thread.tcb_cte_slot(tcbCTable).cap())
+        .and_then(|cnode| cnode.slot(cptr));
+    match slot {
+        Some(slot) => slot.cap(),
+        None => Capability::new_empty(),
+    }
+}
+
+/// 在调用者给出的cnode中查找目标slot，见seL4的lookupTargetSlot
+///
+/// cap_number是root在extra caps中的编号，查找失败时记录在错误中
+pub fn lookup_target_slot(
+    root: Capability,
+    index: usize,
+    depth: usize,
+    cap_number: usize,
+) -> Result<SlotRef, SyscallError> {
+    if depth < 1 || depth > WORD_BITS {
+        println!("Lookup target slot: Invalid depth {}.", depth);
+        return Err(SyscallError::range_error(1, WORD_BITS));
+    }
+    let mut error = SyscallError::with_type(seL4_FailedLookup);
+    error.invalid_cap_number = cap_number;
+    let cnode = match KObj::<CNode>::from_cap(&root) {
+        Some(cnode) => cnode,
+        None => {
+            println!("Lookup target slot: Root is not a CNode.");
+            return Err(error);
+        }
+    };
+    /* cnodes are single level, so the index selects the slot directly */
+    cnode.slot(index).ok_or_else(|| {
+        println!("Lookup target slot: Index {:#x} out of range.", index);
+        error
+    })
+}
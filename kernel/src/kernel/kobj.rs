@@ -0,0 +1,268 @@
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::Deref,
+    ptr::{addr_of, addr_of_mut},
+};
+
+use sel4_common::{
+    bit,
+    constants::{
+        seL4_ASIDPoolBits, seL4_EndpointBits, seL4_MsgMaxExtraCaps, seL4_MsgMaxLength,
+        seL4_PageTableBits, seL4_SlotBits, seL4_TCBBits,
+    },
+    round_down,
+    shared_types::IPCBuffer,
+};
+use spin::{Mutex, MutexGuard};
+
+use crate::{
+    common::{TCB_OFFSET, TCB_SIZE_BITS},
+    machine::Paddr,
+    object::endpoint::Endpoint,
+};
+
+use super::{
+    structures::{CapInfo, CapSlot, Capability},
+    thread::Tcb,
+    vspace::{AsidPool, PageTable},
+};
+
+/// 大内核锁：进入内核（启动、系统调用）时获取，返回用户态前释放
+///
+/// 内核对象中的可变字段都是KCell，只有持有这把锁时才能访问
+pub static KERNEL_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn kernel_lock() -> MutexGuard<'static, ()> {
+    KERNEL_LOCK.lock()
+}
+
+/// 访问内核对象前检查是否持有KERNEL_LOCK，release构建中同样检查
+pub fn check_kernel_locked() {
+    assert!(
+        KERNEL_LOCK.is_locked(),
+        "kernel object accessed without the kernel lock"
+    );
+}
+
+/// 内核对象中的可变字段
+///
+/// 对象可以通过多个KObj同时访问，所以只提供共享引用，修改通过KCell完成。
+/// update的闭包中不能再访问同一个KCell
+#[repr(transparent)]
+pub struct KCell<T>(UnsafeCell<T>);
+
+/* every access happens with KERNEL_LOCK held, which serialises all cores */
+unsafe impl<T: Send> Sync for KCell<T> {}
+
+impl<T> KCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        check_kernel_locked();
+        f(unsafe { &mut *self.0.get() })
+    }
+
+    pub fn set(&self, value: T) {
+        self.update(|v| *v = value);
+    }
+
+    /// 供汇编直接读写，例如trap.S保存和恢复的寄存器
+    pub fn as_ptr(&self) -> *mut T {
+        self.0.get()
+    }
+}
+
+impl<T: Copy> KCell<T> {
+    pub fn get(&self) -> T {
+        check_kernel_locked();
+        unsafe { *self.0.get() }
+    }
+}
+
+/// CNode对象，只能通过KObj<CNode>::slot访问其中的slot
+pub enum CNode {}
+
+/// 指向类型为T的内核对象
+///
+/// 只能由指向该类型对象的cap得到（from_cap），内核自己创建的对象除外（from_paddr）
+pub struct KObj<T> {
+    pptr: Paddr,
+    size_bits: usize,
+    _type: PhantomData<&'static T>,
+}
+
+/// 指向cnode或TCB中的一个slot
+pub type SlotRef = KObj<CapSlot>;
+
+impl<T> Clone for KObj<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for KObj<T> {}
+
+impl<T> KObj<T> {
+    /// pptr处必须是一个已经初始化的T，size_bits为对象的大小
+    pub unsafe fn from_paddr(pptr: Paddr, size_bits: usize) -> Self {
+        Self {
+            pptr,
+            size_bits,
+            _type: PhantomData,
+        }
+    }
+
+    /// 在pptr处创建一个新对象，覆盖原来的内容
+    pub unsafe fn init(pptr: Paddr, size_bits: usize, value: T) -> Self {
+        pptr.as_raw_ptr_mut::<T>().write(value);
+        Self::from_paddr(pptr, size_bits)
+    }
+
+    pub fn pptr(&self) -> Paddr {
+        self.pptr
+    }
+
+    pub fn ptr_eq(&self, another: &Self) -> bool {
+        self.pptr.0 == another.pptr.0
+    }
+}
+
+impl KObj<Tcb> {
+    pub fn from_cap(cap: &Capability) -> Option<Self> {
+        match cap.get_info() {
+            CapInfo::ThreadCap { ptr } => Some(unsafe { Self::from_paddr(ptr, TCB_SIZE_BITS) }),
+            _ => None,
+        }
+    }
+
+    /// reply cap指向的线程，master reply cap不指向可以回复的线程，返回None
+    pub fn from_reply_cap(cap: &Capability) -> Option<Self> {
+        match cap.get_info() {
+            CapInfo::ReplyCap {
+                tcb, master: false, ..
+            } => Some(unsafe { Self::from_paddr(tcb, TCB_SIZE_BITS) }),
+            _ => None,
+        }
+    }
+
+    /// TCB自带的cnode中的第index个slot，见tcbCTable等
+    pub fn tcb_cte_slot(&self, index: usize) -> SlotRef {
+        /* the TCB block starts with its cnode, followed by the Tcb itself */
+        let ctable = round_down!(self.pptr.0, seL4_TCBBits);
+        assert!(index < TCB_OFFSET >> seL4_SlotBits);
+        unsafe { SlotRef::from_paddr(Paddr(ctable + (index << seL4_SlotBits)), seL4_SlotBits) }
+    }
+}
+
+impl KObj<CNode> {
+    pub fn from_cap(cap: &Capability) -> Option<Self> {
+        match cap.get_info() {
+            CapInfo::CnodeCap { ptr, radix } => Some(unsafe { Self::from_paddr(ptr, radix) }),
+            _ => None,
+        }
+    }
+
+    pub fn radix(&self) -> usize {
+        self.size_bits
+    }
+
+    /// 第index个slot，超出cnode大小时返回None
+    pub fn slot(&self, index: usize) -> Option<SlotRef> {
+        if index >= bit!(self.size_bits) {
+            return None;
+        }
+        let slot = self.pptr.0 + (index << seL4_SlotBits);
+        Some(unsafe { SlotRef::from_paddr(Paddr(slot), seL4_SlotBits) })
+    }
+}
+
+impl KObj<Endpoint> {
+    pub fn from_cap(cap: &Capability) -> Option<Self> {
+        match cap.get_info() {
+            CapInfo::EndpointCap { ptr, .. } => {
+                Some(unsafe { Self::from_paddr(ptr, seL4_EndpointBits) })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl KObj<PageTable> {
+    pub fn from_cap(cap: &Capability) -> Option<Self> {
+        match cap.get_info() {
+            CapInfo::PageTableCap { pptr, .. } => {
+                Some(unsafe { Self::from_paddr(pptr, seL4_PageTableBits) })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl KObj<AsidPool> {
+    pub fn from_cap(cap: &Capability) -> Option<Self> {
+        match cap.get_info() {
+            CapInfo::AsidPoolCap { pptr, .. } => {
+                Some(unsafe { Self::from_paddr(pptr, seL4_ASIDPoolBits) })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 线程的IPC buffer，只能由vspace::lookup_ipc_buffer检查buffer cap后得到
+///
+/// buffer位于用户内存中，用户可以随时修改，所以只按字读写，不提供引用
+impl KObj<IPCBuffer> {
+    pub fn msg(&self, i: usize) -> usize {
+        assert!(i < seL4_MsgMaxLength);
+        let buffer = self.pptr.as_raw_ptr::<IPCBuffer>();
+        unsafe { addr_of!((*buffer).msg[i]).read_volatile() }
+    }
+
+    pub fn set_msg(&self, i: usize, value: usize) {
+        assert!(i < seL4_MsgMaxLength);
+        let buffer = self.pptr.as_raw_ptr_mut::<IPCBuffer>();
+        unsafe { addr_of_mut!((*buffer).msg[i]).write_volatile(value) }
+    }
+
+    pub fn cap_or_badge(&self, i: usize) -> usize {
+        assert!(i < seL4_MsgMaxExtraCaps);
+        let buffer = self.pptr.as_raw_ptr::<IPCBuffer>();
+        unsafe { addr_of!((*buffer).caps_or_badges[i]).read_volatile() }
+    }
+}
+
+impl Deref for KObj<Tcb> {
+    type Target = Tcb;
+
+    fn deref(&self) -> &Tcb {
+        unsafe { self.pptr.as_ref() }
+    }
+}
+
+impl Deref for KObj<CapSlot> {
+    type Target = CapSlot;
+
+    fn deref(&self) -> &CapSlot {
+        unsafe { self.pptr.as_ref() }
+    }
+}
+
+impl Deref for KObj<Endpoint> {
+    type Target = Endpoint;
+
+    fn deref(&self) -> &Endpoint {
+        unsafe { self.pptr.as_ref() }
+    }
+}
+
+impl Deref for KObj<AsidPool> {
+    type Target = AsidPool;
+
+    fn deref(&self) -> &AsidPool {
+        unsafe { self.pptr.as_ref() }
+    }
+}
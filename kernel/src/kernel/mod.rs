@@ -1,8 +1,11 @@
+pub mod array_vec;
 mod boot;
 mod bootinfo;
 pub mod cspace;
-mod heap;
+pub mod kobj;
 pub mod statedata;
 pub mod structures;
 pub mod thread;
+#[cfg(feature = "trace")]
+pub mod trace;
 pub mod vspace;
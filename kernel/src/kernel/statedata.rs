@@ -1,8 +1,8 @@
 use spin::Mutex;
 
-use crate::machine::Paddr;
+use crate::common::{CONFIG_NUM_PRIORITIES, L2_BITMAP_SIZE};
 
-use super::thread::ThreadPointer;
+use super::thread::{TcbQueue, ThreadPointer};
 
 #[derive(Clone, Copy, Debug)]
 pub enum SchedulerAction {
@@ -13,5 +13,13 @@ pub enum SchedulerAction {
 
 pub static ksSchedulerAction: Mutex<SchedulerAction> =
     Mutex::new(SchedulerAction::ResumeCurrentThread);
-pub static ksCurThread: Mutex<ThreadPointer> = Mutex::new(ThreadPointer(Paddr(0)));
-pub static ksIdleThread: Mutex<ThreadPointer> = Mutex::new(ThreadPointer(Paddr(0)));
+pub static ksCurThread: Mutex<ThreadPointer> = Mutex::new(ThreadPointer::null());
+pub static ksIdleThread: Mutex<ThreadPointer> = Mutex::new(ThreadPointer::null());
+/* every thread, linked through the TCBs, for the debug syscalls */
+pub static ksDebugTCBs: Mutex<ThreadPointer> = Mutex::new(ThreadPointer::null());
+/* thread whose floating-point state is in the registers of this core */
+pub static ksActiveFPUState: Mutex<ThreadPointer> = Mutex::new(ThreadPointer::null());
+pub static ksReadyQueues: Mutex<[TcbQueue; CONFIG_NUM_PRIORITIES]> =
+    Mutex::new([TcbQueue::empty(); CONFIG_NUM_PRIORITIES]);
+/* bit i of word j is set when the queue of priority j * WORD_BITS + i is not empty */
+pub static ksReadyQueuesBitmap: Mutex<[usize; L2_BITMAP_SIZE]> = Mutex::new([0; L2_BITMAP_SIZE]);
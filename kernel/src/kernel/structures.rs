@@ -1,15 +1,19 @@
 use riscv::addr::BitField;
+use sel4_common::bit;
+use sel4_common::constants::seL4_PageBits;
 use sel4_common::structures_common::{
-    CAP_ASID_CONTROL_CAP, CAP_ASID_POOL_CAP, CAP_CNODE_CAP, CAP_DOMAIN_CAP, CAP_FRAME_CAP,
-    CAP_IRQ_CONTROL_CAP, CAP_NULL_CAP, CAP_PAGE_TABLE_CAP, CAP_THREAD_CAP, CAP_UNTYPED_CAP,
+    CAP_ASID_CONTROL_CAP, CAP_ASID_POOL_CAP, CAP_CNODE_CAP, CAP_DOMAIN_CAP, CAP_ENDPOINT_CAP,
+    CAP_FRAME_CAP, CAP_IRQ_CONTROL_CAP, CAP_NULL_CAP, CAP_PAGE_TABLE_CAP, CAP_REPLY_CAP,
+    CAP_THREAD_CAP, CAP_UNTYPED_CAP,
 };
 
 use crate::{
-    common::CONFIG_ROOT_CNODE_SIZE_BITS,
     machine::{Paddr, Vaddr},
-    println,
+    mask, println,
 };
 
+use super::kobj::{CNode, KCell, KObj, SlotRef};
+
 #[derive(Debug)]
 pub enum CapInfo {
     NullCap,
@@ -17,24 +21,47 @@ pub enum CapInfo {
         vptr: Vaddr,
         pptr: Paddr,
         is_device: bool,
+        size: usize,
+        vm_rights: usize,
+        asid: usize,
     },
     UntypedCap {
         pptr: Paddr,
         is_device: bool,
         size_bits: usize,
+        free_index: usize,
     },
     CnodeCap {
         ptr: Paddr,
+        radix: usize,
     },
     ThreadCap {
         ptr: Paddr,
     },
+    EndpointCap {
+        ptr: Paddr,
+        badge: usize,
+        can_send: bool,
+        can_receive: bool,
+        can_grant: bool,
+        can_grant_reply: bool,
+    },
+    /* a caller cap in the tcbCaller slot of the receiver, see setup_caller_cap */
+    ReplyCap {
+        tcb: Paddr,
+        master: bool,
+        can_grant: bool,
+    },
     AsidControlCap,
-    AsidPoolCap,
+    AsidPoolCap {
+        asid_base: usize,
+        pptr: Paddr,
+    },
     PageTableCap {
         vptr: Vaddr,
         pptr: Paddr,
         asid: usize,
+        is_mapped: bool,
     },
     IrqControlCap,
     DomainCap,
@@ -60,29 +87,51 @@ impl Capability {
         match self.get_type_raw() {
             CAP_NULL_CAP => CapInfo::NullCap,
             CAP_FRAME_CAP => CapInfo::FrameCap {
-                vptr: Vaddr(self.words[0].get_bits(0..39)),
+                vptr: Vaddr(self.words[0].get_bits(0..44) << seL4_PageBits),
                 pptr: Paddr(self.words[1].get_bits(9..48)),
                 is_device: self.words[0].get_bit(54),
+                size: self.words[0].get_bits(57..59),
+                vm_rights: self.words[0].get_bits(55..57),
+                asid: self.words[1].get_bits(48..64),
             },
             CAP_UNTYPED_CAP => CapInfo::UntypedCap {
                 pptr: Paddr(self.words[0].get_bits(0..39)),
                 is_device: self.words[1].get_bit(6) as _,
                 size_bits: self.words[1].get_bits(0..6),
+                free_index: self.words[1].get_bits(25..64),
             },
             CAP_CNODE_CAP => CapInfo::CnodeCap {
                 ptr: Paddr(self.words[0].get_bits(0..35) << 1),
+                radix: self.words[0].get_bits(47..53),
             },
             CAP_ASID_CONTROL_CAP => CapInfo::AsidControlCap,
             CAP_THREAD_CAP => CapInfo::ThreadCap {
                 ptr: Paddr(self.words[0].get_bits(0..39)),
             },
-            CAP_ASID_POOL_CAP => CapInfo::AsidPoolCap,
+            CAP_ENDPOINT_CAP => CapInfo::EndpointCap {
+                ptr: Paddr(self.words[0].get_bits(0..39)),
+                badge: self.words[1],
+                can_send: self.words[0].get_bit(55),
+                can_receive: self.words[0].get_bit(56),
+                can_grant: self.words[0].get_bit(57),
+                can_grant_reply: self.words[0].get_bit(58),
+            },
+            CAP_REPLY_CAP => CapInfo::ReplyCap {
+                tcb: Paddr(self.words[1]),
+                master: self.words[0].get_bit(0),
+                can_grant: self.words[0].get_bit(1),
+            },
+            CAP_ASID_POOL_CAP => CapInfo::AsidPoolCap {
+                asid_base: self.words[0].get_bits(43..59),
+                pptr: Paddr(self.words[0].get_bits(0..37) << 2),
+            },
             CAP_IRQ_CONTROL_CAP => CapInfo::IrqControlCap,
             CAP_DOMAIN_CAP => CapInfo::DomainCap,
             CAP_PAGE_TABLE_CAP => CapInfo::PageTableCap {
-                vptr: Vaddr(self.words[0].get_bits(0..39)),
+                vptr: Vaddr(self.words[0].get_bits(0..44) << seL4_PageBits),
                 pptr: Paddr(self.words[1].get_bits(9..48)),
                 asid: self.words[1].get_bits(48..64),
+                is_mapped: self.words[0].get_bit(44),
             },
             _ => unimplemented!("unknown capability type {}", self.get_type_raw()),
         }
@@ -90,53 +139,38 @@ impl Capability {
 
     pub fn get_pptr(&self) -> Paddr {
         match self.get_info() {
-            CapInfo::CnodeCap { ptr } => ptr,
+            CapInfo::CnodeCap { ptr, .. } => ptr,
             CapInfo::PageTableCap { pptr, .. } => pptr,
+            CapInfo::FrameCap { pptr, .. } => pptr,
+            CapInfo::AsidPoolCap { pptr, .. } => pptr,
             _ => panic!("This cnode has no ptr field"),
         }
     }
 
     /// 调试：打印该cap对应的cnode的全部内容
     pub fn debug_print_cnode(&self) {
-        match self.get_info() {
-            CapInfo::CnodeCap { ptr } => {
-                println!("\n****** cnode cap info ******");
-                let caps_num = 1 << CONFIG_ROOT_CNODE_SIZE_BITS;
-                for i in 0..caps_num {
-                    let capslot = CapSlot::slot_ref(ptr, i);
-                    let info = capslot.cap.get_info();
-                    match info {
-                        CapInfo::NullCap => {}
-                        _ => println!("cnode index {}: {:x?}", i, info),
-                    }
-                }
-                println!("****************************\n");
-            }
-            _ => {
-                panic!("Error: Not a cnode cap!");
+        let cnode = KObj::<CNode>::from_cap(self).expect("Error: Not a cnode cap!");
+        println!("\n****** cnode cap info ******");
+        for i in 0..bit!(cnode.radix()) {
+            let info = cnode.slot(i).unwrap().cap().get_info();
+            match info {
+                CapInfo::NullCap => {}
+                _ => println!("cnode index {}: {:x?}", i, info),
             }
         }
+        println!("****************************\n");
     }
 
-    pub fn cnode_slot_at(&self, index: usize) -> &'static mut CapSlot {
-        match self.get_info() {
-            CapInfo::CnodeCap { ptr } => CapSlot::slot_ref(ptr, index),
-            _ => {
-                loop {}
-                panic!("Error: Not a cnode cap!");
-            }
+    pub fn cnode_slot_at(&self, index: usize) -> SlotRef {
+        let cnode = KObj::<CNode>::from_cap(self).expect("Error: Not a cnode cap!");
+        match cnode.slot(index) {
+            Some(slot) => slot,
+            None => panic!("Error: slot index exceeds max cnode size"),
         }
     }
 
     pub fn cnode_write_slot_at(&self, index: usize, cap: Capability) {
-        match self.get_info() {
-            CapInfo::CnodeCap { ptr } => {
-                CapSlot::slot_ref(ptr, index).write(cap);
-            }
-            _ => {
-                panic!("Error: Not a cnode cap!");
-            }
-        }
+        self.cnode_slot_at(index).write(cap);
     }
 
     pub fn cap_cnode_cap_new(
@@ -188,8 +222,11 @@ impl Capability {
     ) -> Capability {
         let mut cap = Self::new_empty();
 
-        cap.words[0] =
-            CAP_PAGE_TABLE_CAP << 59 | (capPTIsMapped as usize) << 39 | capPTMappedAddress;
+        /* mapped addresses are page aligned and stored as page numbers,
+         * so that they fit for every paging mode */
+        cap.words[0] = CAP_PAGE_TABLE_CAP << 59
+            | (capPTIsMapped as usize) << 44
+            | (capPTMappedAddress >> seL4_PageBits) & mask!(44);
         cap.words[1] = capPTMappedASID << 48 | capPTBasePtr << 9;
 
         cap
@@ -216,7 +253,7 @@ impl Capability {
             | capFSize << 57
             | capFVMRights << 55
             | (capFIsDevice as usize) << 54
-            | capFMappedAddress;
+            | (capFMappedAddress >> seL4_PageBits) & mask!(44);
         cap.words[1] = capFMappedASID << 48 | capFBasePtr << 9;
 
         cap
@@ -240,6 +277,37 @@ impl Capability {
         cap
     }
 
+    pub fn cap_endpoint_cap_new(
+        capEPBadge: usize,
+        capCanGrantReply: bool,
+        capCanGrant: bool,
+        capCanReceive: bool,
+        capCanSend: bool,
+        capEPPtr: usize,
+    ) -> Capability {
+        let mut cap = Self::new_empty();
+        cap.words[0] = CAP_ENDPOINT_CAP << 59
+            | (capCanGrantReply as usize) << 58
+            | (capCanGrant as usize) << 57
+            | (capCanReceive as usize) << 56
+            | (capCanSend as usize) << 55
+            | capEPPtr & mask!(39);
+        cap.words[1] = capEPBadge;
+        cap
+    }
+
+    pub fn cap_reply_cap_new(
+        capReplyCanGrant: bool,
+        capReplyMaster: bool,
+        capTCBPtr: usize,
+    ) -> Capability {
+        let mut cap = Self::new_empty();
+        cap.words[0] =
+            CAP_REPLY_CAP << 59 | (capReplyCanGrant as usize) << 1 | capReplyMaster as usize;
+        cap.words[1] = capTCBPtr;
+        cap
+    }
+
     pub fn cap_untyped_cap_new(
         capFreeIndex: usize,
         capIsDevice: bool,
@@ -279,25 +347,32 @@ impl MDBNode {
     }
 }
 
+/// cnode中的一个slot，只能通过SlotRef访问
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
 pub struct CapSlot {
-    pub cap: Capability,
-    pub mdb_node: MDBNode,
+    cap: KCell<Capability>,
+    mdb_node: KCell<MDBNode>,
 }
 
 impl CapSlot {
-    pub fn slot_ref(base: Paddr, index: usize) -> &'static mut Self {
-        if index >= 1 << CONFIG_ROOT_CNODE_SIZE_BITS {
-            panic!("Error: slot index exceeds max cnode size");
-        }
-        unsafe { base.as_raw_ptr_mut::<Self>().add(index).as_mut().unwrap() }
+    pub fn cap(&self) -> Capability {
+        self.cap.get()
+    }
+
+    pub fn set_cap(&self, cap: Capability) {
+        self.cap.set(cap);
+    }
+
+    pub fn mdb_node(&self) -> MDBNode {
+        self.mdb_node.get()
     }
 
-    pub fn write(&mut self, cap: Capability) {
-        self.cap = cap;
-        self.mdb_node = MDBNode::new_empty();
-        self.mdb_node.set_mdb_revocable(true);
-        self.mdb_node.set_mdb_first_badged(true);
+    pub fn write(&self, cap: Capability) {
+        self.cap.set(cap);
+        self.mdb_node.update(|mdb_node| {
+            *mdb_node = MDBNode::new_empty();
+            mdb_node.set_mdb_revocable(true);
+            mdb_node.set_mdb_first_badged(true);
+        });
     }
 }
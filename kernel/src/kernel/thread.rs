@@ -1,75 +1,164 @@
-use core::{fmt, mem::size_of, ptr};
+use core::{arch::global_asm, fmt, mem::size_of};
 
-use alloc::{
-    string::{String, ToString},
-    vec::Vec,
-};
-use sel4_common::{bit, constants::seL4_TCBBits, round_down};
+use sel4_common::{bit, constants::seL4_TCBBits};
 use spin::{mutex::Mutex, Lazy};
 
 use crate::{
-    common::{seL4_MinPrio, TCB_OFFSET},
+    common::{seL4_MinPrio, TCB_NAME_LENGTH, TCB_OFFSET, TCB_SIZE_BITS, WORD_BITS},
     kernel::statedata::ksIdleThread,
     machine::{
-        registerset::{Rv64Reg, SSTATUS_SPIE},
+        fpu::UserFpuState,
+        registerset::{Rv64Reg, SSTATUS_SPIE, SSTATUS_SPP},
+        sbi::sbi_hsm_available,
         Paddr, Vaddr,
     },
+    object::endpoint::cancel_ipc,
     println,
 };
 
 use super::{
-    statedata::{ksCurThread, ksSchedulerAction, SchedulerAction},
-    structures::CapSlot,
+    kobj::{KCell, KObj},
+    statedata::{
+        ksCurThread, ksDebugTCBs, ksReadyQueues, ksReadyQueuesBitmap, ksSchedulerAction,
+        SchedulerAction,
+    },
     vspace::set_vm_root,
 };
 
+global_asm!(include_str!("idle.S"));
+
 pub const ThreadState_Inactive: u8 = 0;
 pub const ThreadState_Running: u8 = 1;
 pub const ThreadState_Restart: u8 = 2;
 pub const ThreadState_BlockedOnReceive: u8 = 3;
-pub const ThreadState_BlockedOnSend: u8 = 3;
-pub const ThreadState_BlockedOnReply: u8 = 4;
-pub const ThreadState_BlockedOnNotification: u8 = 5;
-pub const ThreadState_RunningVM: u8 = 6;
+pub const ThreadState_BlockedOnSend: u8 = 4;
+pub const ThreadState_BlockedOnReply: u8 = 5;
+pub const ThreadState_BlockedOnNotification: u8 = 6;
 pub const ThreadState_IdleThreadState: u8 = 7;
+pub const ThreadState_RunningVM: u8 = 8;
 
+/// 线程状态，其余字段只在阻塞于端点时有意义，见seL4的thread_state_t
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct ThreadState {
     pub ts_type: u8,
+    pub blocking_ipc_is_call: bool,
+    pub blocking_ipc_can_grant: bool,
+    pub blocking_ipc_can_grant_reply: bool,
+    pub blocking_ipc_badge: usize,
+    /* the endpoint the thread is queued on */
+    pub blocking_object: Paddr,
 }
 
+impl ThreadState {
+    pub const fn new(ts_type: u8) -> Self {
+        Self {
+            ts_type,
+            blocking_ipc_is_call: false,
+            blocking_ipc_can_grant: false,
+            blocking_ipc_can_grant_reply: false,
+            blocking_ipc_badge: 0,
+            blocking_object: Paddr(0),
+        }
+    }
+}
+
+/// 可以为空的线程指针，只能由KObj<Tcb>得到
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
-pub struct ThreadPointer(pub Paddr);
+pub struct ThreadPointer(Paddr);
 
 impl ThreadPointer {
+    pub const fn null() -> Self {
+        Self(Paddr(0))
+    }
     pub fn is_null(&self) -> bool {
         self.0 .0 == 0
     }
-    pub fn get(&self) -> Option<&'static mut TCBInner> {
+    pub fn get(&self) -> Option<KObj<Tcb>> {
         if self.is_null() {
             None
         } else {
-            Some(unsafe { self.0.as_mut() })
+            Some(unsafe { KObj::from_paddr(self.0, TCB_SIZE_BITS) })
         }
     }
 }
 
+impl From<KObj<Tcb>> for ThreadPointer {
+    fn from(tcb: KObj<Tcb>) -> Self {
+        Self(tcb.pptr())
+    }
+}
+
+/// 线程队列：一个优先级的就绪队列通过tcb_sched_next/prev链接，端点的等待队列通过tcb_ep_next/prev链接
+#[derive(Clone, Copy)]
+pub struct TcbQueue {
+    pub head: ThreadPointer,
+    pub end: ThreadPointer,
+}
+
+impl TcbQueue {
+    pub const fn empty() -> Self {
+        Self {
+            head: ThreadPointer::null(),
+            end: ThreadPointer::null(),
+        }
+    }
+
+    /// 加入端点等待队列的队尾，见seL4的tcbEPAppend
+    pub fn ep_append(self, tcb: &KObj<Tcb>) -> Self {
+        let mut queue = self;
+        match queue.end.get() {
+            Some(end) => end.tcb_ep_next.set(tcb.pointer()),
+            None => queue.head = tcb.pointer(),
+        }
+        tcb.tcb_ep_prev.set(queue.end);
+        tcb.tcb_ep_next.set(ThreadPointer::null());
+        queue.end = tcb.pointer();
+        queue
+    }
+
+    /// 从端点等待队列中移除，见seL4的tcbEPDequeue
+    pub fn ep_dequeue(self, tcb: &KObj<Tcb>) -> Self {
+        let mut queue = self;
+        let prev = tcb.tcb_ep_prev.get();
+        let next = tcb.tcb_ep_next.get();
+        match prev.get() {
+            Some(prev) => prev.tcb_ep_next.set(next),
+            None => queue.head = next,
+        }
+        match next.get() {
+            Some(next) => next.tcb_ep_prev.set(prev),
+            None => queue.end = prev,
+        }
+        queue
+    }
+}
+
+/// TCB对象，位于TCB块的后半部分，前半部分是它的cnode
 #[repr(C)]
-#[derive(Debug)]
-pub struct TCBInner {
-    pub name: String,
-    pub registers: [usize; Rv64Reg::n_contextRegisters as _],
-    pub tcb_state: ThreadState,
-    pub tcb_priority: usize,
-    pub tcb_ipc_buffer: Vaddr,
+pub struct Tcb {
+    name: KCell<[u8; TCB_NAME_LENGTH]>,
+    registers: KCell<[usize; Rv64Reg::n_contextRegisters as _]>,
+    /* only up to date while the thread does not own the FPU, see fpu.rs */
+    tcb_fpu_state: KCell<UserFpuState>,
+    tcb_state: KCell<ThreadState>,
+    tcb_priority: KCell<usize>,
+    tcb_ipc_buffer: KCell<Vaddr>,
+    /* set while the thread is in ksReadyQueues */
+    tcb_queued: KCell<bool>,
+    tcb_sched_next: KCell<ThreadPointer>,
+    tcb_sched_prev: KCell<ThreadPointer>,
+    /* links in the queue of the endpoint the thread is blocked on */
+    tcb_ep_next: KCell<ThreadPointer>,
+    tcb_ep_prev: KCell<ThreadPointer>,
+    /* next thread in ksDebugTCBs */
+    debug_next: KCell<ThreadPointer>,
 }
 
-impl fmt::Display for TCBInner {
+impl fmt::Display for Tcb {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let tcb_name = self.name.as_str();
-        let state = match self.tcb_state.ts_type {
+        let state = match self.state() {
             ThreadState_Inactive => "inactive",
             ThreadState_Running => "running",
             ThreadState_Restart => "restart",
@@ -82,123 +171,355 @@ impl fmt::Display for TCBInner {
             _ => panic!("Unknown thread state"),
         };
         let core = 0;
+        let name = self.name.get();
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
         write!(
             f,
             "{:40}\t{:15}\t{:#x?}\t{:20}\t{}\n",
-            tcb_name,
+            core::str::from_utf8(&name[..len]).unwrap_or("?"),
             state,
-            self.registers[Rv64Reg::FaultIP as usize],
-            self.tcb_priority,
+            self.reg(Rv64Reg::FaultIP),
+            self.priority(),
             core
         )
     }
 }
 
-impl TCBInner {
+impl Tcb {
     pub fn new_empty() -> Self {
         Self {
-            name: String::new(),
-            registers: [0; Rv64Reg::n_contextRegisters as _],
-            tcb_state: ThreadState {
-                ts_type: ThreadState_Inactive,
-            },
-            tcb_priority: seL4_MinPrio,
-            tcb_ipc_buffer: Vaddr(0),
+            name: KCell::new([0; TCB_NAME_LENGTH]),
+            registers: KCell::new([0; Rv64Reg::n_contextRegisters as _]),
+            tcb_fpu_state: KCell::new(UserFpuState::new()),
+            tcb_state: KCell::new(ThreadState::new(ThreadState_Inactive)),
+            tcb_priority: KCell::new(seL4_MinPrio),
+            tcb_ipc_buffer: KCell::new(Vaddr(0)),
+            tcb_queued: KCell::new(false),
+            tcb_sched_next: KCell::new(ThreadPointer::null()),
+            tcb_sched_prev: KCell::new(ThreadPointer::null()),
+            tcb_ep_next: KCell::new(ThreadPointer::null()),
+            tcb_ep_prev: KCell::new(ThreadPointer::null()),
+            debug_next: KCell::new(ThreadPointer::null()),
         }
     }
 
-    pub fn init_context(&mut self) {
+    pub fn init_context(&self) {
         /* Enable supervisor interrupts (when going to user-mode) */
-        self.registers[Rv64Reg::SSTATUS as usize] = SSTATUS_SPIE;
+        self.set_reg(Rv64Reg::SSTATUS, SSTATUS_SPIE);
+    }
+
+    pub fn reg(&self, reg: Rv64Reg) -> usize {
+        self.registers.update(|registers| registers[reg as usize])
+    }
+
+    pub fn set_reg(&self, reg: Rv64Reg, value: usize) {
+        self.registers
+            .update(|registers| registers[reg as usize] = value);
+    }
+
+    /// 保存的用户态寄存器，trap.S直接读写
+    pub fn registers_ptr(&self) -> *mut usize {
+        self.registers.as_ptr() as *mut usize
+    }
+
+    /// 保存的浮点寄存器，读写前要先调用fpu_release
+    pub fn fpu_state(&self) -> UserFpuState {
+        self.tcb_fpu_state.get()
+    }
+
+    pub fn set_fpu_state(&self, state: UserFpuState) {
+        self.tcb_fpu_state.set(state);
+    }
+
+    /// 供fpu.S直接读写
+    pub fn fpu_state_ptr(&self) -> *mut UserFpuState {
+        self.tcb_fpu_state.as_ptr()
+    }
+
+    pub fn state(&self) -> u8 {
+        self.tcb_state.get().ts_type
+    }
+
+    pub fn thread_state(&self) -> ThreadState {
+        self.tcb_state.get()
+    }
+
+    /// 只修改状态，不检查是否需要重新调度，fastpath自己决定切换到哪个线程
+    pub fn set_state(&self, state: ThreadState) {
+        self.tcb_state.set(state);
+    }
+
+    pub fn priority(&self) -> usize {
+        self.tcb_priority.get()
+    }
+
+    pub fn set_priority(&self, priority: usize) {
+        self.tcb_priority.set(priority);
+    }
+
+    pub fn ipc_buffer(&self) -> Vaddr {
+        self.tcb_ipc_buffer.get()
+    }
+
+    pub fn set_ipc_buffer(&self, buffer: Vaddr) {
+        self.tcb_ipc_buffer.set(buffer);
     }
 
     pub fn is_runnable(&self) -> bool {
-        // self.tcb_state.ts_type
-        self.tcb_state.ts_type == ThreadState_Running
-            || self.tcb_state.ts_type == ThreadState_Restart
+        self.state() == ThreadState_Running || self.state() == ThreadState_Restart
     }
 
-    pub fn ptr_eq(&self, another: &TCBInner) -> bool {
-        ptr::eq(self as *const _, another as *const _)
+    pub fn set_thread_name(&self, name: &str) {
+        let mut buf = [0; TCB_NAME_LENGTH];
+        /* cut at a character boundary, so that the name stays valid utf-8 */
+        let mut len = name.len().min(TCB_NAME_LENGTH);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.name.set(buf);
     }
+}
 
+impl KObj<Tcb> {
     pub fn schedule_tcb(&self) {
         let cur_thread = *(ksCurThread.lock());
         if cur_thread.is_null() {
             return;
         }
         let action = *(ksSchedulerAction.lock());
-        if self.ptr_eq(cur_thread.get().unwrap()) && !self.is_runnable() {
+        if self.ptr_eq(&cur_thread.get().unwrap()) && !self.is_runnable() {
             if let SchedulerAction::ResumeCurrentThread = action {
-                panic!("reschedule required");
+                reschedule_required();
             }
         }
     }
 
-    pub fn set_thread_state(&mut self, ts: u8) {
-        self.tcb_state.ts_type = ts;
-        self.schedule_tcb();
+    /// 停止线程，见seL4的suspend
+    pub fn suspend(&self) {
+        cancel_ipc(self);
+        self.set_thread_state(ThreadState_Inactive);
+        self.sched_dequeue();
     }
 
-    pub fn tcb_cte_slot(&self, index: usize) -> &mut CapSlot {
-        let ctable = round_down!(self as *const _ as usize, seL4_TCBBits) as *mut CapSlot;
-        unsafe { ctable.add(index).as_mut().unwrap() }
+    /// 让停止的线程重新执行FaultIP处的指令，见seL4的restart
+    pub fn restart(&self) {
+        if self.state() == ThreadState_Inactive {
+            self.set_thread_state(ThreadState_Restart);
+            self.sched_enqueue();
+            possible_switch_to(self);
+        }
+    }
+
+    /// 加入所在优先级就绪队列的队首，见seL4的tcbSchedEnqueue
+    pub fn sched_enqueue(&self) {
+        if self.tcb_queued.get() {
+            return;
+        }
+        let prio = self.priority();
+        let mut queues = ksReadyQueues.lock();
+        let queue = &mut queues[prio];
+        match queue.head.get() {
+            Some(head) => head.tcb_sched_prev.set(self.pointer()),
+            None => {
+                queue.end = self.pointer();
+                add_to_bitmap(prio);
+            }
+        }
+        self.tcb_sched_prev.set(ThreadPointer::null());
+        self.tcb_sched_next.set(queue.head);
+        queue.head = self.pointer();
+        self.tcb_queued.set(true);
+    }
+
+    /// 加入所在优先级就绪队列的队尾，见seL4的tcbSchedAppend
+    pub fn sched_append(&self) {
+        if self.tcb_queued.get() {
+            return;
+        }
+        let prio = self.priority();
+        let mut queues = ksReadyQueues.lock();
+        let queue = &mut queues[prio];
+        match queue.end.get() {
+            Some(end) => end.tcb_sched_next.set(self.pointer()),
+            None => {
+                queue.head = self.pointer();
+                add_to_bitmap(prio);
+            }
+        }
+        self.tcb_sched_prev.set(queue.end);
+        self.tcb_sched_next.set(ThreadPointer::null());
+        queue.end = self.pointer();
+        self.tcb_queued.set(true);
+    }
+
+    /// 从就绪队列中移除，见seL4的tcbSchedDequeue
+    pub fn sched_dequeue(&self) {
+        if !self.tcb_queued.get() {
+            return;
+        }
+        let prio = self.priority();
+        let mut queues = ksReadyQueues.lock();
+        let queue = &mut queues[prio];
+        let prev = self.tcb_sched_prev.get();
+        let next = self.tcb_sched_next.get();
+        match prev.get() {
+            Some(prev) => prev.tcb_sched_next.set(next),
+            None => queue.head = next,
+        }
+        match next.get() {
+            Some(next) => next.tcb_sched_prev.set(prev),
+            None => queue.end = prev,
+        }
+        if queue.head.is_null() {
+            remove_from_bitmap(prio);
+        }
+        self.tcb_queued.set(false);
+    }
+
+    pub fn set_thread_state(&self, ts: u8) {
+        self.set_full_thread_state(ThreadState::new(ts));
+    }
+
+    /// 连同阻塞参数一起设置线程状态
+    pub fn set_full_thread_state(&self, state: ThreadState) {
+        self.set_state(state);
+        self.schedule_tcb();
     }
 
     pub fn pointer(&self) -> ThreadPointer {
-        ThreadPointer(Paddr(self as *const _ as usize))
+        ThreadPointer::from(*self)
     }
 
-    pub fn set_thread_name(&mut self, name: &str) {
-        let truncated_name = name.chars().take(40).collect::<String>();
-        self.name = truncated_name;
+    /// 加入调试用的线程链表ksDebugTCBs，见seL4的tcbDebugAppend
+    pub fn debug_append(&self) {
+        let mut head = ksDebugTCBs.lock();
+        self.debug_next.set(*head);
+        *head = self.pointer();
     }
 }
 
+/// 按ksDebugTCBs的顺序遍历所有线程，最后创建的在最前面
+pub fn debug_for_each_tcb(mut f: impl FnMut(KObj<Tcb>)) {
+    let mut cur = *ksDebugTCBs.lock();
+    while let Some(tcb) = cur.get() {
+        f(tcb);
+        cur = tcb.debug_next.get();
+    }
+}
+
+/// 一个完整的TCB块：cnode在前，Tcb在TCB_OFFSET处
 #[repr(C)]
-#[repr(align(1024))]
-pub struct TCB {
+#[repr(align(2048))]
+pub struct TcbBlock {
     data: [u8; bit!(seL4_TCBBits)],
 }
 
-impl TCB {
+impl TcbBlock {
     pub fn new() -> Self {
-        assert!(size_of::<TCBInner>() <= bit!(seL4_TCBBits) - TCB_OFFSET);
+        assert!(size_of::<Tcb>() <= bit!(seL4_TCBBits) - TCB_OFFSET);
         Self {
             data: [0; bit!(seL4_TCBBits)],
         }
     }
     pub fn pptr(&self) -> Paddr {
-        Paddr(self as *const TCB as usize)
+        Paddr::from_kernel_ptr(self)
     }
     pub fn inner_pptr(&self) -> Paddr {
-        Paddr(self as *const TCB as usize + TCB_OFFSET)
+        Paddr(self.pptr().0 + TCB_OFFSET)
+    }
+}
+
+pub static IDLE_THREAD_TCB: Lazy<Mutex<TcbBlock>> = Lazy::new(|| Mutex::new(TcbBlock::new()));
+
+/// 空闲线程在S态执行idle.S中的循环，打开中断，见seL4的configureIdleThread
+pub fn configure_idle_thread(tcb: &KObj<Tcb>) {
+    extern "C" {
+        fn idle_thread();
+        fn idle_thread_suspend();
+    }
+    let entry = if cfg!(feature = "idle_suspend") && sbi_hsm_available() {
+        idle_thread_suspend as usize
+    } else {
+        idle_thread as usize
+    };
+    tcb.set_reg(Rv64Reg::NextIP, entry);
+    /* sret returns to S-mode and sets SIE */
+    tcb.set_reg(Rv64Reg::SSTATUS, SSTATUS_SPP | SSTATUS_SPIE);
+}
+
+fn add_to_bitmap(prio: usize) {
+    ksReadyQueuesBitmap.lock()[prio / WORD_BITS] |= bit!(prio % WORD_BITS);
+}
+
+fn remove_from_bitmap(prio: usize) {
+    ksReadyQueuesBitmap.lock()[prio / WORD_BITS] &= !bit!(prio % WORD_BITS);
+}
+
+/// 有就绪线程的最高优先级，所有队列都为空时返回None
+fn get_highest_prio() -> Option<usize> {
+    let bitmap = ksReadyQueuesBitmap.lock();
+    let (index, word) = bitmap
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|&(_, &word)| word != 0)?;
+    Some(index * WORD_BITS + WORD_BITS - 1 - word.leading_zeros() as usize)
+}
+
+/// 没有优先级高于prio的就绪线程
+pub fn is_highest_prio(prio: usize) -> bool {
+    get_highest_prio().map_or(true, |highest| prio >= highest)
+}
+
+/// 当前线程不能再运行或者有更高优先级的线程就绪，下次schedule时重新选择
+pub fn reschedule_required() {
+    let action = *(ksSchedulerAction.lock());
+    if let SchedulerAction::SwitchToThread(candidate) = action {
+        candidate.get().unwrap().sched_enqueue();
     }
-    pub unsafe fn inner(&self) -> &TCBInner {
-        self.inner_pptr().as_ref()
+    *(ksSchedulerAction.lock()) = SchedulerAction::ChooseNewThread;
+}
+
+/// 线程变为就绪后，调度器没有其他安排时切换过去，见seL4的possibleSwitchTo
+pub fn possible_switch_to(target: &KObj<Tcb>) {
+    let action = *(ksSchedulerAction.lock());
+    if let SchedulerAction::ResumeCurrentThread = action {
+        *(ksSchedulerAction.lock()) = SchedulerAction::SwitchToThread(target.pointer());
+    } else {
+        reschedule_required();
+        target.sched_enqueue();
     }
-    pub unsafe fn inner_mut(&self) -> &mut TCBInner {
-        self.inner_pptr().as_mut()
+}
+
+/// 运行优先级最高的就绪线程，没有时运行空闲线程
+fn choose_thread() {
+    match get_highest_prio() {
+        Some(prio) => {
+            let thread = ksReadyQueues.lock()[prio].head;
+            assert!(thread.get().unwrap().is_runnable());
+            switch_to_thread(thread);
+        }
+        None => switch_to_idle_thread(),
     }
 }
 
-pub static IDLE_THREAD_TCB: Lazy<Mutex<TCB>> = Lazy::new(|| Mutex::new(TCB::new()));
+fn schedule_choose_new_thread() {
+    choose_thread();
+}
 
 pub fn schedule() {
     let action = *(ksSchedulerAction.lock());
     match action {
         SchedulerAction::ResumeCurrentThread => {}
         _ => {
-            // let was_runnable;
             let cur_thread = ksCurThread.lock().get().unwrap();
-            let was_runnable = if cur_thread.is_runnable() {
-                todo!("SCHED_ENQUEUE_CURRENT_TCB");
-                true
-            } else {
-                false
-            };
+            let was_runnable = cur_thread.is_runnable();
+            if was_runnable {
+                cur_thread.sched_enqueue();
+            }
             if let SchedulerAction::ChooseNewThread = action {
-                todo!("scheduleChooseNewThread");
+                schedule_choose_new_thread();
             } else if let SchedulerAction::SwitchToThread(candidate) = action {
                 let target = candidate.get().unwrap();
                 assert!(target.is_runnable());
@@ -206,12 +527,18 @@ pub fn schedule() {
                  * match fast path.
                  * Don't look at ksCurThread prio when it's idle, to respect
                  * information flow in non-fastpath cases. */
-                let fastfail = cur_thread.ptr_eq(ksIdleThread.lock().get().unwrap())
-                    && target.tcb_priority < cur_thread.tcb_priority;
-                if fastfail {
-                    todo!("scheduleChooseNewThread");
+                let fastfail = cur_thread.ptr_eq(&ksIdleThread.lock().get().unwrap())
+                    || target.priority() < cur_thread.priority();
+                if fastfail && !is_highest_prio(target.priority()) {
+                    /* Prefer threads with higher priorities */
+                    target.sched_enqueue();
+                    schedule_choose_new_thread();
+                } else if was_runnable && target.priority() == cur_thread.priority() {
+                    /* Prefer the current thread when it has the same priority */
+                    target.sched_append();
+                    schedule_choose_new_thread();
                 } else {
-                    assert!(!target.ptr_eq(cur_thread));
+                    assert!(!target.ptr_eq(&cur_thread));
                     switch_to_thread(candidate);
                 }
             } else {
@@ -224,16 +551,27 @@ pub fn schedule() {
 
 pub fn switch_to_thread(tcb: ThreadPointer) {
     set_vm_root(tcb);
+    tcb.get().unwrap().sched_dequeue();
     *(ksCurThread.lock()) = tcb;
 }
 
+/// 切换到空闲线程，使用只有内核映射的页表
+pub fn switch_to_idle_thread() {
+    let idle = *ksIdleThread.lock();
+    set_vm_root(idle);
+    *(ksCurThread.lock()) = idle;
+}
+
 pub fn activate_thread() {
     let cur_thread = ksCurThread.lock().get().unwrap();
-    match cur_thread.tcb_state.ts_type {
+    match cur_thread.state() {
         ThreadState_Running => {}
-        ThreadState_Restart => todo!("thread restart"),
-        _ => todo!("activate_thread, type = {}", cur_thread.tcb_state.ts_type),
+        ThreadState_IdleThreadState => { /* Don't need to do anything */ }
+        ThreadState_Restart => {
+            let pc = cur_thread.reg(Rv64Reg::FaultIP);
+            cur_thread.set_reg(Rv64Reg::NextIP, pc);
+            cur_thread.set_thread_state(ThreadState_Running);
+        }
+        _ => todo!("activate_thread, type = {}", cur_thread.state()),
     }
 }
-
-pub static THREAD_LIST: Lazy<Mutex<Vec<ThreadPointer>>> = Lazy::new(|| Mutex::new(Vec::new()));
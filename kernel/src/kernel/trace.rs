@@ -0,0 +1,132 @@
+use core::mem::size_of;
+
+use riscv::register::time;
+use sel4_common::{
+    bit,
+    trace::{TraceEntry, TraceLogHeader, TRACE_LOG_MAGIC},
+};
+use spin::Mutex;
+
+use crate::{
+    kernel::vspace::page_bits_for_size,
+    machine::{Paddr, PLATFORM},
+    traps::syscalls::{seL4_IllegalOperation, seL4_NoError},
+};
+
+use super::{kobj::SlotRef, statedata::ksCurThread, structures::CapInfo};
+
+/// 内核进出的日志，写在用户提供的frame中，见seL4的benchmark_track
+struct TraceLog {
+    /* header followed by the entries, the frame stays mapped in user space */
+    buffer: Option<Paddr>,
+    /* kept here, the user can overwrite the header */
+    capacity: usize,
+    count: usize,
+    enabled: bool,
+    /* the current kernel entry, written out on the way back to user space */
+    current: Option<TraceEntry>,
+}
+
+static TRACE_LOG: Mutex<TraceLog> = Mutex::new(TraceLog {
+    buffer: None,
+    capacity: 0,
+    count: 0,
+    enabled: false,
+    current: None,
+});
+
+impl TraceLog {
+    fn write_header(&self) {
+        let buffer = match self.buffer {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let header = TraceLogHeader {
+            magic: TRACE_LOG_MAGIC,
+            entry_size: size_of::<TraceEntry>() as u32,
+            capacity: self.capacity as u64,
+            count: self.count as u64,
+            timebase_frequency: PLATFORM.lock().timebase_frequency.unwrap_or(0) as u64,
+        };
+        unsafe {
+            buffer
+                .as_raw_ptr_mut::<TraceLogHeader>()
+                .write_volatile(header)
+        };
+    }
+}
+
+/// 进入内核时调用，记录原因、当前线程和时间
+pub fn trace_kernel_entry(path: u32, cause: usize) {
+    let start = time::read() as u64;
+    let tcb = ksCurThread.lock().get().map_or(0, |tcb| tcb.pptr().0);
+    TRACE_LOG.lock().current = Some(TraceEntry {
+        path,
+        core: 0,
+        cause: cause as u64,
+        tcb: tcb as u64,
+        start,
+        end: 0,
+    });
+}
+
+/// 返回用户态前调用，把这次进入内核的记录写入环形缓冲区
+pub fn trace_kernel_exit() {
+    let mut log = TRACE_LOG.lock();
+    let mut entry = match log.current.take() {
+        Some(entry) => entry,
+        None => return,
+    };
+    let buffer = match log.buffer {
+        Some(buffer) if log.enabled => buffer,
+        _ => return,
+    };
+    entry.end = time::read() as u64;
+    let entries = Paddr(buffer.0 + size_of::<TraceLogHeader>()).as_raw_ptr_mut::<TraceEntry>();
+    unsafe { entries.add(log.count % log.capacity).write_volatile(entry) };
+    log.count += 1;
+    log.write_header();
+}
+
+/// seL4_SysBenchmarkSetLogBuffer：用frame作为日志，返回错误类型
+pub fn set_log_buffer(frame_slot: SlotRef) -> usize {
+    let (pptr, size, is_device) = match frame_slot.cap().get_info() {
+        CapInfo::FrameCap {
+            pptr,
+            size,
+            is_device,
+            ..
+        } => (pptr, size, is_device),
+        _ => return seL4_IllegalOperation,
+    };
+    if is_device {
+        return seL4_IllegalOperation;
+    }
+    let frame_size = bit!(page_bits_for_size(size));
+    let mut log = TRACE_LOG.lock();
+    log.buffer = Some(pptr);
+    log.capacity = (frame_size - size_of::<TraceLogHeader>()) / size_of::<TraceEntry>();
+    log.count = 0;
+    log.enabled = false;
+    log.write_header();
+    seL4_NoError
+}
+
+/// seL4_SysBenchmarkResetLog：清空日志并开始记录
+pub fn reset_log() -> usize {
+    let mut log = TRACE_LOG.lock();
+    if log.buffer.is_none() {
+        return seL4_IllegalOperation;
+    }
+    log.count = 0;
+    log.enabled = true;
+    log.write_header();
+    seL4_NoError
+}
+
+/// seL4_SysBenchmarkFinalizeLog：停止记录，返回写入过的条数
+pub fn finalize_log() -> usize {
+    let mut log = TRACE_LOG.lock();
+    log.enabled = false;
+    log.count
+}
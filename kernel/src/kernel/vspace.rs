@@ -1,28 +1,70 @@
-use core::arch::asm;
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crate::{
-    common::{KERNEL_ELF_BASE, PAGE_PTES, PAGE_SIZE, PTE_FLAG_BITS, PT_INDEX_BITS},
-    get_level_pgbits,
-    machine::{Paddr, Vaddr, Vregion},
-    mask, println,
+    common::{
+        CONFIG_PT_LEVELS, KERNEL_ELF_BASE, PADDR_BASE, PAGE_PTES, PAGE_SIZE, PPTR_BASE, PPTR_TOP,
+        PTE_FLAG_BITS, PT_INDEX_BITS, SATP_MODE, USER_TOP,
+    },
+    get_level_pgbits, is_aligned,
+    machine::{
+        clear_memory, hw_asid_flush, hw_asid_flush_page, kpptr_to_paddr, registerset::Rv64Reg,
+        sfence, sfence_page, Paddr, Vaddr, Vregion,
+    },
+    mask, max_free_index,
+    object::{cnode::cte_insert, endpoint::set_mr, tcb::CUR_EXTRA_CAPS},
+    println,
+    traps::syscalls::{
+        get_syscall_arg, seL4_AlignmentError, seL4_DeleteFirst, seL4_FailedLookup,
+        seL4_IllegalOperation, seL4_RevokeFirst, seL4_TruncatedMessage, SyscallError,
+    },
 };
 use riscv::register::satp;
 use sel4_common::{
     bit,
-    constants::seL4_PageBits,
+    constants::{
+        seL4_ASIDPoolBits, seL4_HugePageBits, seL4_IPCBufferSizeBits, seL4_LargePageBits,
+        seL4_PageBits, seL4_PageTableBits,
+    },
+    invocation::{
+        LABEL_RISCV_ASID_CONTROL_MAKE_POOL, LABEL_RISCV_ASID_POOL_ASSIGN,
+        LABEL_RISCV_PAGE_GET_ADDRESS, LABEL_RISCV_PAGE_MAP, LABEL_RISCV_PAGE_TABLE_MAP,
+        LABEL_RISCV_PAGE_TABLE_UNMAP, LABEL_RISCV_PAGE_UNMAP,
+    },
     round_down, round_up,
-    structures_common::{tcbBuffer, tcbVTable},
+    shared_types::{IPCBuffer, MessageInfo},
+    structures_common::{tcbBuffer, tcbVTable, CapRights, VMAttributes},
 };
 use spin::{Lazy, Mutex};
 
 use super::{
+    cspace::lookup_target_slot,
+    kobj::{check_kernel_locked, KCell, KObj, SlotRef},
+    statedata::ksCurThread,
     structures::{CapInfo, Capability},
-    thread::{TCBInner, ThreadPointer},
+    thread::{Tcb, ThreadPointer, ThreadState_Restart, ThreadState_Running},
 };
 
 pub const ASID_INVALID: usize = 0;
 pub const IT_ASID: usize = 1;
 
+// frame sizes (capFSize)
+pub const RISCV_4K_Page: usize = 0;
+pub const RISCV_Mega_Page: usize = 1;
+pub const RISCV_Giga_Page: usize = 2;
+
+/// 由frame cap中的capFSize得到页面大小的位数
+pub fn page_bits_for_size(page_size: usize) -> usize {
+    match page_size {
+        RISCV_4K_Page => seL4_PageBits,
+        RISCV_Mega_Page => seL4_LargePageBits,
+        RISCV_Giga_Page => seL4_HugePageBits,
+        _ => panic!("invalid page size {}", page_size),
+    }
+}
+
 bitflags! {
     pub struct PTEFlags: u8 {
         const V = 1 << 0;
@@ -81,54 +123,266 @@ pub struct PageTable {
     root: [PTE; PAGE_PTES],
 }
 
-pub static KERNEL_PT: Lazy<Mutex<PageTable>> = Lazy::new(|| {
-    Mutex::new({
+pub static KERNEL_PT: Lazy<Mutex<PageTable>> = Lazy::new(|| Mutex::new(PageTable::new()));
+
+/// 内核镜像所在区域的中间级页表，第i个位于第i + 1级
+static KERNEL_IMAGE_PT: Lazy<Mutex<[PageTable; CONFIG_PT_LEVELS - 2]>> =
+    Lazy::new(|| Mutex::new([(); CONFIG_PT_LEVELS - 2].map(|_| PageTable::new())));
+
+/// 内核镜像的最后一级页表，每个覆盖2MiB，内核镜像以4KiB页面映射
+const KERNEL_IMAGE_PAGE_PTS: usize = 4;
+static KERNEL_IMAGE_PAGE_PT: Lazy<Mutex<[PageTable; KERNEL_IMAGE_PAGE_PTS]>> =
+    Lazy::new(|| Mutex::new([(); KERNEL_IMAGE_PAGE_PTS].map(|_| PageTable::new())));
+
+impl PageTable {
+    const fn new() -> Self {
         PageTable {
             root: [PTE(0); PAGE_PTES],
         }
-    })
-});
+    }
 
-impl PageTable {
     pub fn map_kernel_window(&mut self) {
-        let pa = Paddr(round_down!(KERNEL_ELF_BASE, get_level_pgbits!(0)));
-        let va = Vaddr(pa.0);
+        /* map all of physical memory into the kernel window with root level pages */
+        let mut pa = PADDR_BASE;
+        let mut va = PPTR_BASE;
+        while va < PPTR_TOP {
+            self.root[Vaddr(va).pt_level_index(0)] = PTE::new(
+                Paddr(pa),
+                PTEFlags::R | PTEFlags::W | PTEFlags::G | PTEFlags::A | PTEFlags::D | PTEFlags::V,
+            );
+            pa += bit!(get_level_pgbits!(0));
+            va += bit!(get_level_pgbits!(0));
+        }
 
-        // insert identical mapping
-        self.root[va.pt_level_index(0)] = PTE::new(
-            pa,
-            PTEFlags::R
-                | PTEFlags::X
-                | PTEFlags::W
-                | PTEFlags::G
-                | PTEFlags::A
-                | PTEFlags::D
-                | PTEFlags::V,
+        /* the kernel image is mapped with 4K pages so that sections get their own rights */
+        let image_pts = KERNEL_IMAGE_PT.lock();
+        self.root[Vaddr(KERNEL_ELF_BASE).pt_level_index(0)] = PTE::new(
+            Paddr::from_kernel_ptr(image_pts[0].root.as_ptr()),
+            PTEFlags::G | PTEFlags::V,
         );
     }
 
     pub fn satp(&self) -> usize {
-        let root_pa = self.root.as_ptr();
-        8 << 60 | (root_pa as usize >> seL4_PageBits)
+        let root_pa = Paddr::from_kernel_ptr(self.root.as_ptr());
+        SATP_MODE << 60 | (root_pa.0 >> seL4_PageBits)
     }
 
     fn activate(&self, asid: usize) {
-        assert!(asid <= 0xffff);
-        unsafe {
-            satp::write(asid << 44 | self.satp() as usize);
-            asm!("sfence.vma");
-        }
+        write_satp(self.satp(), asid);
+    }
+}
+
+/// 用户的页表对象，其他核可能正在使用它，所以只通过页表项指针访问
+impl KObj<PageTable> {
+    /// 第index个页表项
+    fn pte_ptr(&self, index: usize) -> *mut PTE {
+        check_kernel_locked();
+        assert!(index < PAGE_PTES);
+        unsafe { self.pptr().as_raw_ptr_mut::<PTE>().add(index) }
+    }
+
+    pub fn activate(&self, asid: usize) {
+        write_satp(SATP_MODE << 60 | (self.pptr().0 >> seL4_PageBits), asid);
+    }
+}
+
+/// 以asid切换到根页表satp
+fn write_satp(satp: usize, asid: usize) {
+    assert!(asid <= 0xffff);
+    let (hw_asid, need_flush) = hw_asid_for_asid(asid);
+    unsafe { satp::write(hw_asid << 44 | satp) };
+    if need_flush {
+        sfence();
     }
 }
 
 pub fn activate_kernel_vspace() {
-    KERNEL_PT.lock().activate(0);
+    KERNEL_PT.lock().activate(ASID_INVALID);
+}
+
+/// 硬件实现的asid位数，启动时由probe_hw_asid_bits探测
+static HW_ASID_BITS: AtomicUsize = AtomicUsize::new(0);
+
+/// 软件asid的总位数
+const ASID_BITS: usize = asidHighBits + asidLowBits;
+
+/// 硬件asid不足以容纳全部软件asid时，按代分配硬件asid。
+/// map中每项为 generation << 16 | hw_asid，代数不等于当前代的项已失效。
+/// 硬件asid 0保留给内核地址空间。
+struct HwAsidAllocator {
+    generation: u32,
+    next: usize,
+    map: [u32; bit!(ASID_BITS)],
+}
+
+static HW_ASIDS: Mutex<HwAsidAllocator> = Mutex::new(HwAsidAllocator {
+    generation: 1,
+    next: 1,
+    map: [0; bit!(ASID_BITS)],
+});
+
+impl HwAsidAllocator {
+    /// asid在当前代中持有的硬件asid
+    fn lookup(&self, asid: usize) -> Option<usize> {
+        let entry = self.map[asid];
+        if entry >> 16 == self.generation && entry & 0xffff != 0 {
+            Some((entry & 0xffff) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// 为asid分配硬件asid；硬件asid用尽时开始新的一代，此时需要刷新全部TLB
+    fn alloc(&mut self, asid: usize, hw_bits: usize) -> (usize, bool) {
+        if let Some(hw_asid) = self.lookup(asid) {
+            return (hw_asid, false);
+        }
+        let mut need_flush = false;
+        if self.next == bit!(hw_bits) {
+            self.generation = (self.generation + 1) & 0xffff;
+            if self.generation == 0 {
+                /* generation counter wrapped: old entries could alias the new generation */
+                self.map.fill(0);
+                self.generation = 1;
+            }
+            self.next = 1;
+            need_flush = true;
+        }
+        let hw_asid = self.next;
+        self.next += 1;
+        self.map[asid] = self.generation << 16 | hw_asid as u32;
+        (hw_asid, need_flush)
+    }
+}
+
+/// 探测satp中可写的asid位数
+#[link_section = ".boot.text"]
+pub fn probe_hw_asid_bits() {
+    let old = satp::read().bits();
+    let asid_mask = mask!(ASID_BITS) << 44;
+    unsafe { satp::write(old | asid_mask) };
+    let hw_asid = (satp::read().bits() & asid_mask) >> 44;
+    unsafe { satp::write(old) };
+    /* implemented ASID bits are always the least significant ones */
+    let bits = hw_asid.trailing_ones() as usize;
+    HW_ASID_BITS.store(bits, Ordering::Relaxed);
+    sfence();
+    println!("Kernel init: {} hardware ASID bits", bits);
+}
+
+/// 返回切换到asid时写入satp的硬件asid，以及是否需要刷新TLB
+fn hw_asid_for_asid(asid: usize) -> (usize, bool) {
+    let hw_bits = HW_ASID_BITS.load(Ordering::Relaxed);
+    if asid == ASID_INVALID {
+        /* the kernel window is mapped global, only untagged user entries have to go */
+        (0, hw_bits == 0)
+    } else if hw_bits >= ASID_BITS {
+        (asid, false)
+    } else if hw_bits == 0 {
+        (0, true)
+    } else {
+        HW_ASIDS.lock().alloc(asid, hw_bits)
+    }
+}
+
+/// asid当前持有的硬件asid；None表示TLB中不会有它的项
+fn current_hw_asid(asid: usize) -> Option<usize> {
+    let hw_bits = HW_ASID_BITS.load(Ordering::Relaxed);
+    if hw_bits >= ASID_BITS {
+        Some(asid)
+    } else if hw_bits == 0 {
+        Some(0)
+    } else {
+        HW_ASIDS.lock().lookup(asid)
+    }
+}
+
+/// 刷新asid地址空间中vaddr所在页面的TLB项
+fn flush_page(asid: usize, vaddr: Vaddr) {
+    match current_hw_asid(asid) {
+        Some(0) => sfence_page(vaddr),
+        Some(hw_asid) => hw_asid_flush_page(vaddr, hw_asid),
+        None => {}
+    }
+}
+
+/// 刷新asid地址空间的全部TLB项，用于修改非叶子页表项之后
+fn flush_asid(asid: usize) {
+    match current_hw_asid(asid) {
+        Some(0) => sfence(),
+        Some(hw_asid) => hw_asid_flush(hw_asid),
+        None => {}
+    }
+}
+
+/// 映射内核镜像：boot段RWX，代码段RX，只读数据段R，数据段与bss段RW
+#[link_section = ".boot.text"]
+fn map_kernel_image() {
+    extern "C" {
+        fn ki_boot_end();
+        fn ki_text_end();
+        fn ki_rodata_end();
+        fn ki_end();
+    }
+    /* the last level tables each cover one large page */
+    const LARGE_PAGE_LEVEL: usize = CONFIG_PT_LEVELS - 2;
+    assert!(
+        ki_end as usize - KERNEL_ELF_BASE
+            <= KERNEL_IMAGE_PAGE_PTS << get_level_pgbits!(LARGE_PAGE_LEVEL),
+        "kernel image too large"
+    );
+
+    let mut image_pts = KERNEL_IMAGE_PT.lock();
+    let mut page_pts = KERNEL_IMAGE_PAGE_PT.lock();
+    let image_va = Vaddr(KERNEL_ELF_BASE);
+    for level in 1..LARGE_PAGE_LEVEL {
+        let next = Paddr::from_kernel_ptr(image_pts[level].root.as_ptr());
+        image_pts[level - 1].root[image_va.pt_level_index(level)] =
+            PTE::new(next, PTEFlags::G | PTEFlags::V);
+    }
+    for (i, page_pt) in page_pts.iter_mut().enumerate() {
+        let va = Vaddr(KERNEL_ELF_BASE + (i << get_level_pgbits!(LARGE_PAGE_LEVEL)));
+        image_pts[LARGE_PAGE_LEVEL - 1].root[va.pt_level_index(LARGE_PAGE_LEVEL)] = PTE::new(
+            Paddr::from_kernel_ptr(page_pt.root.as_ptr()),
+            PTEFlags::G | PTEFlags::V,
+        );
+    }
+
+    let mut va = KERNEL_ELF_BASE;
+    while va < ki_end as usize {
+        let rights = if va < ki_boot_end as usize {
+            /* boot code and data are mixed, and reclaimed after boot */
+            PTEFlags::R | PTEFlags::W | PTEFlags::X
+        } else if va < ki_text_end as usize {
+            PTEFlags::R | PTEFlags::X
+        } else if va < ki_rodata_end as usize {
+            PTEFlags::R
+        } else {
+            PTEFlags::R | PTEFlags::W
+        };
+        let page_pt = &mut page_pts[(va - KERNEL_ELF_BASE) >> get_level_pgbits!(LARGE_PAGE_LEVEL)];
+        page_pt.root[Vaddr(va).pt_level_index(CONFIG_PT_LEVELS - 1)] = PTE::new(
+            Paddr(kpptr_to_paddr(va)),
+            rights | PTEFlags::G | PTEFlags::A | PTEFlags::D | PTEFlags::V,
+        );
+        va += PAGE_SIZE;
+    }
 }
 
+#[link_section = ".boot.text"]
 pub fn map_kernel_window() {
+    map_kernel_image();
     KERNEL_PT.lock().map_kernel_window();
 }
 
+/// 将内核的全局映射复制到新的vspace根页表中
+pub fn copy_global_mappings(new_lvl1pt: &KObj<PageTable>) {
+    let kernel_pt = KERNEL_PT.lock();
+    for i in Vaddr(PPTR_BASE).pt_level_index(0)..PAGE_PTES {
+        unsafe { *new_lvl1pt.pte_ptr(i) = kernel_pt.root[i] };
+    }
+}
+
 #[link_section = ".boot.text"]
 fn get_n_paging(v_reg: Vregion, bits: usize) -> usize {
     let start = round_down!(v_reg.start.0, bits);
@@ -139,7 +393,7 @@ fn get_n_paging(v_reg: Vregion, bits: usize) -> usize {
 #[link_section = ".boot.text"]
 pub fn riscv_get_n_paging(it_v_reg: Vregion) -> usize {
     let mut n = 0;
-    for i in 0..2usize {
+    for i in 0..CONFIG_PT_LEVELS - 1 {
         n += get_n_paging(it_v_reg, get_level_pgbits!(i));
     }
     n
@@ -151,28 +405,29 @@ pub struct LookupPTSlotRet {
     pt_bits_left: usize,
 }
 
-fn lookup_ptslot(lvl1pt: *const PTE, vptr: Vaddr) -> LookupPTSlotRet {
-    let mut level = 2;
+fn lookup_ptslot(lvl1pt: &KObj<PageTable>, vptr: Vaddr) -> LookupPTSlotRet {
+    let mut level = CONFIG_PT_LEVELS - 1;
 
     /* this is how many bits we potentially have left to decode. Initially we have the
      * full address space to decode, and every time we walk this will be reduced. The
      * final value of this after the walk is the size of the frame that can be inserted,
      * or already exists, in ret.ptSlot. The following formulation is an invariant of
      * the loop: */
+    let mut pt_bits_left = PT_INDEX_BITS * level + seL4_PageBits;
+    let mut pt_slot = lvl1pt.pte_ptr((vptr.0 >> pt_bits_left) & mask!(PT_INDEX_BITS));
     unsafe {
-        let mut pt_bits_left = PT_INDEX_BITS * level + seL4_PageBits;
-        let mut pt_slot = lvl1pt.add((vptr.0 >> pt_bits_left) & mask!(PT_INDEX_BITS)) as *mut PTE;
         while (*pt_slot).is_pte_pagetable() {
             level -= 1;
             pt_bits_left -= PT_INDEX_BITS;
-            pt_slot = (*pt_slot).pa().as_raw_ptr_mut();
-            pt_slot = pt_slot.add((vptr.0 >> pt_bits_left) & mask!(PT_INDEX_BITS));
-        }
-        LookupPTSlotRet {
-            pt_slot,
-            pt_bits_left,
+            /* table entries are only written by the kernel, from page table caps */
+            let pt = KObj::<PageTable>::from_paddr((*pt_slot).pa(), seL4_PageTableBits);
+            pt_slot = pt.pte_ptr((vptr.0 >> pt_bits_left) & mask!(PT_INDEX_BITS));
         }
     }
+    LookupPTSlotRet {
+        pt_slot,
+        pt_bits_left,
+    }
 }
 
 /// 找到vspace_cap指向的pagetable，然后在页表中建立pt_cap保存的va->pa的映射
@@ -183,39 +438,41 @@ fn map_it_pt_cap(vspace_cap: Capability, pt_cap: Capability) {
         _ => panic!("invalid pt_cap"),
     };
 
-    let root_pt = vspace_cap.get_pptr().0 as *mut PTE;
+    let root_pt = KObj::<PageTable>::from_cap(&vspace_cap).expect("invalid vspace_cap");
 
     /* Get PT slot to install the address in */
-    let pt_ret = lookup_ptslot(root_pt, pt_vptr);
+    let pt_ret = lookup_ptslot(&root_pt, pt_vptr);
     let target_slot = pt_ret.pt_slot;
 
-    unsafe {
-        *target_slot = PTE::new(pt_pptr, PTEFlags::V | PTEFlags::U);
-        asm!("sfence.vma");
-    }
+    /* the initial thread's vspace has never been active, no flush needed */
+    unsafe { *target_slot = PTE::new(pt_pptr, PTEFlags::V | PTEFlags::U) };
 }
 
 #[link_section = ".boot.text"]
-fn map_it_frame_cap(vspace_cap: Capability, frame_cap: Capability) {
-    let (pt_vptr, pt_pptr) = match frame_cap.get_info() {
-        CapInfo::FrameCap { vptr, pptr, .. } => (vptr, pptr),
+fn map_it_frame_cap(vspace_cap: Capability, frame_cap: Capability, executable: bool) {
+    let (pt_vptr, pt_pptr, size, vm_rights) = match frame_cap.get_info() {
+        CapInfo::FrameCap {
+            vptr,
+            pptr,
+            size,
+            vm_rights,
+            ..
+        } => (vptr, pptr, size, VmRights::from(vm_rights)),
         _ => panic!("invalid pt_cap"),
     };
 
-    let root_pt = vspace_cap.get_pptr().as_raw_ptr_mut::<PTE>();
+    let root_pt = KObj::<PageTable>::from_cap(&vspace_cap).expect("invalid vspace_cap");
 
     /* Get PT slot to install the address in */
-    let pt_ret = lookup_ptslot(root_pt, pt_vptr);
-    assert!(pt_ret.pt_bits_left == seL4_PageBits, "{:#x?}", pt_ret);
+    let pt_ret = lookup_ptslot(&root_pt, pt_vptr);
+    assert!(
+        pt_ret.pt_bits_left == page_bits_for_size(size),
+        "{:#x?}",
+        pt_ret
+    );
     let target_slot = pt_ret.pt_slot;
 
-    unsafe {
-        *target_slot = PTE::new(
-            pt_pptr,
-            PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::V | PTEFlags::U,
-        );
-        asm!("sfence.vma");
-    }
+    unsafe { *target_slot = make_user_pte(pt_pptr, executable, vm_rights) };
 }
 
 #[link_section = ".boot.text"]
@@ -237,40 +494,102 @@ pub fn create_it_pt_cap(
     cap
 }
 
-enum VmRights {
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VmRights {
     VMKernelOnly = 1,
     VMReadOnly = 2,
     VMReadWrite = 3,
 }
 
+impl From<usize> for VmRights {
+    fn from(v: usize) -> Self {
+        match v {
+            1 => VmRights::VMKernelOnly,
+            2 => VmRights::VMReadOnly,
+            3 => VmRights::VMReadWrite,
+            _ => panic!("invalid vm rights {}", v),
+        }
+    }
+}
+
+impl VmRights {
+    pub fn readable(&self) -> bool {
+        *self != VmRights::VMKernelOnly
+    }
+
+    pub fn writable(&self) -> bool {
+        *self == VmRights::VMReadWrite
+    }
+}
+
+/// 用用户传入的rights掩码削减frame cap的访问权限
+pub fn mask_vm_rights(vm_rights: VmRights, cap_rights_mask: CapRights) -> VmRights {
+    if vm_rights == VmRights::VMReadOnly && cap_rights_mask.contains(CapRights::R) {
+        return VmRights::VMReadOnly;
+    }
+    if vm_rights == VmRights::VMReadWrite && cap_rights_mask.contains(CapRights::R) {
+        if !cap_rights_mask.contains(CapRights::W) {
+            return VmRights::VMReadOnly;
+        } else {
+            return VmRights::VMReadWrite;
+        }
+    }
+    if vm_rights == VmRights::VMReadWrite
+        && !cap_rights_mask.contains(CapRights::R)
+        && cap_rights_mask.contains(CapRights::W)
+    {
+        println!("Attempted to make unsupported write only mapping");
+    }
+    VmRights::VMKernelOnly
+}
+
+/// 生成用户态页表项，不可读、不可写且不可执行的页面返回无效页表项
+pub fn make_user_pte(pa: Paddr, executable: bool, vm_rights: VmRights) -> PTE {
+    if !vm_rights.readable() && !vm_rights.writable() && !executable {
+        return PTE(0);
+    }
+    let mut flags = PTEFlags::V | PTEFlags::U | PTEFlags::A | PTEFlags::D;
+    if vm_rights.readable() {
+        flags |= PTEFlags::R;
+    }
+    if vm_rights.writable() {
+        flags |= PTEFlags::W;
+    }
+    if executable {
+        flags |= PTEFlags::X;
+    }
+    PTE::new(pa, flags)
+}
+
 #[link_section = ".boot.text"]
 pub fn create_mapped_it_frame_cap(
     pd_cap: Capability,
     pptr: Paddr,
     vptr: Vaddr,
     asid: usize,
+    vm_rights: VmRights,
     executable: bool,
 ) -> Capability {
     let cap = Capability::cap_frame_cap_new(
-        asid,                       /* capFMappedASID    */
-        pptr.0,                     /* capFBasePtr       */
-        0,                          /* capFSize          */
-        VmRights::VMReadWrite as _, /* capFVMRights      */
-        false,                      /* capFIsDevice      */
-        vptr.0,                     /* capFMappedAddress */
+        asid,           /* capFMappedASID    */
+        pptr.0,         /* capFBasePtr       */
+        RISCV_4K_Page,  /* capFSize          */
+        vm_rights as _, /* capFVMRights      */
+        false,          /* capFIsDevice      */
+        vptr.0,         /* capFMappedAddress */
     );
 
-    map_it_frame_cap(pd_cap, cap);
+    map_it_frame_cap(pd_cap, cap, executable);
     cap
 }
 
 #[link_section = ".boot.text"]
 pub fn create_unmapped_it_frame_cap(pptr: Paddr) -> Capability {
     Capability::cap_frame_cap_new(
-        ASID_INVALID, /* capFMappedASID       */
-        pptr.0,       /* capFBasePtr          */
-        0,            /* capFSize             */
-        0,            /* capFVMRights         */
+        ASID_INVALID,               /* capFMappedASID       */
+        pptr.0,                     /* capFBasePtr          */
+        RISCV_4K_Page,              /* capFSize             */
+        VmRights::VMReadWrite as _, /* capFVMRights         */
         false,
         0, /* capFMappedAddress    */
     )
@@ -279,55 +598,603 @@ pub fn create_unmapped_it_frame_cap(pptr: Paddr) -> Capability {
 pub const asidHighBits: usize = 7;
 pub const asidLowBits: usize = 9;
 
+/// ASID pool对象：保存asid低位到vspace根页表物理地址的映射，全0即为空pool
+#[repr(C)]
+pub struct AsidPool {
+    pub array: [KCell<Paddr>; bit!(asidLowBits)],
+}
+
+/// 全局ASID表：asid高位到ASID pool的映射
+pub static riscvKSASIDTable: Mutex<[Option<KObj<AsidPool>>; bit!(asidHighBits)]> =
+    Mutex::new([None; bit!(asidHighBits)]);
+
 #[link_section = ".boot.text"]
 pub fn write_it_asid_pool(it_ap_cap: Capability, root_pt_cap: Capability) {
-    // asid_pool_t *ap = ASID_POOL_PTR(pptr_of_cap(it_ap_cap));
-    // ap->array[IT_ASID] = PTE_PTR(pptr_of_cap(root_pt_cap));
-    // riscvKSASIDTable[IT_ASID >> asidLowBits] = ap;
+    let ap = match KObj::<AsidPool>::from_cap(&it_ap_cap) {
+        Some(ap) => ap,
+        None => panic!("write_it_asid_pool: not an AsidPoolCap"),
+    };
+    ap.array[IT_ASID].set(root_pt_cap.get_pptr());
+    riscvKSASIDTable.lock()[IT_ASID >> asidLowBits] = Some(ap);
+}
 
-    // todo: write it asid pool
+/// 通过ASID表查找asid对应的vspace根页表
+pub fn find_vspace_for_asid(asid: usize) -> Option<KObj<PageTable>> {
+    let pool = riscvKSASIDTable.lock()[asid >> asidLowBits]?;
+    let vspace_root = pool.array[asid & mask!(asidLowBits)].get();
+    if vspace_root.0 == 0 {
+        return None;
+    }
+    /* pools only hold roots taken from page table caps by ASIDPoolAssign */
+    Some(unsafe { KObj::from_paddr(vspace_root, seL4_PageTableBits) })
+}
+
+/// 解析对ASID control cap的调用：MakePool，用一块untyped内存创建新的ASID pool
+pub fn decode_riscv_asid_control_invocation(
+    inv_label: usize,
+    length: usize,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    if inv_label != LABEL_RISCV_ASID_CONTROL_MAKE_POOL {
+        println!("RISCVASIDControl: Illegal operation.");
+        return SyscallError::with_type(seL4_IllegalOperation);
+    }
+
+    let extra_caps = CUR_EXTRA_CAPS.lock();
+    if length < 2 || extra_caps.len() < 2 {
+        println!("ASIDControlMakePool: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+    let index = get_syscall_arg(0, buffer);
+    let depth = get_syscall_arg(1, buffer);
+    let root_cap = extra_caps[1].cap();
+
+    /* find the first free ASID pool */
+    let asid_base = match riscvKSASIDTable
+        .lock()
+        .iter()
+        .position(|pool| pool.is_none())
+    {
+        Some(i) => i << asidLowBits,
+        None => {
+            println!("ASIDControlMakePool: No free pools found.");
+            return SyscallError::with_type(seL4_DeleteFirst);
+        }
+    };
+
+    let (frame, is_device, size_bits, free_index) = match extra_caps[0].cap().get_info() {
+        CapInfo::UntypedCap {
+            pptr,
+            is_device,
+            size_bits,
+            free_index,
+        } => (pptr, is_device, size_bits, free_index),
+        _ => {
+            println!("ASIDControlMakePool: Invalid untyped cap.");
+            return SyscallError::invalid_capability(1);
+        }
+    };
+    if size_bits != seL4_ASIDPoolBits || is_device {
+        println!("ASIDControlMakePool: Invalid untyped cap.");
+        return SyscallError::invalid_capability(1);
+    }
+    /* the whole untyped becomes the pool, so nothing may have been retyped from it */
+    if free_index != 0 {
+        println!("ASIDControlMakePool: Untyped has already been used.");
+        return SyscallError::with_type(seL4_RevokeFirst);
+    }
+
+    let dest_slot = match lookup_target_slot(root_cap, index, depth, 2) {
+        Ok(slot) => slot,
+        Err(error) => {
+            println!("ASIDControlMakePool: Failed to lookup destination slot.");
+            return error;
+        }
+    };
+    match dest_slot.cap().get_info() {
+        CapInfo::NullCap => {}
+        _ => {
+            println!("ASIDControlMakePool: Destination slot not empty.");
+            return SyscallError::with_type(seL4_DeleteFirst);
+        }
+    }
+
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    cur_thread.set_thread_state(ThreadState_Restart);
+
+    let untyped_slot = extra_caps[0];
+    untyped_slot.set_cap(Capability::cap_untyped_cap_new(
+        max_free_index!(size_bits),
+        is_device,
+        size_bits,
+        frame.0,
+    ));
+    clear_memory(frame, bit!(seL4_ASIDPoolBits));
+    cte_insert(
+        Capability::cap_asid_pool_cap_new(asid_base, frame.0),
+        untyped_slot,
+        dest_slot,
+    );
+    /* the pool was just cleared, which makes it empty */
+    riscvKSASIDTable.lock()[asid_base >> asidLowBits] =
+        Some(unsafe { KObj::from_paddr(frame, seL4_ASIDPoolBits) });
+    SyscallError::new()
+}
+
+/// 解析对ASID pool cap的调用：Assign，为一个vspace根页表分配asid
+pub fn decode_riscv_asid_pool_invocation(inv_label: usize, pool_cap: Capability) -> SyscallError {
+    if inv_label != LABEL_RISCV_ASID_POOL_ASSIGN {
+        println!("RISCVASIDPool: Illegal operation.");
+        return SyscallError::with_type(seL4_IllegalOperation);
+    }
+
+    let extra_caps = CUR_EXTRA_CAPS.lock();
+    if extra_caps.len() < 1 {
+        println!("ASIDPoolAssign: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+
+    let vspace_cap = extra_caps[0].cap();
+    let vspace_root = match vspace_cap.get_info() {
+        CapInfo::PageTableCap {
+            is_mapped: false, ..
+        } => KObj::<PageTable>::from_cap(&vspace_cap).unwrap(),
+        _ => {
+            println!("ASIDPoolAssign: Invalid vspace root.");
+            return SyscallError::invalid_capability(1);
+        }
+    };
+
+    let (asid_base, pool) = match (pool_cap.get_info(), KObj::<AsidPool>::from_cap(&pool_cap)) {
+        (CapInfo::AsidPoolCap { asid_base, .. }, Some(pool)) => (asid_base, pool),
+        _ => panic!("decode_riscv_asid_pool_invocation: not an AsidPoolCap"),
+    };
+    match riscvKSASIDTable.lock()[asid_base >> asidLowBits] {
+        Some(table_pool) if table_pool.ptr_eq(&pool) => {}
+        Some(_) => {
+            println!("ASIDPoolAssign: Failed to lookup pool.");
+            return SyscallError::invalid_capability(0);
+        }
+        None => {
+            println!("ASIDPoolAssign: Failed to lookup pool.");
+            return SyscallError::with_type(seL4_FailedLookup);
+        }
+    }
+
+    /* find a free ASID in the pool, asid 0 is reserved as ASID_INVALID */
+    let first = if asid_base == 0 { 1 } else { 0 };
+    let i = match (first..bit!(asidLowBits)).find(|&i| pool.array[i].get().0 == 0) {
+        Some(i) => i,
+        None => {
+            println!("ASIDPoolAssign: No free ASID.");
+            return SyscallError::with_type(seL4_DeleteFirst);
+        }
+    };
+
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    cur_thread.set_thread_state(ThreadState_Restart);
+
+    /* the new vspace root needs the kernel window before it can be activated */
+    copy_global_mappings(&vspace_root);
+    extra_caps[0].set_cap(Capability::cap_page_table_cap_new(
+        asid_base + i,
+        vspace_root.pptr().0,
+        true,
+        0,
+    ));
+    pool.array[i].set(vspace_root.pptr());
+    SyscallError::new()
+}
+
+fn unmap_page(page_size: usize, asid: usize, vptr: Vaddr, pptr: Paddr) {
+    let vspace_root = match find_vspace_for_asid(asid) {
+        Some(root) => root,
+        None => return,
+    };
+    let lu_ret = lookup_ptslot(&vspace_root, vptr);
+    if lu_ret.pt_bits_left != page_bits_for_size(page_size) {
+        return;
+    }
+    unsafe {
+        let pte = *lu_ret.pt_slot;
+        if !pte.is_valid() || pte.is_pte_pagetable() || pte.pa().0 != pptr.0 {
+            return;
+        }
+        *lu_ret.pt_slot = PTE(0);
+    }
+    flush_page(asid, vptr);
+}
+
+fn perform_page_map(frame_slot: SlotRef, cap: Capability, pte: PTE, pt_slot: *mut PTE) {
+    frame_slot.set_cap(cap);
+    unsafe { *pt_slot = pte };
+    if let CapInfo::FrameCap { vptr, asid, .. } = cap.get_info() {
+        flush_page(asid, vptr);
+    }
+}
+
+fn perform_page_unmap(frame_slot: SlotRef) {
+    if let CapInfo::FrameCap {
+        vptr,
+        pptr,
+        is_device,
+        size,
+        vm_rights,
+        asid,
+    } = frame_slot.cap().get_info()
+    {
+        if asid != ASID_INVALID {
+            unmap_page(size, asid, vptr, pptr);
+        }
+        frame_slot.set_cap(Capability::cap_frame_cap_new(
+            ASID_INVALID,
+            pptr.0,
+            size,
+            vm_rights,
+            is_device,
+            0,
+        ));
+    }
+}
+
+fn perform_page_get_address(pptr: Paddr, call: bool, buffer: Option<KObj<IPCBuffer>>) {
+    let thread = *ksCurThread.lock();
+    let t = thread.get().unwrap();
+    if call {
+        t.set_reg(Rv64Reg::a0, 0);
+        let length = set_mr(thread, buffer, 0, pptr.0);
+        t.set_reg(Rv64Reg::a1, MessageInfo::new(0, 0, 0, length).0);
+    }
+    t.set_thread_state(ThreadState_Running);
+}
+
+/// 解析对frame cap的调用：Map、Unmap、GetAddress
+pub fn decode_riscv_frame_invocation(
+    inv_label: usize,
+    length: usize,
+    frame_slot: SlotRef,
+    call: bool,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    let (frame_vptr, frame_pptr, is_device, frame_size, frame_rights, frame_asid) =
+        match frame_slot.cap().get_info() {
+            CapInfo::FrameCap {
+                vptr,
+                pptr,
+                is_device,
+                size,
+                vm_rights,
+                asid,
+            } => (vptr, pptr, is_device, size, vm_rights, asid),
+            _ => panic!("decode_riscv_frame_invocation: not a FrameCap"),
+        };
+
+    match inv_label {
+        LABEL_RISCV_PAGE_MAP => {
+            let extra_caps = CUR_EXTRA_CAPS.lock();
+            if length < 3 || extra_caps.len() < 1 {
+                println!("RISCVPageMap: Truncated message.");
+                return SyscallError::with_type(seL4_TruncatedMessage);
+            }
+            let lvl1pt_cap = extra_caps[0].cap();
+            drop(extra_caps);
+
+            let vaddr = get_syscall_arg(0, buffer);
+            let w_rights_mask = get_syscall_arg(1, buffer);
+            let attr = VMAttributes::from_bits_truncate(get_syscall_arg(2, buffer));
+
+            let (lvl1pt, asid) = match lvl1pt_cap.get_info() {
+                CapInfo::PageTableCap {
+                    pptr,
+                    asid,
+                    is_mapped: true,
+                    ..
+                } => (pptr, asid),
+                _ => {
+                    println!("RISCVPageMap: Bad PageTable cap.");
+                    return SyscallError::invalid_capability(1);
+                }
+            };
+
+            let page_bits = page_bits_for_size(frame_size);
+            if frame_asid != ASID_INVALID {
+                /* this frame is already mapped: only remapping at the same place is allowed */
+                if frame_asid != asid {
+                    println!("RISCVPageMap: Attempting to remap a frame that does not belong to the passed address space");
+                    return SyscallError::invalid_capability(1);
+                }
+                if frame_vptr.0 != vaddr {
+                    println!("RISCVPageMap: Attempting to map frame into multiple addresses");
+                    return SyscallError::invalid_argument(0);
+                }
+            } else {
+                let vtop = vaddr + bit!(page_bits) - 1;
+                if vtop >= USER_TOP {
+                    println!("RISCVPageMap: Mapping address too high.");
+                    return SyscallError::invalid_argument(0);
+                }
+            }
+
+            let vspace_root = match find_vspace_for_asid(asid) {
+                Some(vspace_root) if vspace_root.pptr().0 == lvl1pt.0 => vspace_root,
+                Some(_) => {
+                    println!("RISCVPageMap: ASID lookup failed");
+                    return SyscallError::invalid_capability(1);
+                }
+                None => {
+                    println!("RISCVPageMap: No PageTable for ASID");
+                    return SyscallError::with_type(seL4_FailedLookup);
+                }
+            };
+
+            if !is_aligned!(vaddr, page_bits) {
+                return SyscallError::with_type(seL4_AlignmentError);
+            }
+
+            let lu_ret = lookup_ptslot(&vspace_root, Vaddr(vaddr));
+            if lu_ret.pt_bits_left != page_bits {
+                println!("RISCVPageMap: No PageTable for this page {:#x?}", vaddr);
+                return SyscallError::with_type(seL4_FailedLookup);
+            }
+            if frame_asid == ASID_INVALID && unsafe { *lu_ret.pt_slot }.is_valid() {
+                println!("RISCVPageMap: Virtual address already mapped");
+                return SyscallError::with_type(seL4_DeleteFirst);
+            }
+
+            let vm_rights = mask_vm_rights(
+                VmRights::from(frame_rights),
+                CapRights::from_bits_truncate(w_rights_mask),
+            );
+            let executable = !attr.contains(VMAttributes::EXECUTE_NEVER);
+
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+
+            perform_page_map(
+                frame_slot,
+                Capability::cap_frame_cap_new(
+                    asid,
+                    frame_pptr.0,
+                    frame_size,
+                    frame_rights,
+                    is_device,
+                    vaddr,
+                ),
+                make_user_pte(frame_pptr, executable, vm_rights),
+                lu_ret.pt_slot,
+            );
+            SyscallError::new()
+        }
+        LABEL_RISCV_PAGE_UNMAP => {
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            perform_page_unmap(frame_slot);
+            SyscallError::new()
+        }
+        LABEL_RISCV_PAGE_GET_ADDRESS => {
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            perform_page_get_address(frame_pptr, call, buffer);
+            SyscallError::new()
+        }
+        _ => {
+            println!("RISCVPage: Illegal operation.");
+            SyscallError::with_type(seL4_IllegalOperation)
+        }
+    }
+}
+
+/// 在vspace中找到指向页表pt的页表项并将其清除
+fn unmap_page_table(asid: usize, vptr: Vaddr, pt: Paddr) {
+    let mut cur_pt = match find_vspace_for_asid(asid) {
+        Some(root) => root,
+        None => return,
+    };
+    let mut pt_slot: *mut PTE = core::ptr::null_mut();
+    let mut level = 0;
+    while level < CONFIG_PT_LEVELS - 1 && cur_pt.pptr().0 != pt.0 {
+        pt_slot = cur_pt.pte_ptr(vptr.pt_level_index(level));
+        unsafe {
+            if !(*pt_slot).is_pte_pagetable() {
+                return;
+            }
+            cur_pt = KObj::from_paddr((*pt_slot).pa(), seL4_PageTableBits);
+        }
+        level += 1;
+    }
+    if cur_pt.pptr().0 != pt.0 {
+        /* didn't find it */
+        return;
+    }
+    assert!(!pt_slot.is_null());
+    unsafe { *pt_slot = PTE(0) };
+    flush_asid(asid);
+}
+
+fn perform_page_table_map(pt_slot_cap: SlotRef, cap: Capability, pte: PTE, pt_slot: *mut PTE) {
+    pt_slot_cap.set_cap(cap);
+    unsafe { *pt_slot = pte };
+    if let CapInfo::PageTableCap { asid, .. } = cap.get_info() {
+        flush_asid(asid);
+    }
+}
+
+fn perform_page_table_unmap(pt_slot_cap: SlotRef) {
+    if let CapInfo::PageTableCap {
+        vptr,
+        pptr,
+        asid,
+        is_mapped: true,
+    } = pt_slot_cap.cap().get_info()
+    {
+        unmap_page_table(asid, vptr, pptr);
+        clear_memory(pptr, PAGE_SIZE);
+        pt_slot_cap.set_cap(Capability::cap_page_table_cap_new(
+            ASID_INVALID,
+            pptr.0,
+            false,
+            0,
+        ));
+    }
+}
+
+/// 解析对page table cap的调用：Map、Unmap
+pub fn decode_riscv_page_table_invocation(
+    inv_label: usize,
+    length: usize,
+    pt_slot_cap: SlotRef,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    let (pt_pptr, pt_asid, pt_is_mapped) = match pt_slot_cap.cap().get_info() {
+        CapInfo::PageTableCap {
+            pptr,
+            asid,
+            is_mapped,
+            ..
+        } => (pptr, asid, is_mapped),
+        _ => panic!("decode_riscv_page_table_invocation: not a PageTableCap"),
+    };
+
+    match inv_label {
+        LABEL_RISCV_PAGE_TABLE_MAP => {
+            let extra_caps = CUR_EXTRA_CAPS.lock();
+            if length < 2 || extra_caps.len() < 1 {
+                println!("RISCVPageTableMap: truncated message");
+                return SyscallError::with_type(seL4_TruncatedMessage);
+            }
+            let lvl1pt_cap = extra_caps[0].cap();
+            drop(extra_caps);
+
+            if pt_is_mapped {
+                println!("RISCVPageTable: PageTable is already mapped.");
+                return SyscallError::invalid_capability(0);
+            }
+
+            let vaddr = get_syscall_arg(0, buffer);
+            let (lvl1pt, asid) = match lvl1pt_cap.get_info() {
+                CapInfo::PageTableCap {
+                    pptr,
+                    asid,
+                    is_mapped: true,
+                    ..
+                } => (pptr, asid),
+                _ => {
+                    println!("RISCVPageTableMap: Invalid top-level PageTable.");
+                    return SyscallError::invalid_capability(1);
+                }
+            };
+
+            if vaddr >= USER_TOP {
+                println!("RISCVPageTableMap: Virtual address cannot be in kernel window.");
+                return SyscallError::invalid_argument(0);
+            }
+
+            let vspace_root = match find_vspace_for_asid(asid) {
+                Some(vspace_root) if vspace_root.pptr().0 == lvl1pt.0 => vspace_root,
+                Some(_) => {
+                    println!("RISCVPageTableMap: ASID lookup failed");
+                    return SyscallError::invalid_capability(1);
+                }
+                None => {
+                    println!("RISCVPageTableMap: ASID lookup failed");
+                    return SyscallError::with_type(seL4_FailedLookup);
+                }
+            };
+
+            let lu_ret = lookup_ptslot(&vspace_root, Vaddr(vaddr));
+            if lu_ret.pt_bits_left == seL4_PageBits || unsafe { *lu_ret.pt_slot }.is_valid() {
+                println!("RISCVPageTableMap: All objects mapped at this address");
+                return SyscallError::with_type(seL4_DeleteFirst);
+            }
+
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+
+            perform_page_table_map(
+                pt_slot_cap,
+                Capability::cap_page_table_cap_new(
+                    asid,
+                    pt_pptr.0,
+                    true,
+                    vaddr & !mask!(lu_ret.pt_bits_left),
+                ),
+                PTE::new(pt_pptr, PTEFlags::V),
+                lu_ret.pt_slot,
+            );
+            SyscallError::new()
+        }
+        LABEL_RISCV_PAGE_TABLE_UNMAP => {
+            if pt_is_mapped {
+                if let Some(vspace_root) = find_vspace_for_asid(pt_asid) {
+                    if vspace_root.pptr().0 == pt_pptr.0 {
+                        println!("RISCVPageTableUnmap: cannot call unmap on top level PageTable");
+                        return SyscallError::with_type(seL4_RevokeFirst);
+                    }
+                }
+            }
+
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            perform_page_table_unmap(pt_slot_cap);
+            SyscallError::new()
+        }
+        _ => {
+            println!("RISCVPageTable: Illegal Operation");
+            SyscallError::with_type(seL4_IllegalOperation)
+        }
+    }
 }
 
 pub fn set_vm_root(tcb: ThreadPointer) {
-    let thread_root_cap = tcb.get().unwrap().tcb_cte_slot(tcbVTable).cap;
-    match thread_root_cap.get_info() {
-        CapInfo::PageTableCap { pptr, asid, .. } => unsafe {
-            pptr.as_ref::<PageTable>().activate(asid)
-        },
-        _ => panic!("set_vm_root: thread_root_cap is not a PageTableCap"),
+    match thread_vspace_root(&tcb.get().unwrap()) {
+        Some((vspace_root, asid)) => vspace_root.activate(asid),
+        None => activate_kernel_vspace(),
     }
 }
 
-pub fn lookup_ipc_buffer(is_receiver: bool, tcb: &TCBInner) -> Paddr {
-    let buffer_vptr = tcb.tcb_ipc_buffer;
-    let buffer_cap = tcb.tcb_cte_slot(tcbBuffer).cap;
+/// 线程的根页表及其asid，vtable不是已经分配了asid的根页表时返回None
+pub fn thread_vspace_root(tcb: &KObj<Tcb>) -> Option<(KObj<PageTable>, usize)> {
+    let (lvl1pt, asid) = match tcb.tcb_cte_slot(tcbVTable).cap().get_info() {
+        CapInfo::PageTableCap {
+            pptr,
+            asid,
+            is_mapped: true,
+            ..
+        } => (pptr, asid),
+        _ => return None,
+    };
+    match find_vspace_for_asid(asid) {
+        Some(vspace_root) if vspace_root.pptr().0 == lvl1pt.0 => Some((vspace_root, asid)),
+        _ => None,
+    }
+}
+
+/// 线程的IPC buffer；buffer cap不是frame cap、是设备内存或权限不够时返回None
+///
+/// 接收方需要写入buffer，所以要求可读写，发送方只读即可
+pub fn lookup_ipc_buffer(is_receiver: bool, tcb: &KObj<Tcb>) -> Option<KObj<IPCBuffer>> {
+    let buffer_vptr = tcb.ipc_buffer();
+    let buffer_cap = tcb.tcb_cte_slot(tcbBuffer).cap();
 
-    match buffer_cap.get_info() {
+    let (pptr, size, vm_rights) = match buffer_cap.get_info() {
         CapInfo::FrameCap {
-            pptr, is_device, ..
-        } => {
-            assert!(!is_device);
-            Paddr(pptr.0 + (buffer_vptr.0 & mask!(seL4_PageBits)))
-        }
-        _ => panic!("lookup_ipc_buffer: not a FrameCap"),
-    }
-
-    // if (unlikely(cap_get_capType(bufferCap) != cap_frame_cap)) {
-    //     return NULL;
-    // }
-    // if (unlikely(cap_frame_cap_get_capFIsDevice(bufferCap))) {
-    //     return NULL;
-    // }
-
-    // vm_rights = cap_frame_cap_get_capFVMRights(bufferCap);
-    // if (likely(vm_rights == VMReadWrite ||
-    //            (!isReceiver && vm_rights == VMReadOnly))) {
-    //     word_t basePtr, pageBits;
-
-    //     basePtr = cap_frame_cap_get_capFBasePtr(bufferCap);
-    //     pageBits = pageBitsForSize(cap_frame_cap_get_capFSize(bufferCap));
-    //     return (word_t *)(basePtr + (w_bufferPtr & MASK(pageBits)));
-    // } else {
-    //     return NULL;
-    // }
+            pptr,
+            is_device: false,
+            size,
+            vm_rights,
+            ..
+        } => (pptr, size, VmRights::from(vm_rights)),
+        _ => return None,
+    };
+    if !(vm_rights == VmRights::VMReadWrite || (!is_receiver && vm_rights == VmRights::VMReadOnly))
+    {
+        return None;
+    }
+    let page_bits = page_bits_for_size(size);
+    let offset = buffer_vptr.0 & mask!(page_bits);
+    /* the whole buffer has to be inside the frame */
+    if offset + size_of::<IPCBuffer>() > bit!(page_bits) {
+        return None;
+    }
+    Some(unsafe { KObj::from_paddr(Paddr(pptr.0 + offset), seL4_IPCBufferSizeBits) })
 }
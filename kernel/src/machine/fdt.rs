@@ -0,0 +1,88 @@
+use sel4_common::fdt::{Fdt, FdtRegion};
+
+use crate::{
+    common::{MAX_NUM_AVAIL_REG, MAX_NUM_DT_RESV_REG},
+    kernel::array_vec::ArrayVec,
+};
+
+use super::{Paddr, Pregion};
+
+const PLIC_COMPATIBLE: [&[u8]; 2] = [b"riscv,plic0", b"sifive,plic-1.0.0"];
+const UART_COMPATIBLE: [&[u8]; 1] = [b"ns16550a"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlicInfo {
+    pub reg: Pregion,
+    pub ndev: usize,
+}
+
+/// 内核使用的平台设备
+#[derive(Clone, Copy, Debug)]
+pub struct PlatformDevices {
+    pub plic: Option<PlicInfo>,
+    pub uart: Option<Pregion>,
+    pub timebase_frequency: Option<usize>,
+}
+
+impl PlatformDevices {
+    pub const fn empty() -> Self {
+        Self {
+            plic: None,
+            uart: None,
+            timebase_frequency: None,
+        }
+    }
+}
+
+/// 从设备树中读出的物理内存、保留内存和平台设备
+#[derive(Debug)]
+pub struct DeviceTree {
+    pub memory: ArrayVec<Pregion, MAX_NUM_AVAIL_REG>,
+    pub reserved: ArrayVec<Pregion, MAX_NUM_DT_RESV_REG>,
+    pub devices: PlatformDevices,
+}
+
+fn pregion(reg: FdtRegion) -> Pregion {
+    Pregion::new(Paddr(reg.start), Paddr(reg.end))
+}
+
+/// 解析设备树，读取/memory、/reserved-memory、内存保留块以及PLIC、UART和timebase
+///
+/// 设备树格式不正确或区域太多时返回None
+pub fn parse(dtb: &[u8]) -> Option<DeviceTree> {
+    let fdt = Fdt::new(dtb)?;
+    let mut dt = DeviceTree {
+        memory: ArrayVec::new(),
+        reserved: ArrayVec::new(),
+        devices: PlatformDevices::empty(),
+    };
+    fdt.for_each_memory_region(
+        |reg| dt.memory.try_push(pregion(reg)).ok(),
+        |reg| dt.reserved.try_push(pregion(reg)).ok(),
+    )?;
+
+    let devices = &mut dt.devices;
+    fdt.walk(|nodes| {
+        let node = &nodes[nodes.len() - 1];
+        /* timebase-frequency is either in /cpus or in each /cpus/cpu@N */
+        if nodes.len() >= 2 && nodes[1].name() == b"cpus" {
+            if devices.timebase_frequency.is_none() {
+                devices.timebase_frequency = node.prop_cells(b"timebase-frequency")?;
+            }
+        } else if node.is_compatible(&PLIC_COMPATIBLE)? {
+            if devices.plic.is_none() {
+                let ndev = node.prop_u32(b"riscv,ndev")?.unwrap_or(0);
+                devices.plic = node.first_reg()?.map(|reg| PlicInfo {
+                    reg: pregion(reg),
+                    ndev,
+                });
+            }
+        } else if node.is_compatible(&UART_COMPATIBLE)? {
+            if devices.uart.is_none() {
+                devices.uart = node.first_reg()?.map(pregion);
+            }
+        }
+        Some(())
+    })?;
+    Some(dt)
+}
@@ -0,0 +1,89 @@
+use core::arch::{asm, global_asm};
+
+use crate::kernel::{
+    kobj::KObj,
+    statedata::ksActiveFPUState,
+    thread::{Tcb, ThreadPointer},
+};
+
+use super::registerset::{Rv64Reg, SSTATUS_FS, SSTATUS_FS_CLEAN, SSTATUS_FS_DIRTY, SSTATUS_FS_OFF};
+
+global_asm!(include_str!("fpu.S"));
+
+pub const RISCV_NUM_FP_REGS: usize = 32;
+
+/// 线程的浮点寄存器，不在寄存器中时保存在TCB里
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UserFpuState {
+    pub regs: [u64; RISCV_NUM_FP_REGS],
+    pub fcsr: u32,
+}
+
+impl UserFpuState {
+    pub const fn new() -> Self {
+        Self {
+            regs: [0; RISCV_NUM_FP_REGS],
+            fcsr: 0,
+        }
+    }
+}
+
+extern "C" {
+    fn __fpu_save(state: *mut UserFpuState);
+    fn __fpu_restore(state: *const UserFpuState);
+}
+
+/* the saved sstatus is restored on the way out, so the kernel can leave FS on */
+fn enable_fpu() {
+    unsafe { asm!("csrs sstatus, {}", in(reg) SSTATUS_FS_CLEAN) };
+}
+
+fn fpu_status(tcb: &KObj<Tcb>) -> usize {
+    tcb.reg(Rv64Reg::SSTATUS) & SSTATUS_FS
+}
+
+fn set_fpu_status(tcb: &KObj<Tcb>, fs: usize) {
+    tcb.set_reg(
+        Rv64Reg::SSTATUS,
+        tcb.reg(Rv64Reg::SSTATUS) & !SSTATUS_FS | fs,
+    );
+}
+
+/// 线程是否可以直接使用浮点寄存器，见seL4的isFpuEnable
+pub fn is_fpu_enabled(tcb: &KObj<Tcb>) -> bool {
+    fpu_status(tcb) != SSTATUS_FS_OFF
+}
+
+/// 把浮点寄存器交给new_owner，原来的线程修改过时先保存，见seL4的switchLocalFpuOwner
+///
+/// 其他线程的sstatus.FS都是Off，第一次使用浮点指令时会触发非法指令异常
+pub fn switch_local_fpu_owner(new_owner: ThreadPointer) {
+    enable_fpu();
+    let mut active = ksActiveFPUState.lock();
+    if let Some(old) = active.get() {
+        /* the state in the TCB is still valid when the registers are clean */
+        if fpu_status(&old) == SSTATUS_FS_DIRTY {
+            unsafe { __fpu_save(old.fpu_state_ptr()) };
+        }
+        set_fpu_status(&old, SSTATUS_FS_OFF);
+    }
+    if let Some(new) = new_owner.get() {
+        unsafe { __fpu_restore(new.fpu_state_ptr()) };
+        set_fpu_status(&new, SSTATUS_FS_CLEAN);
+    }
+    *active = new_owner;
+}
+
+/// 当前线程在sstatus.FS为Off时使用了浮点指令，见seL4的handleFPUFault
+pub fn handle_fpu_fault(tcb: &KObj<Tcb>) {
+    switch_local_fpu_owner(tcb.pointer());
+}
+
+/// 把线程的浮点状态写回TCB，之后可以直接读写TCB中的副本，见seL4的fpuRelease
+pub fn fpu_release(tcb: &KObj<Tcb>) {
+    let owner = *ksActiveFPUState.lock();
+    if owner.get().map_or(false, |owner| owner.ptr_eq(tcb)) {
+        switch_local_fpu_owner(ThreadPointer::null());
+    }
+}
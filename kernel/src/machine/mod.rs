@@ -1,11 +1,28 @@
+pub mod fdt;
+pub mod fpu;
 pub mod io;
 pub mod registerset;
 pub mod sbi;
 
-use core::fmt::{self, Debug, Formatter};
+use core::{
+    arch::asm,
+    fmt::{self, Debug, Formatter},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use sel4_common::constants::seL4_PageBits;
+use spin::Mutex;
 
-use crate::{common::PAGE_SIZE, is_aligned, kernel::vspace::PTE, mask};
+use crate::{
+    common::{
+        CONFIG_PT_LEVELS, KERNEL_ELF_BASE, PAGE_SIZE, PPTR_BASE, PPTR_BASE_OFFSET, PPTR_TOP,
+        PT_INDEX_BITS,
+    },
+    is_aligned,
+    kernel::vspace::PTE,
+    mask,
+};
+
+use self::fdt::PlatformDevices;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -15,9 +32,55 @@ pub struct Paddr(pub usize);
 #[derive(Clone, Copy)]
 pub struct Vaddr(pub usize);
 
+/// 物理地址在内核窗口中的虚拟地址
+pub fn paddr_to_pptr(pa: usize) -> usize {
+    pa + PPTR_BASE_OFFSET
+}
+
+/// 内核窗口中的虚拟地址对应的物理地址
+pub fn pptr_to_paddr(pptr: usize) -> usize {
+    assert!(
+        pptr >= PPTR_BASE && pptr < PPTR_TOP,
+        "{:#x} not in kernel window",
+        pptr
+    );
+    pptr - PPTR_BASE_OFFSET
+}
+
+/* physical address of KERNEL_ELF_BASE, wherever the elfloader put the kernel */
+static KERNEL_ELF_PADDR_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// 记录内核镜像的物理地址，必须在clear_bss之后、第一次调用kpptr_to_paddr之前设置
+pub fn set_kernel_elf_paddr_base(paddr: Paddr) {
+    KERNEL_ELF_PADDR_BASE.store(paddr.0, Ordering::Relaxed);
+}
+
+/// 内核镜像中的虚拟地址（全局变量、栈等）对应的物理地址
+pub fn kpptr_to_paddr(kpptr: usize) -> usize {
+    assert!(kpptr >= KERNEL_ELF_BASE, "{:#x} not in kernel image", kpptr);
+    kpptr - KERNEL_ELF_BASE + KERNEL_ELF_PADDR_BASE.load(Ordering::Relaxed)
+}
+
+/// 启动时从设备树中得到的平台设备
+pub static PLATFORM: Mutex<PlatformDevices> = Mutex::new(PlatformDevices::empty());
+
 // paddr
 
 impl Paddr {
+    /// 由内核可访问的指针（内核窗口或内核镜像中）得到物理地址
+    pub fn from_kernel_ptr<T>(ptr: *const T) -> Self {
+        let addr = ptr as usize;
+        if addr >= KERNEL_ELF_BASE {
+            Paddr(kpptr_to_paddr(addr))
+        } else {
+            Paddr(pptr_to_paddr(addr))
+        }
+    }
+
+    pub fn pptr(&self) -> usize {
+        paddr_to_pptr(self.0)
+    }
+
     pub fn get_page_bytes(&self) -> &'static [u8] {
         assert!(is_aligned!(self.0, seL4_PageBits));
         unsafe { core::slice::from_raw_parts(self.as_raw_ptr::<u8>(), PAGE_SIZE) }
@@ -34,23 +97,23 @@ impl Paddr {
     }
 
     pub fn to_va(&self, pv_offset: usize) -> Vaddr {
-        Vaddr(self.0 - pv_offset)
+        Vaddr(self.0.wrapping_sub(pv_offset))
     }
 
     pub fn as_raw_ptr<T>(&self) -> *const T {
-        self.0 as *const T
+        self.pptr() as *const T
     }
 
     pub fn as_raw_ptr_mut<T>(&self) -> *mut T {
-        self.0 as *mut T
+        self.pptr() as *mut T
     }
 
     pub unsafe fn as_ref<T>(&self) -> &'static T {
-        (self.0 as *const T).as_ref().unwrap()
+        self.as_raw_ptr::<T>().as_ref().unwrap()
     }
 
     pub unsafe fn as_mut<T>(&self) -> &'static mut T {
-        (self.0 as *mut T).as_mut().unwrap()
+        self.as_raw_ptr_mut::<T>().as_mut().unwrap()
     }
 }
 
@@ -58,10 +121,11 @@ impl Paddr {
 
 impl Vaddr {
     pub fn pt_level_index(&self, level: usize) -> usize {
-        (self.0 >> (seL4_PageBits + (2 - level) * 9)) & mask!(9)
+        (self.0 >> (seL4_PageBits + (CONFIG_PT_LEVELS - 1 - level) * PT_INDEX_BITS))
+            & mask!(PT_INDEX_BITS)
     }
     pub fn to_pa(&self, pv_offset: usize) -> Paddr {
-        Paddr(self.0 + pv_offset)
+        Paddr(self.0.wrapping_add(pv_offset))
     }
 }
 
@@ -125,7 +189,8 @@ impl Debug for Pregion {
 #[macro_export]
 macro_rules! get_level_pgbits {
     ($lvl: expr) => {
-        $crate::common::PT_INDEX_BITS * (2 - $lvl) + seL4_PageBits
+        $crate::common::PT_INDEX_BITS * ($crate::common::CONFIG_PT_LEVELS - 1 - $lvl)
+            + seL4_PageBits
     };
 }
 
@@ -141,3 +206,23 @@ pub fn clear_memory(pa: Paddr, len: usize) {
         core::slice::from_raw_parts_mut(pa.as_raw_ptr_mut::<u8>(), len).fill(0);
     }
 }
+
+/// 刷新全部地址空间的全部TLB项
+pub fn sfence() {
+    unsafe { asm!("sfence.vma") };
+}
+
+/// 刷新硬件asid对应地址空间的全部TLB项（全局映射除外）
+pub fn hw_asid_flush(hw_asid: usize) {
+    unsafe { asm!("sfence.vma x0, {}", in(reg) hw_asid) };
+}
+
+/// 刷新硬件asid对应地址空间中vaddr所在页面的TLB项
+pub fn hw_asid_flush_page(vaddr: Vaddr, hw_asid: usize) {
+    unsafe { asm!("sfence.vma {}, {}", in(reg) vaddr.0, in(reg) hw_asid) };
+}
+
+/// 刷新全部地址空间中vaddr所在页面的TLB项
+pub fn sfence_page(vaddr: Vaddr) {
+    unsafe { asm!("sfence.vma {}, x0", in(reg) vaddr.0) };
+}
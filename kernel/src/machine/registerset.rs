@@ -47,6 +47,52 @@ pub enum Rv64Reg {
 }
 
 pub const SSTATUS_SPIE: usize = 0x00000020;
+pub const SSTATUS_SPP: usize = 0x00000100;
+/* floating-point unit status, tracked per thread for lazy switching */
+pub const SSTATUS_FS: usize = 0x00006000;
+pub const SSTATUS_FS_OFF: usize = 0x00000000;
+pub const SSTATUS_FS_CLEAN: usize = 0x00004000;
+pub const SSTATUS_FS_DIRTY: usize = 0x00006000;
 pub const n_msgRegisters: usize = 4;
 pub const msg_registers: [Rv64Reg; n_msgRegisters] =
     [Rv64Reg::a2, Rv64Reg::a3, Rv64Reg::a4, Rv64Reg::a5];
+
+/* order of the registers in seL4_UserContext, used by TCB ReadRegisters/WriteRegisters */
+pub const n_frameRegisters: usize = 16;
+pub const frame_registers: [Rv64Reg; n_frameRegisters] = [
+    Rv64Reg::FaultIP,
+    Rv64Reg::ra,
+    Rv64Reg::sp,
+    Rv64Reg::gp,
+    Rv64Reg::s0,
+    Rv64Reg::s1,
+    Rv64Reg::s2,
+    Rv64Reg::s3,
+    Rv64Reg::s4,
+    Rv64Reg::s5,
+    Rv64Reg::s6,
+    Rv64Reg::s7,
+    Rv64Reg::s8,
+    Rv64Reg::s9,
+    Rv64Reg::s10,
+    Rv64Reg::s11,
+];
+pub const n_gpRegisters: usize = 16;
+pub const gp_registers: [Rv64Reg; n_gpRegisters] = [
+    Rv64Reg::a0,
+    Rv64Reg::a1,
+    Rv64Reg::a2,
+    Rv64Reg::a3,
+    Rv64Reg::a4,
+    Rv64Reg::a5,
+    Rv64Reg::a6,
+    Rv64Reg::a7,
+    Rv64Reg::t0,
+    Rv64Reg::t1,
+    Rv64Reg::t2,
+    Rv64Reg::t3,
+    Rv64Reg::t4,
+    Rv64Reg::t5,
+    Rv64Reg::t6,
+    Rv64Reg::tp,
+];
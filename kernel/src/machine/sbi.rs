@@ -11,6 +11,8 @@ const SBI_REMOTE_FENCE_I: usize = 5;
 const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
+const SBI_BASE_EXT: usize = 0x10;
+const BASE_PROBE_EXTENSION_FUNID: usize = 3;
 const SBI_HSM_EXT: usize = 0x48534d;
 const HSM_HART_START_FUNID: usize = 0;
 const HSM_HART_SUSPEND_FUNID: usize = 3;
@@ -56,6 +58,16 @@ pub fn sbi_send_ipi(mask: usize) {
     sbi_call(SBI_SEND_IPI, NONE, [mask, 0, 0]);
 }
 
+/// 固件是否实现了某个SBI扩展
+pub fn sbi_probe_extension(eid: usize) -> bool {
+    let (error, value) = sbi_call(SBI_BASE_EXT, BASE_PROBE_EXTENSION_FUNID, [eid, 0, 0]);
+    error == 0 && value != 0
+}
+
+pub fn sbi_hsm_available() -> bool {
+    sbi_probe_extension(SBI_HSM_EXT)
+}
+
 pub fn sbi_hart_start(hartid: usize, start_addr: usize, a1: usize) -> usize {
     sbi_call(SBI_HSM_EXT, HSM_HART_START_FUNID, [hartid, start_addr, a1]).0
 }
@@ -1,7 +1,6 @@
 #![no_std]
 #![no_main]
 #![feature(panic_info_message)]
-#![feature(alloc_error_handler)]
 
 mod drivers;
 mod kernel;
@@ -14,7 +13,6 @@ mod common;
 
 #[macro_use]
 extern crate bitflags;
-extern crate alloc;
 
 use core::arch::global_asm;
 
@@ -1,4 +1,4 @@
-use crate::kernel::structures::*;
+use crate::kernel::{kobj::SlotRef, structures::*};
 
 pub fn derive_cap(cap: Capability) -> Capability {
     // match cap.get_info() {
@@ -7,15 +7,15 @@ pub fn derive_cap(cap: Capability) -> Capability {
     cap
 }
 
-pub fn cte_insert(new_cap: Capability, src_slot: &CapSlot, dest_slot: &mut CapSlot) {
-    match dest_slot.cap.get_info() {
+pub fn cte_insert(new_cap: Capability, src_slot: SlotRef, dest_slot: SlotRef) {
+    match dest_slot.cap().get_info() {
         CapInfo::NullCap => {}
         _ => panic!("cte_insert: dest_slot not null"),
     }
-    assert!(dest_slot.mdb_node.is_empty());
+    assert!(dest_slot.mdb_node().is_empty());
 
     // todo: newcap mdb
     // todo: setUntypedCapAsFull
-    dest_slot.cap = new_cap;
+    dest_slot.set_cap(new_cap);
     // todo dest_slot.mdb
 }
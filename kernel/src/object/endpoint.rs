@@ -1,9 +1,270 @@
-use sel4_common::shared_types::MessageInfo;
+use sel4_common::{
+    constants::{seL4_EndpointBits, seL4_MsgMaxLength},
+    shared_types::{IPCBuffer, MessageInfo},
+    structures_common::{tcbCaller, tcbReply},
+};
 
-use crate::{kernel::thread::ThreadPointer, machine::registerset::Rv64Reg};
+use crate::{
+    kernel::{
+        kobj::{KCell, KObj, SlotRef},
+        structures::Capability,
+        thread::{
+            possible_switch_to, Tcb, TcbQueue, ThreadPointer, ThreadState,
+            ThreadState_BlockedOnReceive, ThreadState_BlockedOnReply, ThreadState_BlockedOnSend,
+            ThreadState_Inactive, ThreadState_Running,
+        },
+        vspace::lookup_ipc_buffer,
+    },
+    machine::registerset::{msg_registers, n_msgRegisters, Rv64Reg},
+    traps::syscalls::{
+        seL4_InvalidArgument, seL4_InvalidCapability, seL4_NotEnoughMemory, seL4_RangeError,
+        SyscallError,
+    },
+};
+
+use super::cnode::cte_insert;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EndpointState {
+    Idle,
+    Send,
+    Recv,
+}
+
+/// 端点对象，队列中的线程要么都在等待发送，要么都在等待接收
+///
+/// 全0就是空队列，所以retype清零后的内存就是一个空闲的端点
+#[repr(C)]
+pub struct Endpoint {
+    queue: KCell<TcbQueue>,
+}
+
+impl Endpoint {
+    pub fn new() -> Self {
+        Self {
+            queue: KCell::new(TcbQueue::empty()),
+        }
+    }
+
+    /// 端点的状态由队首线程的状态决定
+    pub fn state(&self) -> EndpointState {
+        match self.head() {
+            None => EndpointState::Idle,
+            Some(head) if head.state() == ThreadState_BlockedOnSend => EndpointState::Send,
+            Some(_) => EndpointState::Recv,
+        }
+    }
+
+    pub fn head(&self) -> Option<KObj<Tcb>> {
+        self.queue.get().head.get()
+    }
+
+    pub fn append(&self, tcb: &KObj<Tcb>) {
+        self.queue.set(self.queue.get().ep_append(tcb));
+    }
+
+    pub fn dequeue(&self, tcb: &KObj<Tcb>) {
+        self.queue.set(self.queue.get().ep_dequeue(tcb));
+    }
+}
+
+/// 通过端点发送消息，见seL4的sendIPC
+///
+/// 没有等待的接收者时，blocking为真则阻塞在端点上，否则直接返回
+pub fn send_ipc(
+    blocking: bool,
+    do_call: bool,
+    badge: usize,
+    can_grant: bool,
+    can_grant_reply: bool,
+    thread: &KObj<Tcb>,
+    ep: &KObj<Endpoint>,
+) {
+    match ep.state() {
+        EndpointState::Idle | EndpointState::Send => {
+            if blocking {
+                thread.set_full_thread_state(ThreadState {
+                    ts_type: ThreadState_BlockedOnSend,
+                    blocking_ipc_is_call: do_call,
+                    blocking_ipc_can_grant: can_grant,
+                    blocking_ipc_can_grant_reply: can_grant_reply,
+                    blocking_ipc_badge: badge,
+                    blocking_object: ep.pptr(),
+                });
+                ep.append(thread);
+            }
+        }
+        EndpointState::Recv => {
+            let dest = ep.head().unwrap();
+            ep.dequeue(&dest);
+            do_ipc_transfer(thread, badge, &dest);
+            let reply_can_grant = dest.thread_state().blocking_ipc_can_grant;
+            dest.set_thread_state(ThreadState_Running);
+            possible_switch_to(&dest);
+            if do_call {
+                if can_grant || can_grant_reply {
+                    setup_caller_cap(thread, &dest, reply_can_grant);
+                } else {
+                    thread.set_thread_state(ThreadState_Inactive);
+                }
+            }
+        }
+    }
+}
+
+/// 从端点接收消息，见seL4的receiveIPC
+///
+/// 调用前要先用delete_caller_cap清空接收者的tcbCaller slot
+pub fn receive_ipc(thread: &KObj<Tcb>, ep: &KObj<Endpoint>, blocking: bool, can_grant: bool) {
+    match ep.state() {
+        EndpointState::Idle | EndpointState::Recv => {
+            if blocking {
+                let mut state = ThreadState::new(ThreadState_BlockedOnReceive);
+                state.blocking_ipc_can_grant = can_grant;
+                state.blocking_object = ep.pptr();
+                thread.set_full_thread_state(state);
+                ep.append(thread);
+            } else {
+                /* seL4_NBRecv without a sender returns badge 0 */
+                thread.set_reg(Rv64Reg::a0, 0);
+            }
+        }
+        EndpointState::Send => {
+            let sender = ep.head().unwrap();
+            ep.dequeue(&sender);
+            let state = sender.thread_state();
+            do_ipc_transfer(&sender, state.blocking_ipc_badge, thread);
+            if state.blocking_ipc_is_call {
+                if state.blocking_ipc_can_grant || state.blocking_ipc_can_grant_reply {
+                    setup_caller_cap(&sender, thread, can_grant);
+                } else {
+                    sender.set_thread_state(ThreadState_Inactive);
+                }
+            } else {
+                sender.set_thread_state(ThreadState_Running);
+                possible_switch_to(&sender);
+            }
+        }
+    }
+}
+
+/// 把发送者的消息复制给接收者，a0为badge，a1为消息头，见seL4的doIPCTransfer
+///
+/// 不支持传递cap，消息头中的extra_caps和caps_unwrapped总为0
+pub fn do_ipc_transfer(sender: &KObj<Tcb>, badge: usize, receiver: &KObj<Tcb>) {
+    let info = MessageInfo(sender.reg(Rv64Reg::a1));
+    let length = info.length().min(seL4_MsgMaxLength);
+    let length = copy_mrs(sender, receiver, length);
+    receiver.set_reg(Rv64Reg::a1, MessageInfo::new(info.label(), 0, 0, length).0);
+    receiver.set_reg(Rv64Reg::a0, badge);
+}
+
+/// 复制length个消息寄存器，返回实际复制的个数；任何一方没有IPC buffer时只复制寄存器
+pub fn copy_mrs(sender: &KObj<Tcb>, receiver: &KObj<Tcb>, length: usize) -> usize {
+    for &reg in msg_registers.iter().take(length) {
+        receiver.set_reg(reg, sender.reg(reg));
+    }
+    if length <= n_msgRegisters {
+        return length;
+    }
+    match (
+        lookup_ipc_buffer(false, sender),
+        lookup_ipc_buffer(true, receiver),
+    ) {
+        (Some(send_buffer), Some(recv_buffer)) => {
+            for i in n_msgRegisters..length {
+                recv_buffer.set_msg(i, send_buffer.msg(i));
+            }
+            length
+        }
+        _ => n_msgRegisters,
+    }
+}
+
+/// 发送者等待回复，接收者的tcbCaller slot中放入指向发送者的reply cap，见seL4的setupCallerCap
+pub fn setup_caller_cap(sender: &KObj<Tcb>, receiver: &KObj<Tcb>, can_grant: bool) {
+    sender.set_thread_state(ThreadState_BlockedOnReply);
+    let reply_cap = Capability::cap_reply_cap_new(can_grant, false, sender.pptr().0);
+    cte_insert(
+        reply_cap,
+        sender.tcb_cte_slot(tcbReply),
+        receiver.tcb_cte_slot(tcbCaller),
+    );
+}
+
+/// 清空tcbCaller slot中的reply cap，见seL4的deleteCallerCap
+pub fn delete_caller_cap(receiver: &KObj<Tcb>) {
+    receiver
+        .tcb_cte_slot(tcbCaller)
+        .set_cap(Capability::new_empty());
+}
+
+/// 通过reply cap回复，并删除这个reply cap，见seL4的doReplyTransfer
+///
+/// 没有MDB，被停止的调用者的reply cap不会被撤销，所以这里要检查调用者仍在等待回复
+pub fn do_reply_transfer(sender: &KObj<Tcb>, receiver: &KObj<Tcb>, slot: SlotRef) {
+    slot.set_cap(Capability::new_empty());
+    if receiver.state() != ThreadState_BlockedOnReply {
+        return;
+    }
+    do_ipc_transfer(sender, 0, receiver);
+    receiver.set_thread_state(ThreadState_Running);
+    possible_switch_to(receiver);
+}
+
+/// 把阻塞在端点上的线程从端点队列中移除，见seL4的cancelIPC
+pub fn cancel_ipc(tcb: &KObj<Tcb>) {
+    let state = tcb.thread_state();
+    match state.ts_type {
+        ThreadState_BlockedOnSend | ThreadState_BlockedOnReceive => {
+            /* blocking_object was taken from an endpoint cap when the thread blocked */
+            let ep =
+                unsafe { KObj::<Endpoint>::from_paddr(state.blocking_object, seL4_EndpointBits) };
+            ep.dequeue(tcb);
+            tcb.set_thread_state(ThreadState_Inactive);
+        }
+        _ => {}
+    }
+}
 
 pub fn reply_from_kernel_susccess_empty(thread: ThreadPointer) {
     let t = thread.get().unwrap();
-    t.registers[Rv64Reg::a0 as usize] = 0;
-    t.registers[Rv64Reg::a1 as usize] = MessageInfo::new(0, 0, 0, 0).0;
+    t.set_reg(Rv64Reg::a0, 0);
+    t.set_reg(Rv64Reg::a1, MessageInfo::new(0, 0, 0, 0).0);
+}
+
+/// 将错误信息写回调用者：label为错误类型，消息寄存器中为错误的详细参数
+pub fn reply_from_kernel_error(thread: ThreadPointer, error: &SyscallError) {
+    let t = thread.get().unwrap();
+    let buffer = lookup_ipc_buffer(true, &t);
+    let len = match error.error_type {
+        seL4_InvalidArgument => set_mr(thread, buffer, 0, error.invalid_argument_number),
+        seL4_InvalidCapability => set_mr(thread, buffer, 0, error.invalid_cap_number),
+        seL4_RangeError => {
+            set_mr(thread, buffer, 0, error.range_error_min);
+            set_mr(thread, buffer, 1, error.range_error_max)
+        }
+        seL4_NotEnoughMemory => set_mr(thread, buffer, 0, error.memory_left),
+        _ => 0,
+    };
+    t.set_reg(Rv64Reg::a0, 0);
+    t.set_reg(Rv64Reg::a1, MessageInfo::new(error.error_type, 0, 0, len).0);
+}
+
+/// 设置第offset个消息寄存器，返回写入后的消息长度；没有IPC buffer时消息在寄存器处截断
+pub fn set_mr(
+    thread: ThreadPointer,
+    buffer: Option<KObj<IPCBuffer>>,
+    offset: usize,
+    reg: usize,
+) -> usize {
+    if offset < n_msgRegisters {
+        thread.get().unwrap().set_reg(msg_registers[offset], reg);
+    } else {
+        match buffer {
+            Some(buffer) => buffer.set_msg(offset, reg),
+            None => return n_msgRegisters,
+        }
+    }
+    offset + 1
 }
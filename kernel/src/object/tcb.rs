@@ -1,19 +1,369 @@
-use alloc::vec::Vec;
-use sel4_common::shared_types::{IPCBuffer, MessageInfo};
+use sel4_common::{
+    bit,
+    constants::{seL4_IPCBufferSizeBits, seL4_MsgMaxExtraCaps},
+    invocation::{
+        LABEL_TCB_CONFIGURE, LABEL_TCB_READ_REGISTERS, LABEL_TCB_RESUME, LABEL_TCB_SET_PRIORITY,
+        LABEL_TCB_SUSPEND, LABEL_TCB_WRITE_REGISTERS,
+    },
+    shared_types::{seL4_UserContextIntRegisters, seL4_UserContextWords, IPCBuffer, MessageInfo},
+    structures_common::{tcbBuffer, tcbCTable, tcbVTable},
+};
 use spin::{Lazy, Mutex};
 
-use crate::kernel::{cspace::lookup_slot, structures::CapSlot, thread::TCBInner};
+use crate::{
+    common::seL4_MinPrio,
+    is_aligned,
+    kernel::{
+        array_vec::ArrayVec,
+        cspace::lookup_slot,
+        kobj::{CNode, KObj, SlotRef},
+        statedata::ksCurThread,
+        structures::{CapInfo, Capability},
+        thread::{
+            possible_switch_to, reschedule_required, Tcb, ThreadState_Restart, ThreadState_Running,
+        },
+    },
+    machine::{
+        fpu::{fpu_release, UserFpuState},
+        registerset::{frame_registers, gp_registers, n_frameRegisters, n_gpRegisters, Rv64Reg},
+        Vaddr,
+    },
+    println,
+    traps::syscalls::{
+        get_syscall_arg, seL4_AlignmentError, seL4_DeleteFirst, seL4_IllegalOperation,
+        seL4_TruncatedMessage, SyscallError,
+    },
+};
 
-pub static CUR_EXTRA_CAPS: Lazy<Mutex<Vec<&'static mut CapSlot>>> =
-    Lazy::new(|| Mutex::new(Vec::new()));
+use super::{
+    cnode::{cte_insert, derive_cap},
+    endpoint::set_mr,
+};
 
-pub fn lookup_extra_caps(thread: &TCBInner, buffer: &IPCBuffer, info: MessageInfo) {
+/* first message word of ReadRegisters and WriteRegisters */
+const TCB_SUSPEND_SOURCE: usize = bit!(0);
+const TCB_RESUME_TARGET: usize = bit!(0);
+
+pub static CUR_EXTRA_CAPS: Lazy<Mutex<ArrayVec<SlotRef, seL4_MsgMaxExtraCaps>>> =
+    Lazy::new(|| Mutex::new(ArrayVec::new()));
+
+pub fn lookup_extra_caps(thread: &KObj<Tcb>, buffer: Option<KObj<IPCBuffer>>, info: MessageInfo) {
     let n_extra_caps = info.extra_caps();
     let mut cur_extra_caps = CUR_EXTRA_CAPS.lock();
     cur_extra_caps.clear();
+    /* extra caps are passed in the IPC buffer, without one there are none */
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => return,
+    };
     for i in 0..n_extra_caps {
-        let cptr = buffer.caps_or_badges[i];
+        let cptr = buffer.cap_or_badge(i);
         let slot = lookup_slot(thread, cptr);
         cur_extra_caps.push(slot);
     }
 }
+
+/// seL4_UserContext中的第i个字，浮点状态要先用fpu_release写回TCB
+fn read_context_word(tcb: &KObj<Tcb>, fpu: &UserFpuState, i: usize) -> usize {
+    if i < n_frameRegisters {
+        tcb.reg(frame_registers[i])
+    } else if i < n_frameRegisters + n_gpRegisters {
+        tcb.reg(gp_registers[i - n_frameRegisters])
+    } else if i < seL4_UserContextWords - 1 {
+        fpu.regs[i - seL4_UserContextIntRegisters] as usize
+    } else {
+        fpu.fcsr as usize
+    }
+}
+
+fn write_context_word(tcb: &KObj<Tcb>, fpu: &mut UserFpuState, i: usize, value: usize) {
+    if i < n_frameRegisters {
+        tcb.set_reg(frame_registers[i], value);
+    } else if i < n_frameRegisters + n_gpRegisters {
+        tcb.set_reg(gp_registers[i - n_frameRegisters], value);
+    } else if i < seL4_UserContextWords - 1 {
+        fpu.regs[i - seL4_UserContextIntRegisters] = value as u64;
+    } else {
+        fpu.fcsr = value as u32;
+    }
+}
+
+fn invoke_tcb_read_registers(
+    src: KObj<Tcb>,
+    suspend_source: bool,
+    count: usize,
+    call: bool,
+    buffer: Option<KObj<IPCBuffer>>,
+) {
+    let thread = *ksCurThread.lock();
+    if suspend_source {
+        src.suspend();
+    }
+    let t = thread.get().unwrap();
+    if call {
+        fpu_release(&src);
+        let fpu = src.fpu_state();
+        for i in 0..count {
+            set_mr(thread, buffer, i, read_context_word(&src, &fpu, i));
+        }
+        t.set_reg(Rv64Reg::a0, 0);
+        t.set_reg(Rv64Reg::a1, MessageInfo::new(0, 0, 0, count).0);
+    }
+    t.set_thread_state(ThreadState_Running);
+}
+
+fn invoke_tcb_write_registers(
+    dest: KObj<Tcb>,
+    resume_target: bool,
+    count: usize,
+    buffer: Option<KObj<IPCBuffer>>,
+) {
+    fpu_release(&dest);
+    let mut fpu = dest.fpu_state();
+    for i in 0..count {
+        write_context_word(&dest, &mut fpu, i, get_syscall_arg(i + 2, buffer));
+    }
+    dest.set_fpu_state(fpu);
+
+    /* continue at the pc that was just written */
+    let pc = dest.reg(Rv64Reg::FaultIP);
+    dest.set_reg(Rv64Reg::NextIP, pc);
+    if resume_target {
+        dest.restart();
+    }
+}
+
+fn decode_read_registers(
+    src: KObj<Tcb>,
+    length: usize,
+    call: bool,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    if length < 2 {
+        println!("TCB ReadRegisters: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+    let flags = get_syscall_arg(0, buffer);
+    let count = get_syscall_arg(1, buffer);
+
+    /* Check for illegal number of registers to read */
+    if count < 1 || count > seL4_UserContextWords {
+        println!(
+            "TCB ReadRegisters: Attempted to read an invalid number of registers ({}).",
+            count
+        );
+        return SyscallError::range_error(1, seL4_UserContextWords);
+    }
+
+    /* Check we're not reading our own registers, they are in the middle of the syscall */
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    if src.ptr_eq(&cur_thread) {
+        println!("TCB ReadRegisters: Attempted to read our own registers.");
+        return SyscallError::with_type(seL4_IllegalOperation);
+    }
+
+    cur_thread.set_thread_state(ThreadState_Restart);
+    invoke_tcb_read_registers(src, flags & TCB_SUSPEND_SOURCE != 0, count, call, buffer);
+    SyscallError::new()
+}
+
+fn decode_write_registers(
+    dest: KObj<Tcb>,
+    length: usize,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    if length < 2 {
+        println!("TCB WriteRegisters: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+    let flags = get_syscall_arg(0, buffer);
+    let count = get_syscall_arg(1, buffer);
+
+    if count > seL4_UserContextWords {
+        println!(
+            "TCB WriteRegisters: Attempted to write an invalid number of registers ({}).",
+            count
+        );
+        return SyscallError::range_error(0, seL4_UserContextWords);
+    }
+    if length - 2 < count {
+        println!(
+            "TCB WriteRegisters: Message too short for requested write size ({}/{}).",
+            length - 2,
+            count
+        );
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    if dest.ptr_eq(&cur_thread) {
+        println!("TCB WriteRegisters: Attempted to write our own registers.");
+        return SyscallError::with_type(seL4_IllegalOperation);
+    }
+
+    cur_thread.set_thread_state(ThreadState_Restart);
+    invoke_tcb_write_registers(dest, flags & TCB_RESUME_TARGET != 0, count, buffer);
+    SyscallError::new()
+}
+
+fn invoke_tcb_configure(
+    target: KObj<Tcb>,
+    cspace: (Capability, SlotRef),
+    vspace: (Capability, SlotRef),
+    buffer_addr: Vaddr,
+    buffer_frame: Option<(Capability, SlotRef)>,
+) {
+    cte_insert(cspace.0, cspace.1, target.tcb_cte_slot(tcbCTable));
+    cte_insert(vspace.0, vspace.1, target.tcb_cte_slot(tcbVTable));
+    target.set_ipc_buffer(buffer_addr);
+    if let Some((cap, slot)) = buffer_frame {
+        cte_insert(cap, slot, target.tcb_cte_slot(tcbBuffer));
+    }
+}
+
+/// 参数为IPC buffer的地址，extra caps依次为cspace、vspace和buffer所在的frame（可以为null cap）
+///
+/// 不能删除cap，所以目标线程原来的cspace、vspace和buffer slot必须为空
+fn decode_configure(
+    target: KObj<Tcb>,
+    length: usize,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    let extra_caps = CUR_EXTRA_CAPS.lock();
+    if length < 1 || extra_caps.len() < 3 {
+        println!("TCB Configure: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+    let (cspace_slot, vspace_slot, buffer_slot) = (extra_caps[0], extra_caps[1], extra_caps[2]);
+    drop(extra_caps);
+    let buffer_addr = get_syscall_arg(0, buffer);
+
+    let cspace_cap = derive_cap(cspace_slot.cap());
+    if KObj::<CNode>::from_cap(&cspace_cap).is_none() {
+        println!("TCB Configure: CSpace root is not a CNode.");
+        return SyscallError::invalid_capability(1);
+    }
+    let vspace_cap = derive_cap(vspace_slot.cap());
+    if !matches!(
+        vspace_cap.get_info(),
+        CapInfo::PageTableCap {
+            is_mapped: true,
+            ..
+        }
+    ) {
+        println!("TCB Configure: Invalid VSpace root.");
+        return SyscallError::invalid_capability(2);
+    }
+    let buffer_cap = derive_cap(buffer_slot.cap());
+    let buffer_frame = match buffer_cap.get_info() {
+        CapInfo::NullCap => None,
+        CapInfo::FrameCap {
+            is_device: false, ..
+        } => {
+            if !is_aligned!(buffer_addr, seL4_IPCBufferSizeBits) {
+                println!("TCB Configure: Misaligned IPC buffer {:#x}.", buffer_addr);
+                return SyscallError::with_type(seL4_AlignmentError);
+            }
+            Some((buffer_cap, buffer_slot))
+        }
+        _ => {
+            println!("TCB Configure: IPC buffer is not a normal frame.");
+            return SyscallError::invalid_capability(3);
+        }
+    };
+
+    for index in [tcbCTable, tcbVTable, tcbBuffer] {
+        match target.tcb_cte_slot(index).cap().get_info() {
+            CapInfo::NullCap => {}
+            _ => {
+                println!("TCB Configure: Slot {} of the target is in use.", index);
+                return SyscallError::with_type(seL4_DeleteFirst);
+            }
+        }
+    }
+
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    cur_thread.set_thread_state(ThreadState_Restart);
+    invoke_tcb_configure(
+        target,
+        (cspace_cap, cspace_slot),
+        (vspace_cap, vspace_slot),
+        Vaddr(buffer_addr),
+        buffer_frame,
+    );
+    SyscallError::new()
+}
+
+/// 见seL4的setPriority，就绪的线程按新优先级重新入队
+fn invoke_tcb_set_priority(target: KObj<Tcb>, prio: usize) {
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    target.sched_dequeue();
+    target.set_priority(prio);
+    if target.is_runnable() {
+        if target.ptr_eq(&cur_thread) {
+            reschedule_required();
+        } else {
+            possible_switch_to(&target);
+        }
+    }
+}
+
+/// 没有MCP，新的优先级不能高于调用者自己的优先级
+fn decode_set_priority(
+    target: KObj<Tcb>,
+    length: usize,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    if length < 1 {
+        println!("TCB SetPriority: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+    let prio = get_syscall_arg(0, buffer);
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    if prio > cur_thread.priority() {
+        println!(
+            "TCB SetPriority: Requested priority {} too high (max {}).",
+            prio,
+            cur_thread.priority()
+        );
+        return SyscallError::range_error(seL4_MinPrio, cur_thread.priority());
+    }
+
+    cur_thread.set_thread_state(ThreadState_Restart);
+    invoke_tcb_set_priority(target, prio);
+    SyscallError::new()
+}
+
+/// 解析对TCB cap的调用：ReadRegisters、WriteRegisters（寄存器之后是浮点状态）、
+/// Configure、SetPriority、Suspend和Resume
+pub fn decode_tcb_invocation(
+    inv_label: usize,
+    length: usize,
+    tcb_slot: SlotRef,
+    call: bool,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    let thread = KObj::<Tcb>::from_cap(&tcb_slot.cap()).unwrap();
+    match inv_label {
+        LABEL_TCB_READ_REGISTERS => decode_read_registers(thread, length, call, buffer),
+        LABEL_TCB_WRITE_REGISTERS => decode_write_registers(thread, length, buffer),
+        LABEL_TCB_CONFIGURE => decode_configure(thread, length, buffer),
+        LABEL_TCB_SET_PRIORITY => decode_set_priority(thread, length, buffer),
+        LABEL_TCB_SUSPEND => {
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            thread.suspend();
+            SyscallError::new()
+        }
+        LABEL_TCB_RESUME => {
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            thread.restart();
+            SyscallError::new()
+        }
+        _ => {
+            println!("TCB: Illegal operation.");
+            SyscallError::with_type(seL4_IllegalOperation)
+        }
+    }
+}
@@ -1,6 +1,272 @@
+use sel4_common::{
+    bit,
+    constants::{
+        seL4_EndpointBits, seL4_HugePageBits, seL4_LargePageBits, seL4_PageBits,
+        seL4_PageTableBits, seL4_TCBBits,
+    },
+    invocation::LABEL_UNTYPED_RETYPE,
+    round_up,
+    shared_types::IPCBuffer,
+    structures_common::{
+        seL4_CapTableObject, seL4_EndpointObject, seL4_NotificationObject, seL4_ObjectTypeCount,
+        seL4_RISCV_4K_Page, seL4_RISCV_Giga_Page, seL4_RISCV_Mega_Page, seL4_RISCV_PageTableObject,
+        seL4_TCBObject, seL4_UntypedObject,
+    },
+};
+
+use crate::{
+    common::{
+        seL4_MaxUntypedBits, seL4_MinUntypedBits, CONFIG_RETYPE_FAN_OUT_LIMIT, TCB_OFFSET,
+        TCB_SIZE_BITS, WORD_BITS,
+    },
+    kernel::{
+        cspace::lookup_target_slot,
+        kobj::{KObj, SlotRef},
+        statedata::ksCurThread,
+        structures::{CapInfo, Capability},
+        thread::{Tcb, ThreadState_Restart},
+        vspace::{RISCV_4K_Page, RISCV_Giga_Page, RISCV_Mega_Page, VmRights, ASID_INVALID},
+    },
+    machine::{clear_memory, Paddr},
+    println,
+    traps::syscalls::{
+        get_syscall_arg, seL4_DeleteFirst, seL4_FailedLookup, seL4_IllegalOperation,
+        seL4_TruncatedMessage, SyscallError,
+    },
+};
+
+use super::{cnode::cte_insert, endpoint::Endpoint, tcb::CUR_EXTRA_CAPS};
+
 #[macro_export]
 macro_rules! max_free_index {
     ($sizeBits: expr) => {
         bit!($sizeBits - $crate::common::seL4_MinUntypedBits)
     };
 }
+
+/// 计算object_type类型对象的大小（位数），user_obj_size只对大小可变的对象有意义
+fn get_object_size(object_type: usize, user_obj_size: usize) -> usize {
+    match object_type {
+        seL4_UntypedObject => user_obj_size,
+        seL4_TCBObject => seL4_TCBBits,
+        seL4_EndpointObject => seL4_EndpointBits,
+        seL4_RISCV_4K_Page => seL4_PageBits,
+        seL4_RISCV_Mega_Page => seL4_LargePageBits,
+        seL4_RISCV_Giga_Page => seL4_HugePageBits,
+        seL4_RISCV_PageTableObject => seL4_PageTableBits,
+        _ => user_obj_size,
+    }
+}
+
+fn is_frame_type(object_type: usize) -> bool {
+    matches!(
+        object_type,
+        seL4_RISCV_4K_Page | seL4_RISCV_Mega_Page | seL4_RISCV_Giga_Page
+    )
+}
+
+/// 在region_base处创建一个新对象并返回指向它的cap
+fn create_object(
+    object_type: usize,
+    region_base: Paddr,
+    user_size: usize,
+    device_mem: bool,
+) -> Capability {
+    let frame_size = match object_type {
+        seL4_RISCV_4K_Page => RISCV_4K_Page,
+        seL4_RISCV_Mega_Page => RISCV_Mega_Page,
+        seL4_RISCV_Giga_Page => RISCV_Giga_Page,
+        seL4_RISCV_PageTableObject => {
+            clear_memory(region_base, bit!(seL4_PageTableBits));
+            return Capability::cap_page_table_cap_new(ASID_INVALID, region_base.0, false, 0);
+        }
+        seL4_UntypedObject => {
+            return Capability::cap_untyped_cap_new(0, device_mem, user_size, region_base.0);
+        }
+        seL4_TCBObject => {
+            /* the cnode half of the block has to start out with null caps */
+            clear_memory(region_base, bit!(seL4_TCBBits));
+            let tcb = unsafe {
+                KObj::init(
+                    Paddr(region_base.0 + TCB_OFFSET),
+                    TCB_SIZE_BITS,
+                    Tcb::new_empty(),
+                )
+            };
+            tcb.init_context();
+            tcb.debug_append();
+            return Capability::cap_thread_cap_new(tcb.pptr().0);
+        }
+        seL4_EndpointObject => {
+            let ep = unsafe { KObj::init(region_base, seL4_EndpointBits, Endpoint::new()) };
+            return Capability::cap_endpoint_cap_new(0, true, true, true, true, ep.pptr().0);
+        }
+        _ => unimplemented!("create_object: object type {}", object_type),
+    };
+    if !device_mem {
+        clear_memory(region_base, bit!(get_object_size(object_type, user_size)));
+    }
+    Capability::cap_frame_cap_new(
+        ASID_INVALID,
+        region_base.0,
+        frame_size,
+        VmRights::VMReadWrite as _,
+        device_mem,
+        0,
+    )
+}
+
+/// 解析对untyped cap的调用：Retype
+pub fn decode_untyped_invocation(
+    inv_label: usize,
+    length: usize,
+    slot: SlotRef,
+    buffer: Option<KObj<IPCBuffer>>,
+) -> SyscallError {
+    if inv_label != LABEL_UNTYPED_RETYPE {
+        println!("Untyped cap: Illegal operation attempted.");
+        return SyscallError::with_type(seL4_IllegalOperation);
+    }
+
+    let extra_caps = CUR_EXTRA_CAPS.lock();
+    if length < 6 || extra_caps.len() < 1 {
+        println!("Untyped invocation: Truncated message.");
+        return SyscallError::with_type(seL4_TruncatedMessage);
+    }
+    let root_cap = extra_caps[0].cap();
+    drop(extra_caps);
+
+    let new_type = get_syscall_arg(0, buffer);
+    let user_obj_size = get_syscall_arg(1, buffer);
+    let node_index = get_syscall_arg(2, buffer);
+    let node_depth = get_syscall_arg(3, buffer);
+    let node_offset = get_syscall_arg(4, buffer);
+    let node_window = get_syscall_arg(5, buffer);
+
+    if new_type >= seL4_ObjectTypeCount {
+        println!("Untyped Retype: Invalid object type.");
+        return SyscallError::invalid_argument(0);
+    }
+
+    let object_size = get_object_size(new_type, user_obj_size);
+    if user_obj_size >= WORD_BITS || object_size > seL4_MaxUntypedBits {
+        println!("Untyped Retype: Invalid object size.");
+        return SyscallError::range_error(0, seL4_MaxUntypedBits);
+    }
+    if new_type == seL4_UntypedObject && user_obj_size < seL4_MinUntypedBits {
+        println!("Untyped Retype: Requested UntypedItem size too small.");
+        return SyscallError::invalid_argument(1);
+    }
+    if matches!(new_type, seL4_NotificationObject | seL4_CapTableObject) {
+        println!(
+            "Untyped Retype: object type {} not supported yet.",
+            new_type
+        );
+        return SyscallError::with_type(seL4_IllegalOperation);
+    }
+
+    /* lookup the destination cnode */
+    let node_cap = if node_depth == 0 {
+        root_cap
+    } else {
+        match lookup_target_slot(root_cap, node_index, node_depth, 1) {
+            Ok(slot) => slot.cap(),
+            Err(error) => {
+                println!("Untyped Retype: Failed to lookup destination node.");
+                return error;
+            }
+        }
+    };
+    let node_size = match node_cap.get_info() {
+        CapInfo::CnodeCap { radix, .. } => bit!(radix),
+        _ => {
+            println!("Untyped Retype: Destination cap invalid or read-only.");
+            return SyscallError::with_type(seL4_FailedLookup);
+        }
+    };
+
+    if node_offset > node_size - 1 {
+        println!(
+            "Untyped Retype: Destination node offset {} too large.",
+            node_offset
+        );
+        return SyscallError::range_error(0, node_size - 1);
+    }
+    if node_window < 1 || node_window > CONFIG_RETYPE_FAN_OUT_LIMIT {
+        println!(
+            "Untyped Retype: Number of requested objects ({}) too small or large.",
+            node_window
+        );
+        return SyscallError::range_error(1, CONFIG_RETYPE_FAN_OUT_LIMIT);
+    }
+    if node_window > node_size - node_offset {
+        println!("Untyped Retype: Requested destination window overruns size of node.");
+        return SyscallError::range_error(1, node_size - node_offset);
+    }
+
+    for i in node_offset..node_offset + node_window {
+        match node_cap.cnode_slot_at(i).cap().get_info() {
+            CapInfo::NullCap => {}
+            _ => {
+                println!(
+                    "Untyped Retype: Slot {:#x} in destination window non-empty.",
+                    i
+                );
+                return SyscallError::with_type(seL4_DeleteFirst);
+            }
+        }
+    }
+
+    let (ut_pptr, is_device, block_size, free_index) = match slot.cap().get_info() {
+        CapInfo::UntypedCap {
+            pptr,
+            is_device,
+            size_bits,
+            free_index,
+        } => (pptr, is_device, size_bits, free_index),
+        _ => panic!("decode_untyped_invocation: not an UntypedCap"),
+    };
+
+    /* objects are allocated from the free index, aligned to their own size */
+    let free_ref = ut_pptr.0 + (free_index << seL4_MinUntypedBits);
+    let untyped_free_bytes = bit!(block_size) - (free_index << seL4_MinUntypedBits);
+    if (untyped_free_bytes >> object_size) < node_window {
+        println!(
+            "Untyped Retype: Insufficient memory ({} * {} bytes needed, {} bytes available).",
+            node_window,
+            bit!(object_size),
+            untyped_free_bytes
+        );
+        return SyscallError::not_enough_memory(untyped_free_bytes);
+    }
+    /* the end of the untyped is aligned to object_size, so alignment never overruns it */
+    let aligned_free_ref = round_up!(free_ref, object_size);
+    let total_object_size = node_window << object_size;
+
+    if is_device && !is_frame_type(new_type) && new_type != seL4_UntypedObject {
+        println!("Untyped Retype: Creating kernel objects with device untyped");
+        return SyscallError::invalid_argument(1);
+    }
+
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    cur_thread.set_thread_state(ThreadState_Restart);
+
+    /* update the untyped's free index, then create the objects */
+    let new_free_index = (aligned_free_ref + total_object_size - ut_pptr.0) >> seL4_MinUntypedBits;
+    slot.set_cap(Capability::cap_untyped_cap_new(
+        new_free_index,
+        is_device,
+        block_size,
+        ut_pptr.0,
+    ));
+    for i in 0..node_window {
+        let new_cap = create_object(
+            new_type,
+            Paddr(aligned_free_ref + (i << object_size)),
+            user_obj_size,
+            is_device,
+        );
+        cte_insert(new_cap, slot, node_cap.cnode_slot_at(node_offset + i));
+    }
+    SyscallError::new()
+}
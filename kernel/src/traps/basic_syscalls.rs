@@ -3,21 +3,34 @@
 use sel4_common::{
     invocation::LABEL_CNODE_COPY,
     shared_types::{IPCBuffer, MessageInfo},
+    structures_common::tcbCaller,
     syscall_ids::*,
 };
 
 use crate::{
     kernel::{
-        cspace::lookup_slot,
+        cspace::{lookup_cap, lookup_slot},
+        kobj::{KObj, SlotRef},
         statedata::ksCurThread,
-        structures::{CapInfo, CapSlot, Capability},
-        thread::{activate_thread, schedule, ThreadState_Restart, ThreadState_Running},
-        vspace::lookup_ipc_buffer,
+        structures::{CapInfo, Capability},
+        thread::{
+            activate_thread, schedule, Tcb, ThreadState_Inactive, ThreadState_Restart,
+            ThreadState_Running,
+        },
+        vspace::{
+            decode_riscv_asid_control_invocation, decode_riscv_asid_pool_invocation,
+            decode_riscv_frame_invocation, decode_riscv_page_table_invocation, lookup_ipc_buffer,
+        },
     },
+    machine::registerset::n_msgRegisters,
     object::{
         cnode::{cte_insert, derive_cap},
-        endpoint::reply_from_kernel_susccess_empty,
-        tcb::{lookup_extra_caps, CUR_EXTRA_CAPS},
+        endpoint::{
+            delete_caller_cap, do_reply_transfer, receive_ipc, reply_from_kernel_error,
+            reply_from_kernel_susccess_empty, send_ipc, Endpoint,
+        },
+        tcb::{decode_tcb_invocation, lookup_extra_caps, CUR_EXTRA_CAPS},
+        untyped::decode_untyped_invocation,
     },
     println,
     traps::syscalls::{seL4_InvalidCapability, seL4_TruncatedMessage, SyscallError},
@@ -31,7 +44,7 @@ fn decode_cnode_invocation(
     inv_label: usize,
     length: usize,
     dest_cap: Capability,
-    buffer: &mut IPCBuffer,
+    buffer: Option<KObj<IPCBuffer>>,
 ) -> SyscallError {
     let mut ret = SyscallError::new();
 
@@ -42,7 +55,7 @@ fn decode_cnode_invocation(
     let src_index = get_syscall_arg(2, buffer);
     let src_depth = get_syscall_arg(3, buffer);
 
-    let src_root = CUR_EXTRA_CAPS.lock()[0].cap;
+    let src_root = CUR_EXTRA_CAPS.lock()[0].cap();
     let src_slot = src_root.cnode_slot_at(src_index);
 
     let new_cap: Capability;
@@ -56,7 +69,7 @@ fn decode_cnode_invocation(
                 return ret;
             }
             let cap_rights = get_syscall_arg(4, buffer);
-            let src_cap = src_slot.cap;
+            let src_cap = src_slot.cap();
             new_cap = derive_cap(src_cap);
             is_move = false;
         }
@@ -78,12 +91,12 @@ fn decode_invocation(
     inv_label: usize,
     length: usize,
     cap_index: usize,
-    slot: &CapSlot,
+    slot: SlotRef,
     is_blocking: bool,
     is_call: bool,
-    buffer: &mut IPCBuffer,
+    buffer: Option<KObj<IPCBuffer>>,
 ) -> SyscallError {
-    let cap = slot.cap;
+    let cap = slot.cap();
     let mut ret = SyscallError::new();
     match cap.get_info() {
         CapInfo::NullCap => {
@@ -94,6 +107,59 @@ fn decode_invocation(
         CapInfo::CnodeCap { .. } => {
             return decode_cnode_invocation(inv_label, length, cap, buffer);
         }
+        CapInfo::ThreadCap { .. } => {
+            return decode_tcb_invocation(inv_label, length, slot, is_call, buffer);
+        }
+        CapInfo::FrameCap { .. } => {
+            return decode_riscv_frame_invocation(inv_label, length, slot, is_call, buffer);
+        }
+        CapInfo::PageTableCap { .. } => {
+            return decode_riscv_page_table_invocation(inv_label, length, slot, buffer);
+        }
+        CapInfo::UntypedCap { .. } => {
+            return decode_untyped_invocation(inv_label, length, slot, buffer);
+        }
+        CapInfo::AsidControlCap => {
+            return decode_riscv_asid_control_invocation(inv_label, length, buffer);
+        }
+        CapInfo::AsidPoolCap { .. } => {
+            return decode_riscv_asid_pool_invocation(inv_label, cap);
+        }
+        CapInfo::EndpointCap {
+            badge,
+            can_send,
+            can_grant,
+            can_grant_reply,
+            ..
+        } => {
+            if !can_send {
+                println!(
+                    "Attempted to invoke a read-only endpoint cap {:#x?}",
+                    cap_index
+                );
+                return SyscallError::invalid_capability(0);
+            }
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            let ep = KObj::<Endpoint>::from_cap(&cap).unwrap();
+            send_ipc(
+                is_blocking,
+                is_call,
+                badge,
+                can_grant,
+                can_grant_reply,
+                &cur_thread,
+                &ep,
+            );
+            return ret;
+        }
+        CapInfo::ReplyCap { master: false, .. } => {
+            let cur_thread = ksCurThread.lock().get().unwrap();
+            cur_thread.set_thread_state(ThreadState_Restart);
+            let caller = KObj::<Tcb>::from_reply_cap(&cap).unwrap();
+            do_reply_transfer(&cur_thread, &caller, slot);
+            return ret;
+        }
         _ => todo!(),
     }
 }
@@ -106,15 +172,21 @@ fn handle_invocation(
     is_blocking: bool,
 ) {
     let cur_thread = ksCurThread.lock().get().unwrap();
-    let lu_ret = lookup_slot(cur_thread, cptr);
-    let buffer = unsafe { lookup_ipc_buffer(false, cur_thread).as_mut::<IPCBuffer>() };
+    let lu_ret = lookup_slot(&cur_thread, cptr);
+    let buffer = lookup_ipc_buffer(false, &cur_thread);
     let info = MessageInfo(msg_info);
 
-    lookup_extra_caps(cur_thread, buffer, info);
+    lookup_extra_caps(&cur_thread, buffer, info);
+
+    /* without an IPC buffer only the message registers can be read */
+    let mut length = info.length();
+    if buffer.is_none() && length > n_msgRegisters {
+        length = n_msgRegisters;
+    }
 
     let status = decode_invocation(
         info.label(),
-        info.length(),
+        length,
         cptr,
         lu_ret,
         is_blocking,
@@ -124,10 +196,15 @@ fn handle_invocation(
 
     match status.error_type {
         seL4_NoError => {}
-        _ => todo!(),
+        _ => {
+            if is_call {
+                reply_from_kernel_error(cur_thread.pointer(), &status);
+            }
+            return;
+        }
     }
 
-    if cur_thread.tcb_state.ts_type == ThreadState_Restart {
+    if cur_thread.state() == ThreadState_Restart {
         if is_call {
             reply_from_kernel_susccess_empty(cur_thread.pointer());
         }
@@ -135,9 +212,51 @@ fn handle_invocation(
     }
 }
 
+/// 从cptr处的端点接收，见seL4的handleRecv
+///
+/// 没有fault handler，cap无效时线程像出现了无法处理的fault一样停止
+fn handle_recv(cptr: usize, is_blocking: bool) {
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    let cap = lookup_cap(&cur_thread, cptr);
+    match cap.get_info() {
+        CapInfo::EndpointCap {
+            can_receive: true,
+            can_grant,
+            ..
+        } => {
+            delete_caller_cap(&cur_thread);
+            let ep = KObj::<Endpoint>::from_cap(&cap).unwrap();
+            receive_ipc(&cur_thread, &ep, is_blocking, can_grant);
+        }
+        _ => {
+            println!("Receive: invalid endpoint cap {:#x?}", cptr);
+            cur_thread.set_thread_state(ThreadState_Inactive);
+        }
+    }
+}
+
+/// 通过tcbCaller slot中的reply cap回复，见seL4的handleReply
+fn handle_reply() {
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    let caller_slot = cur_thread.tcb_cte_slot(tcbCaller);
+    match KObj::<Tcb>::from_reply_cap(&caller_slot.cap()) {
+        Some(caller) => do_reply_transfer(&cur_thread, &caller, caller_slot),
+        None => println!("Attempted reply operation when no reply cap present."),
+    }
+}
+
 pub fn handle_basic_syscall(cptr: usize, msg_info: usize, syscall: usize) {
     match syscall {
+        seL4_SysSend => handle_invocation(cptr, msg_info, syscall, false, true),
+        seL4_SysNBSend => handle_invocation(cptr, msg_info, syscall, false, false),
         seL4_SysCall => handle_invocation(cptr, msg_info, syscall, true, true),
+        seL4_SysRecv => handle_recv(cptr, true),
+        seL4_SysNBRecv => handle_recv(cptr, false),
+        seL4_SysReply => handle_reply(),
+        seL4_SysReplyRecv => {
+            handle_reply();
+            handle_recv(cptr, true);
+        }
         _ => todo!("handle_basic_syscall"),
     }
     schedule();
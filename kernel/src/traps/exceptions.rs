@@ -1,7 +1,48 @@
-use crate::println;
+use riscv::register::scause::{self, Exception, Trap};
+#[cfg(feature = "trace")]
+use sel4_common::trace::TRACE_PATH_EXCEPTION;
 
+use crate::{
+    kernel::{
+        kobj::kernel_lock,
+        statedata::ksCurThread,
+        thread::{activate_thread, schedule},
+    },
+    machine::{
+        fpu::{handle_fpu_fault, is_fpu_enabled},
+        registerset::Rv64Reg,
+    },
+    println,
+};
+
+#[cfg(feature = "trace")]
+use crate::kernel::trace::trace_kernel_entry;
+
+use super::restore_user_context;
+
+/// 用户线程触发异常，目前只处理浮点寄存器的延迟切换
 #[no_mangle]
-pub fn handle_exception() {
-    println!("exception 0");
-    loop {}
+pub fn handle_exception() -> ! {
+    let guard = kernel_lock();
+    #[cfg(feature = "trace")]
+    trace_kernel_entry(TRACE_PATH_EXCEPTION, scause::read().bits());
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    match scause::read().cause() {
+        /* the thread does not own the FPU yet, give it and retry the instruction */
+        Trap::Exception(Exception::IllegalInstruction) if !is_fpu_enabled(&cur_thread) => {
+            handle_fpu_fault(&cur_thread);
+        }
+        cause => {
+            println!(
+                "exception {:?} at {:#x?}",
+                cause,
+                cur_thread.reg(Rv64Reg::FaultIP)
+            );
+            loop {}
+        }
+    }
+    schedule();
+    activate_thread();
+    drop(guard);
+    restore_user_context();
 }
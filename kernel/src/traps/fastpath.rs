@@ -0,0 +1,159 @@
+use sel4_common::{
+    shared_types::MessageInfo,
+    structures_common::tcbCaller,
+    syscall_ids::{seL4_SysCall, seL4_SysReplyRecv},
+};
+
+use crate::{
+    kernel::{
+        cspace::lookup_cap,
+        kobj::KObj,
+        statedata::ksCurThread,
+        structures::{CapInfo, Capability},
+        thread::{
+            is_highest_prio, Tcb, ThreadState, ThreadState_BlockedOnReceive,
+            ThreadState_BlockedOnReply, ThreadState_Running,
+        },
+        vspace::{thread_vspace_root, PageTable},
+    },
+    machine::registerset::{msg_registers, n_msgRegisters, Rv64Reg},
+    object::endpoint::{Endpoint, EndpointState},
+};
+
+use super::syscalls::slowpath;
+
+/// 消息只在寄存器中且不传递cap，见seL4的fastpath_mi_check
+fn fastpath_mi_check(info: MessageInfo) -> bool {
+    info.length() <= n_msgRegisters && info.extra_caps() == 0
+}
+
+/// 消息都在寄存器中，不需要查找IPC buffer
+fn fastpath_copy_mrs(length: usize, src: &KObj<Tcb>, dest: &KObj<Tcb>) {
+    for &reg in msg_registers.iter().take(length) {
+        dest.set_reg(reg, src.reg(reg));
+    }
+}
+
+/// 直接切换到dest并写入badge和消息头，dest不经过就绪队列，见seL4的switchToThread_fp
+fn fastpath_switch(
+    dest: &KObj<Tcb>,
+    vspace_root: KObj<PageTable>,
+    asid: usize,
+    badge: usize,
+    info: MessageInfo,
+) {
+    vspace_root.activate(asid);
+    *ksCurThread.lock() = dest.pointer();
+    dest.set_reg(Rv64Reg::a0, badge);
+    dest.set_reg(
+        Rv64Reg::a1,
+        MessageInfo::new(info.label(), 0, 0, info.length()).0,
+    );
+}
+
+/// seL4_Call：端点上有接收者时直接切换过去，否则走slowpath，见seL4的fastpath_call
+pub fn fastpath_call(cptr: usize, msg_info: usize) {
+    let info = MessageInfo(msg_info);
+    if !fastpath_mi_check(info) {
+        return slowpath(cptr, msg_info, seL4_SysCall);
+    }
+    let cur_thread = ksCurThread.lock().get().unwrap();
+
+    let ep_cap = lookup_cap(&cur_thread, cptr);
+    let (badge, can_grant, can_grant_reply) = match ep_cap.get_info() {
+        CapInfo::EndpointCap {
+            badge,
+            can_send: true,
+            can_grant,
+            can_grant_reply,
+            ..
+        } => (badge, can_grant, can_grant_reply),
+        _ => return slowpath(cptr, msg_info, seL4_SysCall),
+    };
+    let ep = KObj::<Endpoint>::from_cap(&ep_cap).unwrap();
+    if ep.state() != EndpointState::Recv {
+        return slowpath(cptr, msg_info, seL4_SysCall);
+    }
+
+    let dest = ep.head().unwrap();
+    let (vspace_root, asid) = match thread_vspace_root(&dest) {
+        Some(root) => root,
+        None => return slowpath(cptr, msg_info, seL4_SysCall),
+    };
+    /* the receiver must be allowed to run now, as schedule would decide */
+    if dest.priority() < cur_thread.priority() && !is_highest_prio(dest.priority()) {
+        return slowpath(cptr, msg_info, seL4_SysCall);
+    }
+    /* without grant rights the caller gets no reply cap, see send_ipc */
+    if !can_grant && !can_grant_reply {
+        return slowpath(cptr, msg_info, seL4_SysCall);
+    }
+    /* there is no domain scheduler, so both threads are in domain 0 */
+
+    ep.dequeue(&dest);
+    cur_thread.set_state(ThreadState::new(ThreadState_BlockedOnReply));
+    /* the receiver emptied its caller slot when it started to wait */
+    let reply_can_grant = dest.thread_state().blocking_ipc_can_grant;
+    dest.tcb_cte_slot(tcbCaller)
+        .set_cap(Capability::cap_reply_cap_new(
+            reply_can_grant,
+            false,
+            cur_thread.pptr().0,
+        ));
+    fastpath_copy_mrs(info.length(), &cur_thread, &dest);
+    dest.set_state(ThreadState::new(ThreadState_Running));
+    fastpath_switch(&dest, vspace_root, asid, badge, info);
+}
+
+/// seL4_ReplyRecv：回复调用者并在端点上等待，直接切换到调用者，见seL4的fastpath_reply_recv
+pub fn fastpath_reply_recv(cptr: usize, msg_info: usize) {
+    let info = MessageInfo(msg_info);
+    if !fastpath_mi_check(info) {
+        return slowpath(cptr, msg_info, seL4_SysReplyRecv);
+    }
+    let cur_thread = ksCurThread.lock().get().unwrap();
+
+    let ep_cap = lookup_cap(&cur_thread, cptr);
+    let can_grant = match ep_cap.get_info() {
+        CapInfo::EndpointCap {
+            can_receive: true,
+            can_grant,
+            ..
+        } => can_grant,
+        _ => return slowpath(cptr, msg_info, seL4_SysReplyRecv),
+    };
+    let ep = KObj::<Endpoint>::from_cap(&ep_cap).unwrap();
+    /* a waiting sender would be received right away */
+    if ep.state() == EndpointState::Send {
+        return slowpath(cptr, msg_info, seL4_SysReplyRecv);
+    }
+
+    let caller_slot = cur_thread.tcb_cte_slot(tcbCaller);
+    let caller = match KObj::<Tcb>::from_reply_cap(&caller_slot.cap()) {
+        Some(caller) => caller,
+        None => return slowpath(cptr, msg_info, seL4_SysReplyRecv),
+    };
+    /* the reply cap of a caller that was suspended is left to the slowpath */
+    if caller.state() != ThreadState_BlockedOnReply {
+        return slowpath(cptr, msg_info, seL4_SysReplyRecv);
+    }
+    let (vspace_root, asid) = match thread_vspace_root(&caller) {
+        Some(root) => root,
+        None => return slowpath(cptr, msg_info, seL4_SysReplyRecv),
+    };
+    /* the current thread blocks, so only the ready threads compete with the caller */
+    if !is_highest_prio(caller.priority()) {
+        return slowpath(cptr, msg_info, seL4_SysReplyRecv);
+    }
+    /* there is no domain scheduler, so both threads are in domain 0 */
+
+    let mut state = ThreadState::new(ThreadState_BlockedOnReceive);
+    state.blocking_ipc_can_grant = can_grant;
+    state.blocking_object = ep.pptr();
+    cur_thread.set_state(state);
+    ep.append(&cur_thread);
+    caller_slot.set_cap(Capability::new_empty());
+    fastpath_copy_mrs(info.length(), &cur_thread, &caller);
+    caller.set_state(ThreadState::new(ThreadState_Running));
+    fastpath_switch(&caller, vspace_root, asid, 0, info);
+}
@@ -1,7 +1,46 @@
-use crate::println;
+use riscv::register::scause::{self, Interrupt, Trap};
+#[cfg(feature = "trace")]
+use sel4_common::trace::TRACE_PATH_INTERRUPT;
 
+use crate::{
+    drivers::{plic_complete_claim, plic_get_claim, plic_mask_irq},
+    kernel::{
+        kobj::kernel_lock,
+        thread::{activate_thread, schedule},
+    },
+    machine::sbi::set_timer,
+    println,
+};
+
+#[cfg(feature = "trace")]
+use crate::kernel::trace::trace_kernel_entry;
+
+use super::restore_user_context;
+
+/// 用户线程或空闲线程被中断，处理完后重新调度，见seL4的c_handle_interrupt
 #[no_mangle]
-pub fn handle_interrupt() {
-    println!("interrupt 0");
-    loop {}
+pub fn handle_interrupt() -> ! {
+    let guard = kernel_lock();
+    #[cfg(feature = "trace")]
+    trace_kernel_entry(TRACE_PATH_INTERRUPT, scause::read().bits());
+    match scause::read().cause() {
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            /* there is no timer driver yet, push the next tick out of reach */
+            set_timer(usize::MAX);
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => match plic_get_claim() {
+            0 => {}
+            irq => {
+                /* no IRQ handler caps yet, keep the source quiet */
+                println!("unhandled irq {}", irq);
+                plic_mask_irq(true, irq);
+                plic_complete_claim(irq);
+            }
+        },
+        cause => println!("spurious interrupt {:?}", cause),
+    }
+    schedule();
+    activate_thread();
+    drop(guard);
+    restore_user_context();
 }
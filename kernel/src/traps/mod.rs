@@ -1,19 +1,24 @@
 mod basic_syscalls;
 mod exceptions;
+mod fastpath;
 mod interrupts;
-mod syscalls;
+pub mod syscalls;
 mod unknown_syscalls;
 
 use core::arch::global_asm;
 
 use crate::kernel::statedata::ksCurThread;
+#[cfg(feature = "trace")]
+use crate::kernel::trace::trace_kernel_exit;
 
 global_asm!(include_str!("trap.S"));
 
 #[no_mangle]
 pub fn restore_user_context() -> ! {
+    #[cfg(feature = "trace")]
+    trace_kernel_exit();
     let cur_thread = ksCurThread.lock().get().unwrap();
-    let cur_thread_reg = (&cur_thread.registers) as *const _ as usize;
+    let cur_thread_reg = cur_thread.registers_ptr() as usize;
     extern "C" {
         fn __restore(_: usize);
     }
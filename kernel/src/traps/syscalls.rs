@@ -2,16 +2,29 @@
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
 
-use sel4_common::shared_types::IPCBuffer;
+#[cfg(feature = "trace")]
+use sel4_common::trace::TRACE_PATH_SYSCALL;
+use sel4_common::{
+    shared_types::IPCBuffer,
+    syscall_ids::{seL4_SysCall, seL4_SysReplyRecv},
+};
 
 use crate::{
-    kernel::statedata::ksCurThread,
+    kernel::{
+        kobj::{kernel_lock, KObj},
+        statedata::ksCurThread,
+    },
     machine::registerset::{msg_registers, n_msgRegisters},
     println,
 };
 
+#[cfg(feature = "trace")]
+use crate::kernel::trace::trace_kernel_entry;
+
 use super::{
-    basic_syscalls::handle_basic_syscall, restore_user_context,
+    basic_syscalls::handle_basic_syscall,
+    fastpath::{fastpath_call, fastpath_reply_recv},
+    restore_user_context,
     unknown_syscalls::handle_unknown_syscall,
 };
 
@@ -21,13 +34,20 @@ pub const BASIC_SYSCALL_MAX: isize = -1;
 #[no_mangle]
 pub fn handle_syscall(cptr: usize, msg_info: usize, syscall: usize) -> ! {
     // println!("syscall 0: {:#x?}, {:#x?}, {:#x?}", cptr, msg_info, syscall);
-    slowpath(cptr, msg_info, syscall);
+    let guard = kernel_lock();
+    #[cfg(feature = "trace")]
+    trace_kernel_entry(TRACE_PATH_SYSCALL, syscall);
+    match syscall {
+        seL4_SysCall => fastpath_call(cptr, msg_info),
+        seL4_SysReplyRecv => fastpath_reply_recv(cptr, msg_info),
+        _ => slowpath(cptr, msg_info, syscall),
+    }
+    drop(guard);
     restore_user_context();
 }
 
 pub fn slowpath(cptr: usize, msg_info: usize, syscall: usize) {
     if syscall as isize >= BASIC_SYSCALL_MIN && syscall as isize <= BASIC_SYSCALL_MAX {
-        println!("cptr = {:#x?}", cptr);
         handle_basic_syscall(cptr, msg_info, syscall);
     } else {
         handle_unknown_syscall(cptr, msg_info, syscall);
@@ -48,18 +68,62 @@ pub const seL4_NotEnoughMemory: usize = 10;
 
 pub struct SyscallError {
     pub error_type: usize,
+    pub invalid_argument_number: usize,
+    pub invalid_cap_number: usize,
+    pub range_error_min: usize,
+    pub range_error_max: usize,
+    pub memory_left: usize,
 }
 
 impl SyscallError {
     pub fn new() -> Self {
         Self {
             error_type: seL4_NoError,
+            invalid_argument_number: 0,
+            invalid_cap_number: 0,
+            range_error_min: 0,
+            range_error_max: 0,
+            memory_left: 0,
         }
     }
+
+    pub fn with_type(error_type: usize) -> Self {
+        let mut ret = Self::new();
+        ret.error_type = error_type;
+        ret
+    }
+
+    pub fn invalid_argument(n: usize) -> Self {
+        let mut ret = Self::with_type(seL4_InvalidArgument);
+        ret.invalid_argument_number = n;
+        ret
+    }
+
+    pub fn invalid_capability(n: usize) -> Self {
+        let mut ret = Self::with_type(seL4_InvalidCapability);
+        ret.invalid_cap_number = n;
+        ret
+    }
+
+    pub fn range_error(min: usize, max: usize) -> Self {
+        let mut ret = Self::with_type(seL4_RangeError);
+        ret.range_error_min = min;
+        ret.range_error_max = max;
+        ret
+    }
+
+    pub fn not_enough_memory(memory_left: usize) -> Self {
+        let mut ret = Self::with_type(seL4_NotEnoughMemory);
+        ret.memory_left = memory_left;
+        ret
+    }
 }
-pub fn get_syscall_arg(i: usize, ipc_buffer: &IPCBuffer) -> usize {
+pub fn get_syscall_arg(i: usize, ipc_buffer: Option<KObj<IPCBuffer>>) -> usize {
     if i < n_msgRegisters {
-        return ksCurThread.lock().get().unwrap().registers[msg_registers[i] as usize];
+        return ksCurThread.lock().get().unwrap().reg(msg_registers[i]);
     }
-    ipc_buffer.msg[i]
+    /* handle_invocation limits the length to the registers when there is no buffer */
+    ipc_buffer
+        .expect("syscall argument beyond the message registers without IPC buffer")
+        .msg(i)
 }
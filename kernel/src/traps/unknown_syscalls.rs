@@ -1,21 +1,44 @@
 use sel4_common::syscall_ids::*;
 
-use crate::{kernel::thread::THREAD_LIST, machine::sbi::console_putchar, println};
+use crate::{kernel::thread::debug_for_each_tcb, machine::sbi::console_putchar, println};
+#[cfg(feature = "trace")]
+use crate::{
+    kernel::{
+        cspace::lookup_slot,
+        statedata::ksCurThread,
+        trace::{finalize_log, reset_log, set_log_buffer},
+    },
+    machine::registerset::Rv64Reg,
+};
 
 fn debug_dump_scheduler() {
     println!("Dumping all tcbs!");
     println!("Name                                    \tState          \tIP                  \t Prio \t Core");
     println!("------------------------------------------------------------------------------------------------------");
-    let list = THREAD_LIST.lock();
-    for tcb in list.iter() {
-        println!("{}", tcb.get().unwrap());
-    }
+    debug_for_each_tcb(|tcb| println!("{}", *tcb));
+}
+
+/// 日志相关的系统调用，结果放在a0中返回
+#[cfg(feature = "trace")]
+fn handle_benchmark_syscall(cptr: usize, syscall: usize) {
+    let cur_thread = ksCurThread.lock().get().unwrap();
+    let ret = match syscall {
+        seL4_SysBenchmarkSetLogBuffer => set_log_buffer(lookup_slot(&cur_thread, cptr)),
+        seL4_SysBenchmarkResetLog => reset_log(),
+        seL4_SysBenchmarkFinalizeLog => finalize_log(),
+        _ => unreachable!(),
+    };
+    cur_thread.set_reg(Rv64Reg::a0, ret);
 }
 
 pub fn handle_unknown_syscall(cptr: usize, msg_info: usize, syscall: usize) {
     match syscall {
         seL4_SysDebugPutChar => console_putchar(cptr),
         seL4_SysDebugDumpScheduler => debug_dump_scheduler(),
+        #[cfg(feature = "trace")]
+        seL4_SysBenchmarkSetLogBuffer
+        | seL4_SysBenchmarkResetLog
+        | seL4_SysBenchmarkFinalizeLog => handle_benchmark_syscall(cptr, syscall),
         _ => todo!("unsupported unknown syscall {}", syscall as isize),
     }
 }
@@ -0,0 +1,208 @@
+use core::mem::size_of;
+
+/// "seL4BOOT"
+pub const BOOT_HANDOFF_MAGIC: u64 = 0x7365_4c34_424f_4f54;
+/// 每次改变BootHandoff的布局或含义时加一
+pub const BOOT_HANDOFF_VERSION: u32 = 7;
+pub const BOOT_HANDOFF_MAX_USER_IMAGES: usize = 16;
+pub const BOOT_HANDOFF_MAX_NODES: usize = 8;
+pub const IMAGE_NAME_LEN: usize = 32;
+pub const IMAGE_MAX_SEGMENTS: usize = 8;
+
+/* same values as the p_flags of an ELF program header */
+pub const SEGMENT_X: usize = 1;
+pub const SEGMENT_W: usize = 2;
+pub const SEGMENT_R: usize = 4;
+
+/// NUL填充的镜像名
+pub fn image_name(name: &[u8; IMAGE_NAME_LEN]) -> &str {
+    let len = name.iter().position(|&c| c == 0).unwrap_or(IMAGE_NAME_LEN);
+    core::str::from_utf8(&name[..len]).unwrap_or("")
+}
+
+/* the image digests were checked against the manifest in the archive */
+pub const MEASUREMENT_VERIFIED: usize = 1 << 0;
+/* the manifest signature was checked against the elfloader's public key */
+pub const MEASUREMENT_SIGNED: usize = 1 << 1;
+pub const MEASUREMENT_DIGEST_LEN: usize = 32;
+
+/// elfloader对启动镜像的度量：按加载顺序（内核、rootserver、其它镜像）
+/// 把每个镜像在归档中的SHA-256摘要依次连接后再做一次SHA-256
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BootMeasurement {
+    pub flags: usize,
+    pub digest: [u8; MEASUREMENT_DIGEST_LEN],
+}
+
+impl BootMeasurement {
+    pub const fn empty() -> Self {
+        Self {
+            flags: 0,
+            digest: [0; MEASUREMENT_DIGEST_LEN],
+        }
+    }
+}
+
+/// 镜像中一个PT_LOAD段的虚拟地址范围和访问权限
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ImageSegment {
+    pub virt_start: usize,
+    pub virt_end: usize,
+    /* SEGMENT_R | SEGMENT_W | SEGMENT_X */
+    pub flags: usize,
+}
+
+impl ImageSegment {
+    pub const fn empty() -> Self {
+        Self {
+            virt_start: 0,
+            virt_end: 0,
+            flags: 0,
+        }
+    }
+}
+
+/// 一个被elfloader加载到物理内存中的ELF镜像
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ImageRegion {
+    /* file name in the cpio archive, NUL padded */
+    pub name: [u8; IMAGE_NAME_LEN],
+    pub phys_start: usize,
+    pub phys_end: usize,
+    pub virt_start: usize,
+    pub virt_end: usize,
+    pub virt_entry: usize,
+    pub num_segments: usize,
+    pub segments: [ImageSegment; IMAGE_MAX_SEGMENTS],
+}
+
+impl ImageRegion {
+    pub const fn empty() -> Self {
+        Self {
+            name: [0; IMAGE_NAME_LEN],
+            phys_start: 0,
+            phys_end: 0,
+            virt_start: 0,
+            virt_end: 0,
+            virt_entry: 0,
+            num_segments: 0,
+            segments: [ImageSegment::empty(); IMAGE_MAX_SEGMENTS],
+        }
+    }
+
+    /// 镜像名，超过IMAGE_NAME_LEN的部分被截断
+    pub fn set_name(&mut self, name: &str) {
+        let len = name.len().min(IMAGE_NAME_LEN);
+        self.name = [0; IMAGE_NAME_LEN];
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
+    pub fn name(&self) -> &str {
+        image_name(&self.name)
+    }
+
+    pub fn segments(&self) -> &[ImageSegment] {
+        &self.segments[..self.num_segments.min(IMAGE_MAX_SEGMENTS)]
+    }
+
+    /// 物理地址 = 虚拟地址 + phys_virt_offset
+    pub fn phys_virt_offset(&self) -> usize {
+        self.phys_start.wrapping_sub(self.virt_start)
+    }
+}
+
+/// elfloader跳转到内核时传递的启动信息，a0中为它的物理地址
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BootHandoff {
+    pub magic: u64,
+    pub version: u32,
+    /* size_of::<BootHandoff>() as seen by the elfloader */
+    pub size: u32,
+    /* hart the elfloader ran on, it enters the kernel as core 0 */
+    pub hart_id: usize,
+    /* hart_ids[core_id] for every hart entering the kernel, including the boot hart */
+    pub num_nodes: usize,
+    pub hart_ids: [usize; BOOT_HANDOFF_MAX_NODES],
+    /* phys_start is only known at run time, the elfloader places the kernel and
+     * the user images wherever the memory map has room */
+    pub kernel: ImageRegion,
+    /* user_images[0] is the root server */
+    pub num_user_images: usize,
+    pub user_images: [ImageRegion; BOOT_HANDOFF_MAX_USER_IMAGES],
+    /* 0 if there is no device tree */
+    pub dtb_paddr: usize,
+    pub dtb_size: usize,
+    /* elfloader code that harts left out of num_nodes may still be running,
+     * the kernel must not hand it out as untyped memory */
+    pub park_paddr: usize,
+    pub park_size: usize,
+    pub measurement: BootMeasurement,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BootHandoffError {
+    BadMagic(u64),
+    VersionMismatch(u32),
+    SizeMismatch(u32),
+    BadUserImageCount(usize),
+    BadSegmentCount(usize),
+    BadNodeCount(usize),
+}
+
+impl BootHandoff {
+    pub const fn new() -> Self {
+        Self {
+            magic: BOOT_HANDOFF_MAGIC,
+            version: BOOT_HANDOFF_VERSION,
+            size: size_of::<BootHandoff>() as u32,
+            hart_id: 0,
+            num_nodes: 1,
+            hart_ids: [0; BOOT_HANDOFF_MAX_NODES],
+            kernel: ImageRegion::empty(),
+            num_user_images: 0,
+            user_images: [ImageRegion::empty(); BOOT_HANDOFF_MAX_USER_IMAGES],
+            dtb_paddr: 0,
+            dtb_size: 0,
+            park_paddr: 0,
+            park_size: 0,
+            measurement: BootMeasurement::empty(),
+        }
+    }
+
+    /// 检查magic和版本，以发现elfloader和内核的不匹配
+    pub fn validate(&self) -> Result<(), BootHandoffError> {
+        if self.magic != BOOT_HANDOFF_MAGIC {
+            return Err(BootHandoffError::BadMagic(self.magic));
+        }
+        if self.version != BOOT_HANDOFF_VERSION {
+            return Err(BootHandoffError::VersionMismatch(self.version));
+        }
+        if self.size as usize != size_of::<BootHandoff>() {
+            return Err(BootHandoffError::SizeMismatch(self.size));
+        }
+        if self.num_user_images == 0 || self.num_user_images > BOOT_HANDOFF_MAX_USER_IMAGES {
+            return Err(BootHandoffError::BadUserImageCount(self.num_user_images));
+        }
+        if self.num_nodes == 0 || self.num_nodes > BOOT_HANDOFF_MAX_NODES {
+            return Err(BootHandoffError::BadNodeCount(self.num_nodes));
+        }
+        for image in self.user_images() {
+            if image.num_segments > IMAGE_MAX_SEGMENTS {
+                return Err(BootHandoffError::BadSegmentCount(image.num_segments));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn hart_ids(&self) -> &[usize] {
+        &self.hart_ids[..self.num_nodes.min(BOOT_HANDOFF_MAX_NODES)]
+    }
+
+    pub fn user_images(&self) -> &[ImageRegion] {
+        &self.user_images[..self.num_user_images.min(BOOT_HANDOFF_MAX_USER_IMAGES)]
+    }
+}
@@ -1,17 +1,26 @@
 use crate::{constants::CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS, bit};
 use core::fmt::{Debug, Formatter, self};
 
+/// 与seL4的seL4_BootInfo布局一致
 #[repr(C)]
 pub struct BootInfo {
-    pub extra_len: usize,
-    pub node_id: usize,
-    pub num_nodes: usize,
-    pub num_io_pt_levels: usize,
-    pub ipc_buffer: usize,
-    pub empty: SlotRegion,
-    pub it_cnode_size_bits: usize,
-    pub untyped: SlotRegion,
-    pub untyped_list: [UntypedDesc; CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS],
+    pub extra_len: usize,                 /* length of any additional bootinfo information */
+    pub node_id: usize,                   /* ID [0..numNodes-1] of the seL4 node (0 if uniprocessor) */
+    pub num_nodes: usize,                 /* number of seL4 nodes (1 if uniprocessor) */
+    pub num_io_pt_levels: usize,          /* number of IOMMU PT levels (0 if no IOMMU support) */
+    pub ipc_buffer: usize,                /* pointer to initial thread's IPC buffer */
+    pub empty: SlotRegion,                /* empty slots (null caps) */
+    pub shared_frames: SlotRegion,        /* shared-frame caps (shared between seL4 nodes) */
+    pub user_image_frames: SlotRegion,    /* userland-image frame caps */
+    pub user_image_paging: SlotRegion,    /* userland-image paging structure caps */
+    pub io_space_caps: SlotRegion,        /* IOSpace caps for ARM SMMU */
+    pub extra_bi_pages: SlotRegion,       /* caps for any pages used to back the additional bootinfo information */
+    pub it_cnode_size_bits: usize,        /* initial thread's root CNode size (2^n slots) */
+    pub init_thread_domain: usize,        /* Initial thread's domain ID */
+    #[cfg(feature = "kernel_mcs")]
+    pub schedcontrol: SlotRegion,         /* Caps to sched_control for each node */
+    pub untyped: SlotRegion,              /* untyped-object caps (untyped caps) */
+    pub untyped_list: [UntypedDesc; CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS], /* information about each untyped */
 }
 
 #[repr(C)]
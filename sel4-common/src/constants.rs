@@ -1,9 +1,13 @@
 pub const seL4_PageBits: usize = 12;
+pub const seL4_LargePageBits: usize = 21;
+pub const seL4_HugePageBits: usize = 30;
 pub const seL4_SlotBits: usize = 5;
-pub const seL4_TCBBits: usize = 10;
+pub const seL4_TCBBits: usize = 11;
+pub const seL4_EndpointBits: usize = 4;
 pub const seL4_ASIDPoolBits: usize = 12;
 pub const seL4_VSpaceBits: usize = 12;
 pub const seL4_PageTableBits: usize = 12;
+pub const seL4_IPCBufferSizeBits: usize = 10;
 pub const BI_FRAME_SIZE_BITS: usize = seL4_PageBits;
 
 pub const CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS: usize = 230;
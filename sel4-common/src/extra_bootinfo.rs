@@ -0,0 +1,122 @@
+use core::mem::size_of;
+
+use crate::{
+    boot_handoff::{image_name, BootMeasurement, IMAGE_NAME_LEN},
+    bootinfo_common::{SlotRegion, UntypedDesc},
+};
+
+/* ids of the extra bootinfo chunks, see seL4/bootinfo_types.h */
+pub const SEL4_BOOTINFO_HEADER_PADDING: usize = 0;
+pub const SEL4_BOOTINFO_HEADER_X86_VBE: usize = 1;
+pub const SEL4_BOOTINFO_HEADER_X86_MBMMAP: usize = 2;
+pub const SEL4_BOOTINFO_HEADER_X86_ACPI_RSDP: usize = 3;
+pub const SEL4_BOOTINFO_HEADER_X86_FRAMEBUFFER: usize = 4;
+pub const SEL4_BOOTINFO_HEADER_X86_TSC_FREQ: usize = 5;
+pub const SEL4_BOOTINFO_HEADER_FDT: usize = 6;
+pub const SEL4_BOOTINFO_HEADER_NUM: usize = 7;
+/* not in upstream seL4, kept well away from its ids: untyped descriptors for
+ * the untyped caps after the first CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS */
+pub const SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW: usize = 0x100;
+/* not in upstream seL4: user images loaded besides the root server */
+pub const SEL4_BOOTINFO_HEADER_USER_IMAGES: usize = 0x101;
+/* not in upstream seL4: the elfloader's measurement of the boot images */
+pub const SEL4_BOOTINFO_HEADER_MEASUREMENT: usize = 0x102;
+
+/// extra bootinfo中每个chunk的头部，len包含头部本身
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BootInfoHeader {
+    pub id: usize,
+    pub len: usize,
+}
+
+pub const BOOTINFO_HEADER_SIZE: usize = size_of::<BootInfoHeader>();
+
+/// rootserver之外的一个用户镜像，frames为按物理地址排列的未映射页面cap
+#[repr(C)]
+pub struct UserImageDesc {
+    pub name: [u8; IMAGE_NAME_LEN],
+    pub frames: SlotRegion,
+    pub virt_start: usize,
+    pub virt_end: usize,
+    pub virt_entry: usize,
+}
+
+impl UserImageDesc {
+    pub fn name(&self) -> &str {
+        image_name(&self.name)
+    }
+}
+
+/// extra bootinfo中的一个chunk，content不包含头部
+#[derive(Clone, Copy, Debug)]
+pub struct ExtraBootInfoChunk<'a> {
+    pub id: usize,
+    pub content: &'a [u8],
+}
+
+impl<'a> ExtraBootInfoChunk<'a> {
+    /// SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW chunk中的untyped描述符
+    ///
+    /// 内核把这个chunk放在extra bootinfo的开头，所以content是对齐的
+    pub fn untyped_list(&self) -> &'a [UntypedDesc] {
+        assert_eq!(self.id, SEL4_BOOTINFO_HEADER_UNTYPED_OVERFLOW);
+        unsafe { self.content_as_slice() }
+    }
+
+    /// SEL4_BOOTINFO_HEADER_USER_IMAGES chunk中的用户镜像描述符
+    ///
+    /// 内核把这个chunk紧接在untyped溢出chunk之后，所以content是对齐的
+    pub fn user_images(&self) -> &'a [UserImageDesc] {
+        assert_eq!(self.id, SEL4_BOOTINFO_HEADER_USER_IMAGES);
+        unsafe { self.content_as_slice() }
+    }
+
+    /// SEL4_BOOTINFO_HEADER_MEASUREMENT chunk中的度量值
+    pub fn measurement(&self) -> Option<BootMeasurement> {
+        assert_eq!(self.id, SEL4_BOOTINFO_HEADER_MEASUREMENT);
+        if self.content.len() < size_of::<BootMeasurement>() {
+            return None;
+        }
+        Some(unsafe { (self.content.as_ptr() as *const BootMeasurement).read_unaligned() })
+    }
+
+    unsafe fn content_as_slice<T>(&self) -> &'a [T] {
+        core::slice::from_raw_parts(
+            self.content.as_ptr() as *const T,
+            self.content.len() / size_of::<T>(),
+        )
+    }
+}
+
+/// 按顺序遍历extra bootinfo中的chunk，遇到不合法的头部时停止
+pub struct ExtraBootInfoChunks<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ExtraBootInfoChunks<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for ExtraBootInfoChunks<'a> {
+    type Item = ExtraBootInfoChunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < BOOTINFO_HEADER_SIZE {
+            return None;
+        }
+        let header = unsafe { (self.data.as_ptr() as *const BootInfoHeader).read_unaligned() };
+        if header.len < BOOTINFO_HEADER_SIZE || header.len > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+        let content = &self.data[BOOTINFO_HEADER_SIZE..header.len];
+        self.data = &self.data[header.len..];
+        Some(ExtraBootInfoChunk {
+            id: header.id,
+            content,
+        })
+    }
+}
@@ -0,0 +1,302 @@
+/* flattened device tree parsing shared by the kernel and the elfloader, it has
+ * no dependencies so both can use it before any allocator or page table */
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+/* struct block layout is only stable since version 16 */
+const FDT_MIN_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/* nesting of the nodes we care about: / -> reserved-memory -> child */
+const FDT_MAX_DEPTH: usize = 8;
+
+/* default #address-cells and #size-cells, see devicetree spec 2.3.5 */
+const FDT_DEFAULT_ADDR_CELLS: usize = 2;
+const FDT_DEFAULT_SIZE_CELLS: usize = 1;
+
+/// 设备树中的一段物理地址[start, end)
+#[derive(Clone, Copy, Debug)]
+pub struct FdtRegion {
+    pub start: usize,
+    pub end: usize,
+}
+
+fn be32(buf: &[u8], off: usize) -> Option<u32> {
+    let b = buf.get(off..off.checked_add(4)?)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(buf: &[u8], off: usize) -> Option<u64> {
+    Some((be32(buf, off)? as u64) << 32 | be32(buf, off.checked_add(4)?)? as u64)
+}
+
+/// 读取cells个32位大端数拼成的数，长度不是cells * 4时返回None
+pub fn read_cells(buf: &[u8], cells: usize) -> Option<usize> {
+    if buf.len() != cells * 4 {
+        return None;
+    }
+    Some(buf.chunks_exact(4).fold(0, |acc, c| {
+        acc << 32 | u32::from_be_bytes([c[0], c[1], c[2], c[3]]) as usize
+    }))
+}
+
+/// 以0结尾的字符串（不含0）
+fn cstr(buf: &[u8], off: usize) -> Option<&[u8]> {
+    let s = buf.get(off..)?;
+    Some(&s[..s.iter().position(|&c| c == 0)?])
+}
+
+fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+/// 检查过头部的设备树
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    dtb: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    off_mem_rsvmap: usize,
+}
+
+/// 遍历结构块时的一个节点，属性都在子节点之前
+#[derive(Clone, Copy)]
+pub struct FdtNode<'a> {
+    name: &'a [u8],
+    /* the properties, up to the first child or the end of the node */
+    props: &'a [u8],
+    strings: &'a [u8],
+    /* cells of this node's reg, inherited from the parent */
+    addr_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> Fdt<'a> {
+    /// 检查设备树的头部，格式不正确时返回None
+    pub fn new(dtb: &'a [u8]) -> Option<Self> {
+        if be32(dtb, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let dtb = dtb.get(..be32(dtb, 4)? as usize)?;
+        if be32(dtb, 20)? < FDT_MIN_VERSION {
+            return None;
+        }
+        let off_dt_struct = be32(dtb, 8)? as usize;
+        let off_dt_strings = be32(dtb, 12)? as usize;
+        let strings = dtb.get(off_dt_strings..off_dt_strings + be32(dtb, 32)? as usize)?;
+        let structs = dtb.get(off_dt_struct..off_dt_struct + be32(dtb, 36)? as usize)?;
+        Some(Self {
+            dtb,
+            structs,
+            strings,
+            off_mem_rsvmap: be32(dtb, 16)? as usize,
+        })
+    }
+
+    /// 对内存保留块中的每一项调用f
+    pub fn for_each_mem_rsv(&self, mut f: impl FnMut(FdtRegion) -> Option<()>) -> Option<()> {
+        /* terminated by an all-zero entry */
+        let mut off = self.off_mem_rsvmap;
+        loop {
+            let address = be64(self.dtb, off)? as usize;
+            let size = be64(self.dtb, off + 8)? as usize;
+            off += 16;
+            if address == 0 && size == 0 {
+                return Some(());
+            }
+            f(FdtRegion {
+                start: address,
+                end: address.checked_add(size)?,
+            })?;
+        }
+    }
+
+    /// 遍历结构块，每个节点结束时以从根到该节点的路径调用f
+    pub fn walk(&self, mut f: impl FnMut(&[FdtNode<'a>]) -> Option<()>) -> Option<()> {
+        let empty = FdtNode {
+            name: &[],
+            props: &[],
+            strings: self.strings,
+            addr_cells: 0,
+            size_cells: 0,
+        };
+        let mut nodes = [empty; FDT_MAX_DEPTH];
+        /* #address-cells and #size-cells of each node, for its children */
+        let mut child_cells = [(0, 0); FDT_MAX_DEPTH];
+        let mut depth = 0;
+        let mut off = 0;
+        loop {
+            let token = be32(self.structs, off)?;
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(self.structs, off)?;
+                    off = align4(off + name.len() + 1);
+                    if depth == FDT_MAX_DEPTH {
+                        return None;
+                    }
+                    let (addr_cells, size_cells) = match depth {
+                        0 => (FDT_DEFAULT_ADDR_CELLS, FDT_DEFAULT_SIZE_CELLS),
+                        _ => child_cells[depth - 1],
+                    };
+                    let mut node = FdtNode {
+                        name,
+                        props: self.structs.get(off..)?,
+                        strings: self.strings,
+                        addr_cells,
+                        size_cells,
+                    };
+                    node.props = &node.props[..node.props_len()?];
+                    child_cells[depth] = (
+                        node.prop_u32(b"#address-cells")?
+                            .unwrap_or(FDT_DEFAULT_ADDR_CELLS),
+                        node.prop_u32(b"#size-cells")?
+                            .unwrap_or(FDT_DEFAULT_SIZE_CELLS),
+                    );
+                    off += node.props.len();
+                    nodes[depth] = node;
+                    depth += 1;
+                }
+                FDT_END_NODE => {
+                    if depth == 0 {
+                        return None;
+                    }
+                    f(&nodes[..depth])?;
+                    depth -= 1;
+                }
+                FDT_NOP => {}
+                FDT_END => return Some(()),
+                /* properties are consumed with their node */
+                _ => return None,
+            }
+        }
+    }
+
+    /// 读取/memory、/reserved-memory的子节点和内存保留块
+    ///
+    /// /reserved-memory中没有reg的子节点由操作系统动态分配，这里不支持，忽略它们
+    pub fn for_each_memory_region(
+        &self,
+        mut memory: impl FnMut(FdtRegion) -> Option<()>,
+        mut reserved: impl FnMut(FdtRegion) -> Option<()>,
+    ) -> Option<()> {
+        self.for_each_mem_rsv(&mut reserved)?;
+        self.walk(|nodes| {
+            let node = &nodes[nodes.len() - 1];
+            let is_memory = node
+                .prop(b"device_type")?
+                .map_or(false, |t| t == b"memory\0");
+            if is_memory {
+                node.for_each_reg(&mut memory)
+            } else if nodes.len() == 3 && nodes[1].name() == b"reserved-memory" {
+                node.for_each_reg(&mut reserved)
+            } else {
+                Some(())
+            }
+        })
+    }
+}
+
+impl<'a> FdtNode<'a> {
+    /// 节点名，不含unit address
+    pub fn name(&self) -> &'a [u8] {
+        self.name.split(|&c| c == b'@').next().unwrap()
+    }
+
+    /// 属性部分的长度，到第一个子节点或节点结束为止
+    fn props_len(&self) -> Option<usize> {
+        let mut off = 0;
+        loop {
+            match be32(self.props, off)? {
+                FDT_PROP => off = align4(off + 12 + be32(self.props, off + 4)? as usize),
+                FDT_NOP => off += 4,
+                _ => return Some(off),
+            }
+        }
+    }
+
+    /// 名为name的属性的值，属性格式不正确时返回None
+    pub fn prop(&self, name: &[u8]) -> Option<Option<&'a [u8]>> {
+        let mut off = 0;
+        while off < self.props.len() {
+            if be32(self.props, off)? == FDT_NOP {
+                off += 4;
+                continue;
+            }
+            let len = be32(self.props, off + 4)? as usize;
+            let prop_name = cstr(self.strings, be32(self.props, off + 8)? as usize)?;
+            let value = self.props.get(off + 12..off + 12 + len)?;
+            if prop_name == name {
+                return Some(Some(value));
+            }
+            off = align4(off + 12 + len);
+        }
+        Some(None)
+    }
+
+    /// 只有一个32位数的属性，长度不对时返回None
+    pub fn prop_u32(&self, name: &[u8]) -> Option<Option<usize>> {
+        match self.prop(name)? {
+            Some(value) => Some(Some(read_cells(value, 1)?)),
+            None => Some(None),
+        }
+    }
+
+    /// 由若干32位数拼成的属性，长度不是4的倍数时返回None
+    pub fn prop_cells(&self, name: &[u8]) -> Option<Option<usize>> {
+        match self.prop(name)? {
+            Some(value) => Some(Some(read_cells(value, value.len() / 4)?)),
+            None => Some(None),
+        }
+    }
+
+    /// compatible属性中是否有with中的一项
+    pub fn is_compatible(&self, with: &[&[u8]]) -> Option<bool> {
+        Some(match self.prop(b"compatible")? {
+            Some(compatible) => compatible.split(|&c| c == 0).any(|s| with.contains(&s)),
+            None => false,
+        })
+    }
+
+    /// 对reg属性中的每一个(address, size)对调用f，区域越过地址空间末尾时返回None
+    pub fn for_each_reg(&self, mut f: impl FnMut(FdtRegion) -> Option<()>) -> Option<()> {
+        let (addr_cells, size_cells) = (self.addr_cells, self.size_cells);
+        /* without size cells reg holds no regions */
+        if size_cells == 0 {
+            return Some(());
+        }
+        let reg = match self.prop(b"reg")? {
+            Some(reg) => reg,
+            None => return Some(()),
+        };
+        let entry = (addr_cells + size_cells) * 4;
+        if reg.len() % entry != 0 {
+            return None;
+        }
+        for e in reg.chunks_exact(entry) {
+            let (start, size) = e.split_at(addr_cells * 4);
+            let start = read_cells(start, addr_cells)?;
+            let size = read_cells(size, size_cells)?;
+            if size != 0 {
+                f(FdtRegion {
+                    start,
+                    end: start.checked_add(size)?,
+                })?;
+            }
+        }
+        Some(())
+    }
+
+    /// reg属性中的第一个区域
+    pub fn first_reg(&self) -> Option<Option<FdtRegion>> {
+        let mut first = None;
+        self.for_each_reg(|reg| {
+            first.get_or_insert(reg);
+            Some(())
+        })?;
+        Some(first)
+    }
+}
@@ -1,2 +1,17 @@
 pub const LABEL_NO_ERROR: usize = 0;
 pub const LABEL_CNODE_COPY: usize = 1;
+pub const LABEL_RISCV_PAGE_MAP: usize = 2;
+pub const LABEL_RISCV_PAGE_UNMAP: usize = 3;
+pub const LABEL_RISCV_PAGE_GET_ADDRESS: usize = 4;
+pub const LABEL_UNTYPED_RETYPE: usize = 5;
+pub const LABEL_RISCV_PAGE_TABLE_MAP: usize = 6;
+pub const LABEL_RISCV_PAGE_TABLE_UNMAP: usize = 7;
+pub const LABEL_RISCV_ASID_CONTROL_MAKE_POOL: usize = 8;
+pub const LABEL_RISCV_ASID_POOL_ASSIGN: usize = 9;
+
+pub const LABEL_TCB_READ_REGISTERS: usize = 10;
+pub const LABEL_TCB_WRITE_REGISTERS: usize = 11;
+pub const LABEL_TCB_CONFIGURE: usize = 12;
+pub const LABEL_TCB_SET_PRIORITY: usize = 13;
+pub const LABEL_TCB_SUSPEND: usize = 14;
+pub const LABEL_TCB_RESUME: usize = 15;
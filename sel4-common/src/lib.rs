@@ -1,10 +1,14 @@
 #![no_std]
 #![no_main]
 
+pub mod boot_handoff;
 pub mod bootinfo_common;
+pub mod extra_bootinfo;
+pub mod fdt;
 pub mod macros;
 pub mod syscall_ids;
 pub mod constants;
 pub mod invocation;
 pub mod shared_types;
-pub mod structures_common;
\ No newline at end of file
+pub mod structures_common;
+pub mod trace;
\ No newline at end of file
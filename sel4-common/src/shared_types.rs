@@ -34,3 +34,61 @@ pub struct IPCBuffer {
     pub receive_index: usize,
     pub receive_depth: usize
 }
+
+/* integer registers, then f0-f31 and fcsr */
+pub const seL4_UserContextIntRegisters: usize = 32;
+pub const seL4_UserContextFpuRegisters: usize = 33;
+pub const seL4_UserContextWords: usize =
+    seL4_UserContextIntRegisters + seL4_UserContextFpuRegisters;
+
+/// TCB ReadRegisters/WriteRegisters传递的寄存器，按字段顺序放在消息中，见seL4_UserContext
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct UserContext {
+    pub pc: usize,
+    pub ra: usize,
+    pub sp: usize,
+    pub gp: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub tp: usize,
+
+    pub f: [u64; 32],
+    pub fcsr: usize,
+}
+
+impl UserContext {
+    pub fn as_words(&self) -> &[usize; seL4_UserContextWords] {
+        unsafe { &*(self as *const Self as *const [usize; seL4_UserContextWords]) }
+    }
+
+    pub fn as_words_mut(&mut self) -> &mut [usize; seL4_UserContextWords] {
+        unsafe { &mut *(self as *mut Self as *mut [usize; seL4_UserContextWords]) }
+    }
+}
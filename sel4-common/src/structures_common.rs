@@ -17,6 +17,18 @@ pub const CAP_IRQ_HANDLER_CAP: usize = 16;
 pub const CAP_ZOMBIE_CAP: usize = 18;
 pub const CAP_DOMAIN_CAP: usize = 20;
 
+// object types for untyped retype
+pub const seL4_UntypedObject: usize = 0;
+pub const seL4_TCBObject: usize = 1;
+pub const seL4_EndpointObject: usize = 2;
+pub const seL4_NotificationObject: usize = 3;
+pub const seL4_CapTableObject: usize = 4;
+pub const seL4_RISCV_Giga_Page: usize = 5;
+pub const seL4_RISCV_4K_Page: usize = 6;
+pub const seL4_RISCV_Mega_Page: usize = 7;
+pub const seL4_RISCV_PageTableObject: usize = 8;
+pub const seL4_ObjectTypeCount: usize = 9;
+
 // rootserver's cslot indexes
 pub const seL4_CapNull: usize = 0; /* null cap */
 pub const seL4_CapInitThreadTCB: usize = 1; /* initial thread's TCB cap */
@@ -49,3 +61,9 @@ bitflags! {
         const ALL = 0xf;
     }
 }
+
+bitflags! {
+    pub struct VMAttributes: usize {
+        const EXECUTE_NEVER = 1 << 0;
+    }
+}
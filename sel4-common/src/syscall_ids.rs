@@ -10,3 +10,6 @@ pub const seL4_SysYield: usize = -7 as _;
 pub const seL4_SysNBRecv: usize = -8 as _;
 pub const seL4_SysDebugPutChar: usize = -9 as _;
 pub const seL4_SysDebugDumpScheduler: usize = -10 as _;
+pub const seL4_SysBenchmarkResetLog: usize = -11 as _;
+pub const seL4_SysBenchmarkFinalizeLog: usize = -12 as _;
+pub const seL4_SysBenchmarkSetLogBuffer: usize = -13 as _;
@@ -0,0 +1,40 @@
+/* Layout of the kernel trace log, shared by the kernel, the user library and
+ * tools/trace-decoder. It has no dependencies so that host tools can include it. */
+
+/// 日志帧开头的TraceLogHeader.magic
+pub const TRACE_LOG_MAGIC: u32 = 0x6c34_7472;
+
+/* why the kernel was entered, TraceEntry.path */
+pub const TRACE_PATH_SYSCALL: u32 = 0;
+pub const TRACE_PATH_INTERRUPT: u32 = 1;
+pub const TRACE_PATH_EXCEPTION: u32 = 2;
+
+/// 日志帧开头的信息，之后是capacity个TraceEntry组成的环形缓冲区
+///
+/// 内核只写不读，用户修改这些字段不影响内核
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TraceLogHeader {
+    pub magic: u32,
+    pub entry_size: u32,
+    pub capacity: u64,
+    /* entries written since the last reset, entry i is at i % capacity */
+    pub count: u64,
+    /* rdtime ticks per second, 0 when the device tree does not say */
+    pub timebase_frequency: u64,
+}
+
+/// 一次进入内核，从trap入口到返回用户态
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub path: u32,
+    pub core: u32,
+    /* syscall number for TRACE_PATH_SYSCALL, scause otherwise */
+    pub cause: u64,
+    /* the thread that entered the kernel */
+    pub tcb: u64,
+    /* rdtime on entry and before returning to user space */
+    pub start: u64,
+    pub end: u64,
+}
@@ -1,11 +1,23 @@
 use core::arch::global_asm;
 use cpio_reader::iter_files;
-use xmas_elf::ElfFile;
+use sel4_common::boot_handoff::{
+    BootHandoff, ImageRegion, ImageSegment, BOOT_HANDOFF_MAX_USER_IMAGES, IMAGE_MAX_SEGMENTS,
+    IMAGE_NAME_LEN, SEGMENT_R, SEGMENT_W, SEGMENT_X,
+};
+use xmas_elf::{program::ProgramHeader, ElfFile};
 
-use crate::println;
+use crate::{
+    _end, _text,
+    lz4::{is_lz4_frame, lz4_content_size, lz4_decompress},
+    manifest::{measure_images, MANIFEST_NAME, MANIFEST_SIG_NAME},
+    memory::MemoryMap,
+    println,
+};
 global_asm!(include_str!("archive.S"));
 
 pub const PAGE_BITS: usize = 12;
+/* the kernel is mapped with megapages until it switches to its own page table */
+pub const LARGE_PAGE_BITS: usize = 21;
 
 #[macro_export]
 macro_rules! round_up {
@@ -53,18 +65,18 @@ pub struct ImageInfo {
     /* Virtual address of the user image's entry point. */
     pub virt_entry: usize,
     pub phys_virt_offset: usize,
+
+    /* PT_LOAD segments with their permissions */
+    pub num_segments: usize,
+    pub segments: [ImageSegment; IMAGE_MAX_SEGMENTS],
 }
 
-fn elf_get_memory_bounds(elf: &ElfFile, is_phys: bool) -> (usize, usize) {
+fn elf_get_memory_bounds(elf: &ElfFile) -> (usize, usize) {
     let mut mem_min = usize::max_value();
     let mut mem_max = 0usize;
     for header in elf.program_iter() {
         if header.get_type().unwrap() == xmas_elf::program::Type::Load {
-            let sect_min = if is_phys {
-                header.physical_addr()
-            } else {
-                header.virtual_addr()
-            };
+            let sect_min = header.virtual_addr();
             let sect_max = sect_min + header.mem_size();
             mem_min = mem_min.min(sect_min as _);
             mem_max = mem_max.max(sect_max as _);
@@ -77,26 +89,108 @@ fn elf_get_entry_point(elf: &ElfFile) -> usize {
     elf.header.pt2.entry_point() as _
 }
 
-fn unpack_elf(elf: &ElfFile, elf_min_vaddr: usize, dest_paddr: usize) {
-    for header in elf.program_iter() {
-        if header.get_type().unwrap() == xmas_elf::program::Type::Load {
-            let seg_dest_paddr = header.virtual_addr() as usize - elf_min_vaddr + dest_paddr;
-            let seg_size = header.file_size();
-            let seg_offset = header.offset();
-            unsafe {
-                core::slice::from_raw_parts_mut(seg_dest_paddr as *mut u8, seg_size as usize)
-                    .copy_from_slice(core::slice::from_raw_parts(
-                        elf.input.as_ptr().add(seg_offset as usize),
-                        seg_size as usize,
-                    ));
+fn elf_load_segments<'a>(elf: &'a ElfFile) -> impl Iterator<Item = ProgramHeader<'a>> {
+    elf.program_iter()
+        .filter(|header| header.get_type().unwrap() == xmas_elf::program::Type::Load)
+}
+
+fn segment_flags(header: &ProgramHeader) -> usize {
+    let flags = header.flags();
+    let mut ret = 0;
+    if flags.is_read() {
+        ret |= SEGMENT_R;
+    }
+    if flags.is_write() {
+        ret |= SEGMENT_W;
+    }
+    if flags.is_execute() {
+        ret |= SEGMENT_X;
+    }
+    ret
+}
+
+fn check_overlap(name: &str, start: usize, end: usize, other: &str, o_start: usize, o_end: usize) {
+    if start < o_end && o_start < end {
+        panic!(
+            "{} [{:#x?}..{:#x?}] overlaps {} [{:#x?}..{:#x?}]",
+            name,
+            start,
+            end - 1,
+            other,
+            o_start,
+            o_end - 1
+        );
+    }
+}
+
+/// 目标物理内存[start, end)不能覆盖elfloader自己（包括其中的cpio归档）
+fn check_elfloader_overlap(name: &str, start: usize, end: usize) {
+    check_overlap(
+        name,
+        start,
+        end,
+        "the elfloader",
+        _text as usize,
+        _end as usize,
+    );
+}
+
+/// 检查各个段互不重叠并记录它们的权限
+fn elf_get_segments(name: &str, elf: &ElfFile) -> (usize, [ImageSegment; IMAGE_MAX_SEGMENTS]) {
+    let mut segments = [ImageSegment::empty(); IMAGE_MAX_SEGMENTS];
+    let mut num_segments = 0;
+    for header in elf_load_segments(elf) {
+        if header.file_size() > header.mem_size() {
+            panic!("{}: segment file size exceeds its memory size", name);
+        }
+        let seg = ImageSegment {
+            virt_start: header.virtual_addr() as usize,
+            virt_end: (header.virtual_addr() + header.mem_size()) as usize,
+            flags: segment_flags(&header),
+        };
+        for other in &segments[..num_segments] {
+            if seg.virt_start < other.virt_end && other.virt_start < seg.virt_end {
+                panic!(
+                    "{}: segment [{:#x?}..{:#x?}] overlaps segment [{:#x?}..{:#x?}]",
+                    name, seg.virt_start, seg.virt_end, other.virt_start, other.virt_end
+                );
             }
         }
+        if num_segments == IMAGE_MAX_SEGMENTS {
+            panic!(
+                "{}: too many loadable segments (max {})",
+                name, IMAGE_MAX_SEGMENTS
+            );
+        }
+        segments[num_segments] = seg;
+        num_segments += 1;
+    }
+    (num_segments, segments)
+}
+
+fn unpack_elf(elf: &ElfFile, elf_min_vaddr: usize, dest_paddr: usize) {
+    for header in elf_load_segments(elf) {
+        let seg_dest_paddr = header.virtual_addr() as usize - elf_min_vaddr + dest_paddr;
+        let seg_size = header.file_size() as usize;
+        let seg_offset = header.offset() as usize;
+        let seg_mem_size = header.mem_size() as usize;
+        unsafe {
+            core::slice::from_raw_parts_mut(seg_dest_paddr as *mut u8, seg_size).copy_from_slice(
+                core::slice::from_raw_parts(elf.input.as_ptr().add(seg_offset), seg_size),
+            );
+            /* the part not backed by the file is the bss */
+            core::ptr::write_bytes(
+                (seg_dest_paddr + seg_size) as *mut u8,
+                0,
+                seg_mem_size - seg_size,
+            );
+        }
     }
 }
 
 fn load_elf(name: &str, elf: &ElfFile, dest_paddr: usize) -> ImageInfo {
     println!("ELF-loading image {:#x?} to {:#x?}", name, dest_paddr);
-    let (min_vaddr, mut max_vaddr) = elf_get_memory_bounds(elf, false);
+    let (min_vaddr, mut max_vaddr) = elf_get_memory_bounds(elf);
     max_vaddr = round_up!(max_vaddr, PAGE_BITS);
     let image_size = max_vaddr - min_vaddr;
 
@@ -109,6 +203,8 @@ fn load_elf(name: &str, elf: &ElfFile, dest_paddr: usize) -> ImageInfo {
     println!("  vaddr=[{:#x?}..{:#x?}]", min_vaddr, max_vaddr - 1);
     println!("  virt_entry={:#x?}", elf_get_entry_point(elf));
 
+    check_elfloader_overlap(name, dest_paddr, dest_paddr + image_size);
+    let (num_segments, segments) = elf_get_segments(name, elf);
     unpack_elf(elf, min_vaddr, dest_paddr);
 
     ImageInfo {
@@ -117,14 +213,125 @@ fn load_elf(name: &str, elf: &ElfFile, dest_paddr: usize) -> ImageInfo {
         virt_region_start: min_vaddr,
         virt_region_end: max_vaddr,
         virt_entry: elf_get_entry_point(elf),
-        phys_virt_offset: dest_paddr - min_vaddr,
+        phys_virt_offset: dest_paddr.wrapping_sub(min_vaddr),
+        num_segments,
+        segments,
+    }
+}
+
+/* FDT header fields, all big-endian u32, see devicetree spec 5.2 */
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_FIRST_SUPPORTED_VERSION: u32 = 16;
+const FDT_LAST_SUPPORTED_VERSION: u32 = 17;
+
+fn fdt32(dtb: *const u8, off: usize) -> u32 {
+    let mut b = [0u8; 4];
+    unsafe { core::ptr::copy_nonoverlapping(dtb.add(off), b.as_mut_ptr(), 4) };
+    u32::from_be_bytes(b)
+}
+
+/// 检查设备树头部，合法时返回设备树的大小
+fn fdt_check_header(dtb: *const u8) -> Option<usize> {
+    if dtb.is_null() || fdt32(dtb, 0) != FDT_MAGIC {
+        return None;
+    }
+    let totalsize = fdt32(dtb, 4) as usize;
+    let version = fdt32(dtb, 20);
+    let last_comp_version = fdt32(dtb, 24);
+    if totalsize < FDT_HEADER_SIZE
+        || version < FDT_FIRST_SUPPORTED_VERSION
+        || last_comp_version > FDT_LAST_SUPPORTED_VERSION
+    {
+        return None;
+    }
+    /* struct block, strings block and memory reservation map must be inside the blob */
+    let off_dt_struct = fdt32(dtb, 8) as usize;
+    let off_dt_strings = fdt32(dtb, 12) as usize;
+    let off_mem_rsvmap = fdt32(dtb, 16) as usize;
+    let size_dt_strings = fdt32(dtb, 32) as usize;
+    let size_dt_struct = fdt32(dtb, 36) as usize;
+    if off_mem_rsvmap < FDT_HEADER_SIZE
+        || off_mem_rsvmap >= totalsize
+        || off_dt_struct + size_dt_struct > totalsize
+        || off_dt_strings + size_dt_strings > totalsize
+    {
+        return None;
+    }
+    Some(totalsize)
+}
+
+/// 用户镜像的加载顺序：先是rootserver（cpio中的app），再按cpio中的顺序加载其它镜像
+fn user_image_entries<'a>(cpio: &'a [u8]) -> impl Iterator<Item = (&'a str, &'a [u8])> {
+    let rootserver = iter_files(cpio).filter(|entry| entry.name() == "app");
+    let others = iter_files(cpio).filter(|entry| {
+        !["kernel", "app", MANIFEST_NAME, MANIFEST_SIG_NAME].contains(&entry.name())
+    });
+    rootserver
+        .chain(others)
+        .map(|entry| (entry.name(), entry.file()))
+}
+
+/// LZ4压缩的文件解压后的大小，未压缩时返回None
+fn decompressed_size(name: &str, file: &[u8]) -> Option<usize> {
+    if !is_lz4_frame(file) {
+        return None;
+    }
+    match lz4_content_size(file) {
+        Ok(0) => panic!("{}: empty LZ4 frame", name),
+        Ok(size) => Some(size),
+        Err(err) => panic!("{}: bad LZ4 frame header: {:?}", name, err),
     }
 }
 
+/// LZ4压缩的文件被解压到scratch处，其它文件原样返回
+fn decompress_entry<'a>(name: &str, file: &'a [u8], scratch: &mut usize) -> &'a [u8] {
+    let size = match decompressed_size(name, file) {
+        Some(size) => size,
+        None => return file,
+    };
+    println!(
+        "Decompressing {} ({:#x?} -> {:#x?} bytes) to {:#x?}",
+        name,
+        file.len(),
+        size,
+        *scratch
+    );
+    let dest = unsafe { core::slice::from_raw_parts_mut(*scratch as *mut u8, size) };
+    if let Err(err) = lz4_decompress(file, dest) {
+        panic!("{}: LZ4 decompression failed: {:?}", name, err);
+    }
+    /* keep the next image page aligned, xmas-elf reads the headers in place */
+    *scratch = round_up!(*scratch + size, PAGE_BITS);
+    dest
+}
+
+fn elf_get_image_size(elf: &ElfFile) -> usize {
+    let (min_vaddr, max_vaddr) = elf_get_memory_bounds(elf);
+    round_up!(max_vaddr, PAGE_BITS) - min_vaddr
+}
+
+pub fn image_region(name: &str, info: &ImageInfo) -> ImageRegion {
+    let mut region = ImageRegion {
+        name: [0; IMAGE_NAME_LEN],
+        phys_start: info.phys_region_start,
+        phys_end: info.phys_region_end,
+        virt_start: info.virt_region_start,
+        virt_end: info.virt_region_end,
+        virt_entry: info.virt_entry,
+        num_segments: info.num_segments,
+        segments: info.segments,
+    };
+    region.set_name(name);
+    region
+}
+
+/// 加载内核和所有用户镜像，并把用户镜像和设备树的位置填入handoff，返回内核镜像的信息
 pub fn load_images(
-    _max_user_images: usize,
-    _bootloader_dtb: *const usize,
-) -> (ImageInfo, ImageInfo) {
+    handoff: &mut BootHandoff,
+    max_user_images: usize,
+    bootloader_dtb: *const usize,
+) -> ImageInfo {
     extern "C" {
         fn _archive_start();
         fn _archive_end();
@@ -137,28 +344,91 @@ pub fn load_images(
         )
     };
 
-    let mut kernel_info = None;
-    let mut user_info = None;
-    let mut app_phys_addr = None;
-
-    for entry in iter_files(cpio) {
-        match entry.name() {
-            "kernel" => {
-                let file = entry.file();
-                let kernel_elf = ElfFile::new(file).unwrap();
-                let (kernel_phys_start, kernel_phys_end) = elf_get_memory_bounds(&kernel_elf, true);
-                kernel_info = Some(load_elf("kernel", &kernel_elf, kernel_phys_start));
-                app_phys_addr = Some(round_up!(kernel_phys_end, PAGE_BITS));
-            }
-            "app" => {
-                let app_phys_start = app_phys_addr.unwrap();
-                let file = entry.file();
-                let app_elf = ElfFile::new(file).unwrap();
-                user_info = Some(load_elf("app", &app_elf, app_phys_start));
-            }
-            _ => panic!("Unknown cpio entry {:#x?}", entry.name()),
-        }
+    if !iter_files(cpio).any(|entry| entry.name() == "app") {
+        panic!("No user image present in archive");
+    }
+    let num_user_images = user_image_entries(cpio).count();
+    if num_user_images > max_user_images || num_user_images > BOOT_HANDOFF_MAX_USER_IMAGES {
+        panic!(
+            "Too many user images in archive: {} (max {})",
+            num_user_images, max_user_images
+        );
+    }
+
+    let bootloader_dtb = bootloader_dtb as *const u8;
+    let dtb_size = fdt_check_header(bootloader_dtb).unwrap_or(0);
+    let dtb =
+        (dtb_size != 0).then(|| unsafe { core::slice::from_raw_parts(bootloader_dtb, dtb_size) });
+    let mut memory = MemoryMap::new(dtb);
+    /* neither the elfloader with its archive nor the DTB may be overwritten
+     * before they have been used */
+    memory.reserve(_text as usize, _end as usize);
+    memory.reserve(bootloader_dtb as usize, bootloader_dtb as usize + dtb_size);
+
+    let kernel_file = iter_files(cpio)
+        .find(|entry| entry.name() == "kernel")
+        .map(|entry| entry.file())
+        .expect("No kernel image present in archive");
+    handoff.measurement = measure_images(
+        cpio,
+        core::iter::once(("kernel", kernel_file)).chain(user_image_entries(cpio)),
+    );
+
+    /* compressed images are decompressed first, their sizes are only known
+     * afterwards. The scratch area is free memory again once they are loaded */
+    let scratch_size: usize = core::iter::once(("kernel", kernel_file))
+        .chain(user_image_entries(cpio))
+        .filter_map(|(name, file)| decompressed_size(name, file))
+        .map(|size| round_up!(size, PAGE_BITS))
+        .sum();
+    let mut scratch = 0;
+    if scratch_size != 0 {
+        scratch = memory.alloc("decompression scratch", scratch_size, PAGE_BITS);
+    }
+    let kernel_elf = ElfFile::new(decompress_entry("kernel", kernel_file, &mut scratch)).unwrap();
+    let mut user_images: [(&str, &[u8]); BOOT_HANDOFF_MAX_USER_IMAGES] =
+        [("", &[]); BOOT_HANDOFF_MAX_USER_IMAGES];
+    for (i, (name, file)) in user_image_entries(cpio).enumerate() {
+        user_images[i] = (name, decompress_entry(name, file, &mut scratch));
+    }
+    let user_images = &user_images[..num_user_images];
+
+    /* the user images are placed one after another right after the kernel,
+     * the dtb right after the last one, wherever the memory map has room */
+    let kernel_size = elf_get_image_size(&kernel_elf);
+    let user_size: usize = user_images
+        .iter()
+        .map(|(_, file)| elf_get_image_size(&ElfFile::new(file).unwrap()))
+        .sum();
+    let kernel_phys_start = memory.alloc(
+        "the images",
+        kernel_size + user_size + dtb_size,
+        LARGE_PAGE_BITS,
+    );
+    let user_phys_start = kernel_phys_start + kernel_size;
+    let dtb_phys_start = user_phys_start + user_size;
+
+    if dtb_size != 0 {
+        println!(
+            "Copying DTB from {:#x?} to {:#x?} ({:#x?} bytes)",
+            bootloader_dtb as usize, dtb_phys_start, dtb_size
+        );
+        unsafe { core::ptr::copy(bootloader_dtb, dtb_phys_start as *mut u8, dtb_size) };
+    } else {
+        println!("No valid DTB passed by the bootloader");
+    }
+
+    let kernel_info = load_elf("kernel", &kernel_elf, kernel_phys_start);
+
+    let mut next_phys_addr = user_phys_start;
+    for (i, &(name, file)) in user_images.iter().enumerate() {
+        let info = load_elf(name, &ElfFile::new(file).unwrap(), next_phys_addr);
+        next_phys_addr = info.phys_region_end;
+        handoff.user_images[i] = image_region(name, &info);
     }
+    handoff.num_user_images = num_user_images;
+    handoff.dtb_paddr = if dtb_size != 0 { dtb_phys_start } else { 0 };
+    handoff.dtb_size = dtb_size;
 
-    (kernel_info.unwrap(), user_info.unwrap())
+    kernel_info
 }
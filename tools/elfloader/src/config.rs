@@ -1 +1,11 @@
-pub const CONFIG_MAX_NUM_NODES: usize = 1;
+/* must match BOOT_HANDOFF_MAX_NODES and CONFIG_MAX_NUM_NODES in crt0.S */
+pub const CONFIG_MAX_NUM_NODES: usize = 8;
+/* hart ids probed through SBI HSM when starting the secondary harts */
+pub const MAX_HART_ID: usize = 64;
+/* physical memory used when the bootloader does not pass a device tree, the
+ * same as AVAIL_REGION_START/END in the kernel */
+pub const FALLBACK_RAM_START: usize = 0x80200000;
+pub const FALLBACK_RAM_END: usize = 0x90000000;
+/* hex encoded Ed25519 public key, set by the Makefile from SIGNING_KEY; when
+ * present the elfloader only boots archives with a manifest signed by it */
+pub const MANIFEST_PUBLIC_KEY: Option<&str> = option_env!("MANIFEST_PUBLIC_KEY");
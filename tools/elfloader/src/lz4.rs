@@ -0,0 +1,156 @@
+/* LZ4 frame format, see lz4_Frame_format.md and lz4_Block_format.md in the lz4 repository */
+const LZ4_FRAME_MAGIC: u32 = 0x184d2204;
+const LZ4_FRAME_VERSION: u8 = 0b01;
+
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLG_DICT_ID: u8 = 1 << 0;
+
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+const MIN_MATCH: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Lz4Error {
+    BadMagic,
+    UnsupportedVersion(u8),
+    /* the frame was not created with --content-size */
+    NoContentSize,
+    OutputTooSmall,
+    Truncated,
+    BadOffset,
+}
+
+fn le32(buf: &[u8], off: usize) -> Result<u32, Lz4Error> {
+    let b = buf.get(off..off + 4).ok_or(Lz4Error::Truncated)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn le64(buf: &[u8], off: usize) -> Result<u64, Lz4Error> {
+    Ok(le32(buf, off)? as u64 | (le32(buf, off + 4)? as u64) << 32)
+}
+
+pub fn is_lz4_frame(data: &[u8]) -> bool {
+    le32(data, 0).map_or(false, |magic| magic == LZ4_FRAME_MAGIC)
+}
+
+/// 帧描述符中记录的解压后大小
+pub fn lz4_content_size(data: &[u8]) -> Result<usize, Lz4Error> {
+    if !is_lz4_frame(data) {
+        return Err(Lz4Error::BadMagic);
+    }
+    let flg = *data.get(4).ok_or(Lz4Error::Truncated)?;
+    if flg >> 6 != LZ4_FRAME_VERSION {
+        return Err(Lz4Error::UnsupportedVersion(flg >> 6));
+    }
+    if flg & FLG_CONTENT_SIZE == 0 {
+        return Err(Lz4Error::NoContentSize);
+    }
+    Ok(le64(data, 6)? as usize)
+}
+
+/// 长度字段为15时后面跟着若干字节，直到遇到不是255的字节
+fn read_length(input: &[u8], pos: &mut usize, mut len: usize) -> Result<usize, Lz4Error> {
+    if len == 15 {
+        loop {
+            let b = *input.get(*pos).ok_or(Lz4Error::Truncated)?;
+            *pos += 1;
+            len += b as usize;
+            if b != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// 解压一个块到output[out_pos..]，返回新的out_pos
+///
+/// 匹配可以引用之前的块的输出，所以整个帧要解压到同一块连续的内存中
+fn decompress_block(
+    input: &[u8],
+    output: &mut [u8],
+    mut out_pos: usize,
+) -> Result<usize, Lz4Error> {
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos).ok_or(Lz4Error::Truncated)?;
+        pos += 1;
+
+        let literal_len = read_length(input, &mut pos, (token >> 4) as usize)?;
+        let literals = input
+            .get(pos..pos + literal_len)
+            .ok_or(Lz4Error::Truncated)?;
+        output
+            .get_mut(out_pos..out_pos + literal_len)
+            .ok_or(Lz4Error::OutputTooSmall)?
+            .copy_from_slice(literals);
+        pos += literal_len;
+        out_pos += literal_len;
+
+        /* the last sequence has only literals */
+        if pos == input.len() {
+            return Ok(out_pos);
+        }
+
+        let offset = input
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or(Lz4Error::Truncated)?;
+        pos += 2;
+        if offset == 0 || offset > out_pos {
+            return Err(Lz4Error::BadOffset);
+        }
+        let match_len = read_length(input, &mut pos, (token & 0xf) as usize)? + MIN_MATCH;
+        if out_pos + match_len > output.len() {
+            return Err(Lz4Error::OutputTooSmall);
+        }
+        /* the match may overlap the bytes it produces, so copy byte by byte */
+        for i in out_pos..out_pos + match_len {
+            output[i] = output[i - offset];
+        }
+        out_pos += match_len;
+    }
+}
+
+/// 解压一个LZ4帧，返回解压后的大小。不检查校验和
+pub fn lz4_decompress(input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+    let content_size = lz4_content_size(input)?;
+    if content_size > output.len() {
+        return Err(Lz4Error::OutputTooSmall);
+    }
+    let output = &mut output[..content_size];
+    let flg = input[4];
+    /* magic, FLG, BD, content size, optional dictionary id and the header checksum */
+    let mut pos = 4 + 2 + 8 + if flg & FLG_DICT_ID != 0 { 4 } else { 0 } + 1;
+    let mut out_pos = 0;
+    loop {
+        let block_size = le32(input, pos)?;
+        pos += 4;
+        if block_size == 0 {
+            break;
+        }
+        let len = (block_size & !BLOCK_UNCOMPRESSED) as usize;
+        let block = input.get(pos..pos + len).ok_or(Lz4Error::Truncated)?;
+        if block_size & BLOCK_UNCOMPRESSED != 0 {
+            output
+                .get_mut(out_pos..out_pos + len)
+                .ok_or(Lz4Error::OutputTooSmall)?
+                .copy_from_slice(block);
+            out_pos += len;
+        } else {
+            out_pos = decompress_block(block, output, out_pos)?;
+        }
+        pos += len;
+        if flg & FLG_BLOCK_CHECKSUM != 0 {
+            pos += 4;
+        }
+    }
+    if flg & FLG_CONTENT_CHECKSUM != 0 && input.len() < pos + 4 {
+        return Err(Lz4Error::Truncated);
+    }
+    if out_pos != content_size {
+        return Err(Lz4Error::Truncated);
+    }
+    Ok(out_pos)
+}
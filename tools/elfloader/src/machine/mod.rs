@@ -1,5 +1,7 @@
 mod io;
 mod sbi;
+mod vm;
 
 pub use io::*;
 pub use sbi::*;
+pub use vm::*;
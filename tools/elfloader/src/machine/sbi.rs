@@ -11,12 +11,18 @@ const SBI_REMOTE_FENCE_I: usize = 5;
 const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
+const SBI_BASE_EXT: usize = 0x10;
+const BASE_PROBE_EXTENSION_FUNID: usize = 3;
 const SBI_HSM_EXT: usize = 0x48534d;
 const HSM_HART_START_FUNID: usize = 0;
 const HSM_HART_SUSPEND_FUNID: usize = 3;
 const HSM_HART_GET_STATUS_FUNID: usize = 2;
 const NONE: usize = 0;
 
+/* hart states returned by sbi_get_hart_status */
+pub const HSM_STATUS_STARTED: usize = 0;
+pub const HSM_STATUS_STOPPED: usize = 1;
+
 #[inline(always)]
 fn sbi_call(eid: usize, fid: usize, args: [usize; 3]) -> (usize, usize) {
     let mut ret1;
@@ -56,10 +62,32 @@ pub fn sbi_send_ipi(mask: usize) {
     sbi_call(SBI_SEND_IPI, NONE, [mask, 0, 0]);
 }
 
-pub fn sbi_hart_start(hartid: usize, start_addr: usize, a1: usize) -> usize {
-    sbi_call(SBI_HSM_EXT, HSM_HART_START_FUNID, [hartid, start_addr, a1]).0
+/// 固件是否实现了某个SBI扩展
+pub fn sbi_probe_extension(eid: usize) -> bool {
+    let (error, value) = sbi_call(SBI_BASE_EXT, BASE_PROBE_EXTENSION_FUNID, [eid, 0, 0]);
+    error == 0 && value != 0
+}
+
+pub fn sbi_hsm_available() -> bool {
+    sbi_probe_extension(SBI_HSM_EXT)
 }
 
-pub fn sbi_get_hart_status(hartid: usize) -> usize {
-    sbi_call(SBI_HSM_EXT, HSM_HART_GET_STATUS_FUNID, [hartid, 0, 0]).0
+/// 在start_addr处以物理地址启动一个hart，a0为hart id，a1为opaque，成功时返回0
+pub fn sbi_hart_start(hartid: usize, start_addr: usize, opaque: usize) -> usize {
+    sbi_call(
+        SBI_HSM_EXT,
+        HSM_HART_START_FUNID,
+        [hartid, start_addr, opaque],
+    )
+    .0
+}
+
+/// hart不存在时返回None
+pub fn sbi_get_hart_status(hartid: usize) -> Option<usize> {
+    let (error, status) = sbi_call(SBI_HSM_EXT, HSM_HART_GET_STATUS_FUNID, [hartid, 0, 0]);
+    if error == 0 {
+        Some(status)
+    } else {
+        None
+    }
 }
@@ -0,0 +1,91 @@
+use core::arch::asm;
+
+use crate::{
+    bit,
+    common::{ImageInfo, LARGE_PAGE_BITS, PAGE_BITS},
+    mask,
+};
+
+const PT_LEVEL_1_BITS: usize = 30;
+const PT_LEVEL_2_BITS: usize = LARGE_PAGE_BITS;
+const PTE_PPN_SHIFT: usize = 10;
+const PTES_PER_PT: usize = 512;
+
+/* V | R | W | X | A | D */
+const PTE_KERNEL_RWX: usize = 0xcf;
+/* V only, points to the next level table */
+const PTE_V: usize = 0x1;
+
+#[repr(C, align(4096))]
+struct PageTable([usize; PTES_PER_PT]);
+
+/// 跳转到内核前使用的根页表，只包含elfloader的恒等映射和内核镜像所在的1GiB
+static mut BOOT_LVL1_PT: PageTable = PageTable([0; PTES_PER_PT]);
+/// 内核镜像所在的1GiB，用2MiB的大页映射，内核的物理地址只需按2MiB对齐
+static mut BOOT_LVL2_PT: PageTable = PageTable([0; PTES_PER_PT]);
+
+fn pt_index_lvl1(vaddr: usize) -> usize {
+    (vaddr >> PT_LEVEL_1_BITS) & mask!(9)
+}
+
+fn pt_index_lvl2(vaddr: usize) -> usize {
+    (vaddr >> PT_LEVEL_2_BITS) & mask!(9)
+}
+
+fn leaf_pte(paddr: usize, bits: usize) -> usize {
+    let ppn = (paddr >> bits) << (bits - PAGE_BITS);
+    ppn << PTE_PPN_SHIFT | PTE_KERNEL_RWX
+}
+
+fn next_level_pte(pt: &PageTable) -> usize {
+    (pt.0.as_ptr() as usize >> PAGE_BITS) << PTE_PPN_SHIFT | PTE_V
+}
+
+/// 建立内核镜像虚拟地址到物理地址的映射，并恒等映射elfloader自身
+pub fn map_kernel_window(kernel_info: &ImageInfo) {
+    extern "C" {
+        fn _text();
+        fn _end();
+    }
+    /* the kernel is mapped with megapages, so virtual and physical addresses
+     * must have the same offset inside them */
+    assert_eq!(
+        kernel_info.virt_region_start & mask!(PT_LEVEL_2_BITS),
+        kernel_info.phys_region_start & mask!(PT_LEVEL_2_BITS)
+    );
+    assert_eq!(
+        kernel_info.virt_region_start >> PT_LEVEL_1_BITS,
+        (kernel_info.virt_region_end - 1) >> PT_LEVEL_1_BITS,
+        "kernel image crosses a gigapage boundary"
+    );
+    /* the elfloader may have been loaded anywhere, it keeps running from a
+     * single identity mapped gigapage */
+    assert_eq!(
+        _text as usize >> PT_LEVEL_1_BITS,
+        (_end as usize - 1) >> PT_LEVEL_1_BITS,
+        "elfloader crosses a gigapage boundary"
+    );
+
+    unsafe {
+        let elfloader_paddr = _text as usize;
+        BOOT_LVL1_PT.0[pt_index_lvl1(elfloader_paddr)] = leaf_pte(elfloader_paddr, PT_LEVEL_1_BITS);
+        BOOT_LVL1_PT.0[pt_index_lvl1(kernel_info.virt_region_start)] =
+            next_level_pte(&BOOT_LVL2_PT);
+        let mut vaddr = kernel_info.virt_region_start & !mask!(PT_LEVEL_2_BITS);
+        while vaddr < kernel_info.virt_region_end {
+            let paddr = vaddr.wrapping_add(kernel_info.phys_virt_offset);
+            BOOT_LVL2_PT.0[pt_index_lvl2(vaddr)] = leaf_pte(paddr, PT_LEVEL_2_BITS);
+            vaddr += 1 << PT_LEVEL_2_BITS;
+        }
+    }
+}
+
+/// 打开Sv39分页，之后elfloader继续在恒等映射中运行
+pub fn enable_virtual_memory() {
+    let satp = 8 << 60 | unsafe { BOOT_LVL1_PT.0.as_ptr() } as usize >> PAGE_BITS;
+    unsafe {
+        asm!("sfence.vma");
+        asm!("csrw satp, {}", in(reg) satp);
+        asm!("sfence.vma");
+    }
+}
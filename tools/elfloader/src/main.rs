@@ -4,35 +4,53 @@
 mod common;
 mod config;
 mod lang_items;
+mod lz4;
 mod machine;
+mod manifest;
+mod memory;
+mod smp;
 
-use common::load_images;
+use common::{image_region, load_images};
 use config::*;
 use core::arch::global_asm;
+use machine::{enable_virtual_memory, map_kernel_window};
+use sel4_common::boot_handoff::{BootHandoff, BOOT_HANDOFF_MAX_USER_IMAGES};
+use smp::{boot_secondary_harts, release_secondary_harts};
 
 global_asm!(include_str!("crt0.S"));
 
-type InitRiscvKernelFn = fn(usize, usize, usize, usize, usize, usize);
+/* handoff, hart id, core id */
+type InitRiscvKernelFn = fn(*const BootHandoff, usize, usize);
+
+/// 传递给内核的启动信息，内核在切换到自己的页表之前读取它
+static mut BOOT_HANDOFF: BootHandoff = BootHandoff::new();
 
 extern "C" {
     pub fn _text();
     pub fn _end();
+    fn _park_start();
+    fn _park_end();
 }
 
-pub fn run_elfloader(_hart_id: usize, bootloader_dtb: *mut usize) -> ! {
-    let _num_apps = 0usize;
-    let (kernel_info, user_info) = load_images(1, bootloader_dtb);
+pub fn run_elfloader(hart_id: usize, bootloader_dtb: *mut usize) -> ! {
+    let handoff = unsafe { &mut BOOT_HANDOFF };
+    let kernel_info = load_images(handoff, BOOT_HANDOFF_MAX_USER_IMAGES, bootloader_dtb);
+    /* the kernel is linked at a virtual address in the last gigabyte */
+    map_kernel_window(&kernel_info);
+    handoff.hart_id = hart_id;
+    handoff.kernel = image_region("kernel", &kernel_info);
+    handoff.park_paddr = _park_start as usize;
+    handoff.park_size = _park_end as usize - _park_start as usize;
+    boot_secondary_harts(handoff, hart_id);
+    release_secondary_harts(kernel_info.virt_entry, handoff);
+    enable_virtual_memory();
     println!("Jumping to kernel-image entry point...\n");
-    let kernel_entry =
-        unsafe { core::mem::transmute::<_, InitRiscvKernelFn>(kernel_info.virt_entry) };
-    kernel_entry(
-        user_info.phys_region_start,
-        user_info.phys_region_end,
-        user_info.phys_virt_offset,
-        user_info.virt_entry,
-        0,
-        0,
-    );
+    let kernel_entry = unsafe {
+        core::arch::asm!("fence.i");
+        core::mem::transmute::<_, InitRiscvKernelFn>(kernel_info.virt_entry)
+    };
+    /* the elfloader is identity mapped, so the kernel can read the handoff at its physical address */
+    kernel_entry(handoff, hart_id, 0);
     panic!("Shouldn't reach here!");
 }
 
@@ -0,0 +1,140 @@
+use cpio_reader::iter_files;
+use ed25519_dalek::{PublicKey, Signature};
+use sel4_common::boot_handoff::{
+    BootMeasurement, MEASUREMENT_DIGEST_LEN, MEASUREMENT_SIGNED, MEASUREMENT_VERIFIED,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{config::MANIFEST_PUBLIC_KEY, println};
+
+/* in the format of sha256sum, one "<hex digest>  <file name>" line per image */
+pub const MANIFEST_NAME: &str = "manifest";
+/* raw Ed25519 signature of the manifest file */
+pub const MANIFEST_SIG_NAME: &str = "manifest.sig";
+
+const PUBLIC_KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+type Sha256Digest = [u8; MEASUREMENT_DIGEST_LEN];
+
+fn sha256(data: &[u8]) -> Sha256Digest {
+    let mut digest = [0; MEASUREMENT_DIGEST_LEN];
+    digest.copy_from_slice(&Sha256::digest(data));
+    digest
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex<const N: usize>(hex: &[u8]) -> Option<[u8; N]> {
+    if hex.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = hex_digit(hex[i * 2])? << 4 | hex_digit(hex[i * 2 + 1])?;
+    }
+    Some(bytes)
+}
+
+fn find_entry<'a>(cpio: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    iter_files(cpio)
+        .find(|entry| entry.name() == name)
+        .map(|entry| entry.file())
+}
+
+/// 解析manifest中的一行，sha256sum在文件名前用' '表示文本模式，用'*'表示二进制模式
+fn parse_manifest_line(line: &[u8]) -> Option<(Sha256Digest, &str)> {
+    let hex_len = MEASUREMENT_DIGEST_LEN * 2;
+    if line.len() <= hex_len + 2 || line[hex_len] != b' ' {
+        return None;
+    }
+    if line[hex_len + 1] != b' ' && line[hex_len + 1] != b'*' {
+        return None;
+    }
+    let digest = parse_hex(&line[..hex_len])?;
+    let name = core::str::from_utf8(&line[hex_len + 2..]).ok()?;
+    Some((digest, name))
+}
+
+fn manifest_lines(manifest: &[u8]) -> impl Iterator<Item = (Sha256Digest, &str)> {
+    manifest
+        .split(|&c| c == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_manifest_line(line).unwrap_or_else(|| panic!("Malformed manifest line")))
+}
+
+fn verify_signature(manifest: &[u8], signature: Option<&[u8]>, public_key: &str) {
+    let public_key = parse_hex::<PUBLIC_KEY_LEN>(public_key.as_bytes())
+        .and_then(|key| PublicKey::from_bytes(&key).ok())
+        .expect("Invalid MANIFEST_PUBLIC_KEY built into the elfloader");
+    let signature = signature
+        .filter(|sig| sig.len() == SIGNATURE_LEN)
+        .and_then(|sig| Signature::try_from(sig).ok())
+        .expect("Manifest signature missing or malformed");
+    if public_key.verify_strict(manifest, &signature).is_err() {
+        panic!("Manifest signature verification failed");
+    }
+    println!("Manifest signature verified");
+}
+
+/// 在解压和加载任何镜像之前检查归档中镜像的摘要和manifest的签名，并计算度量值
+///
+/// images为按加载顺序排列的（内核、rootserver、其它镜像）归档中的原始文件
+pub fn measure_images<'a>(
+    cpio: &[u8],
+    images: impl Iterator<Item = (&'a str, &'a [u8])>,
+) -> BootMeasurement {
+    let manifest = find_entry(cpio, MANIFEST_NAME);
+    let signature = find_entry(cpio, MANIFEST_SIG_NAME);
+
+    let mut flags = 0;
+    match (manifest, MANIFEST_PUBLIC_KEY) {
+        (Some(manifest), Some(public_key)) => {
+            verify_signature(manifest, signature, public_key);
+            flags |= MEASUREMENT_SIGNED | MEASUREMENT_VERIFIED;
+        }
+        (Some(_), None) => {
+            if signature.is_some() {
+                println!("WARNING: no public key built into the elfloader, manifest signature not checked");
+            }
+            flags |= MEASUREMENT_VERIFIED;
+        }
+        (None, Some(_)) => {
+            panic!("No manifest in archive, but the elfloader requires signed images")
+        }
+        (None, None) => println!("WARNING: no manifest in archive, images are not verified"),
+    }
+
+    let mut measurement = Sha256::new();
+    for (name, file) in images {
+        let digest = sha256(file);
+        if let Some(manifest) = manifest {
+            match manifest_lines(manifest).find(|&(_, n)| n == name) {
+                Some((expected, _)) if expected == digest => {}
+                Some(_) => panic!("Digest of image {:?} does not match the manifest", name),
+                None => panic!("Image {:?} is not listed in the manifest", name),
+            }
+        }
+        measurement.update(digest);
+    }
+    if let Some(manifest) = manifest {
+        /* an image listed but missing from the archive has been removed */
+        for (_, name) in manifest_lines(manifest) {
+            if find_entry(cpio, name).is_none() {
+                panic!("Image {:?} listed in the manifest is missing", name);
+            }
+        }
+        println!("All images match the manifest");
+    }
+
+    let mut digest = [0; MEASUREMENT_DIGEST_LEN];
+    digest.copy_from_slice(&measurement.finalize());
+    BootMeasurement { flags, digest }
+}
@@ -0,0 +1,137 @@
+use sel4_common::fdt::Fdt;
+
+use crate::{
+    config::{FALLBACK_RAM_END, FALLBACK_RAM_START},
+    mask, println,
+};
+
+/* entries of the memory map, QEMU virt has one memory node and a few reserved regions */
+const MAX_REGIONS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Region {
+    start: usize,
+    end: usize,
+}
+
+impl Region {
+    const fn empty() -> Self {
+        Self { start: 0, end: 0 }
+    }
+
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        start < self.end && self.start < end
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Regions {
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+impl Regions {
+    const fn new() -> Self {
+        Self {
+            regions: [Region::empty(); MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        if self.len == MAX_REGIONS {
+            panic!("too many memory regions (max {})", MAX_REGIONS);
+        }
+        self.regions[self.len] = Region { start, end };
+        self.len += 1;
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.len].iter()
+    }
+}
+
+/// 物理内存以及其中已被占用的区域，镜像从中分配放置的位置
+pub struct MemoryMap {
+    ram: Regions,
+    reserved: Regions,
+}
+
+fn align_up(addr: usize, bits: usize) -> usize {
+    (addr + mask!(bits)) & !mask!(bits)
+}
+
+impl MemoryMap {
+    /// 由bootloader传来的设备树得到物理内存，没有设备树时使用FALLBACK_RAM_*
+    pub fn new(dtb: Option<&[u8]>) -> Self {
+        let mut map = Self {
+            ram: Regions::new(),
+            reserved: Regions::new(),
+        };
+        if let Some(dtb) = dtb {
+            let parsed = Fdt::new(dtb).and_then(|fdt| {
+                fdt.for_each_memory_region(
+                    |reg| {
+                        map.ram.push(reg.start, reg.end);
+                        Some(())
+                    },
+                    |reg| {
+                        map.reserved.push(reg.start, reg.end);
+                        Some(())
+                    },
+                )
+            });
+            if parsed.is_none() {
+                panic!("malformed DTB");
+            }
+        }
+        if map.ram.len == 0 {
+            println!("No memory in the DTB, using the default memory region");
+            map.ram.push(FALLBACK_RAM_START, FALLBACK_RAM_END);
+        }
+        for r in map.ram.iter() {
+            println!("  memory=[{:#x?}..{:#x?}]", r.start, r.end - 1);
+        }
+        for r in map.reserved.iter() {
+            println!("  reserved=[{:#x?}..{:#x?}]", r.start, r.end - 1);
+        }
+        map
+    }
+
+    /// 标记[start, end)已被占用，之后的分配不会与它重叠
+    pub fn reserve(&mut self, start: usize, end: usize) {
+        self.reserved.push(start, end);
+    }
+
+    /// 从地址最低处分配size字节，起始地址按2^align_bits对齐，找不到时panic
+    pub fn alloc(&mut self, name: &str, size: usize, align_bits: usize) -> usize {
+        let mut best: Option<usize> = None;
+        for r in self.ram.iter() {
+            let mut start = align_up(r.start, align_bits);
+            /* every reserved region moves the start forward, so this terminates */
+            while let Some(res) = self
+                .reserved
+                .iter()
+                .find(|res| res.overlaps(start, start + size))
+            {
+                start = align_up(res.end, align_bits);
+            }
+            if start + size <= r.end && best.map_or(true, |best| start < best) {
+                best = Some(start);
+            }
+        }
+        let start = best.unwrap_or_else(|| {
+            panic!(
+                "no free memory for {} ({:#x?} bytes, aligned to {:#x?})",
+                name,
+                size,
+                mask!(align_bits) + 1
+            )
+        });
+        self.reserve(start, start + size);
+        start
+    }
+}
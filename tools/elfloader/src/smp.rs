@@ -0,0 +1,124 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use sel4_common::boot_handoff::BootHandoff;
+
+use crate::{
+    config::{CONFIG_MAX_NUM_NODES, MAX_HART_ID},
+    machine::{
+        enable_virtual_memory, sbi_get_hart_status, sbi_hart_start, sbi_hsm_available,
+        HSM_STATUS_STOPPED,
+    },
+    println, InitRiscvKernelFn,
+};
+
+/* how long to wait for harts that entered without SBI HSM */
+const LEGACY_HART_WAIT_LOOPS: usize = 1 << 24;
+
+/* secondary harts can get here before the boot hart clears the bss, so
+ * everything they touch lives in .data */
+#[link_section = ".data"]
+static CORES_UP: AtomicUsize = AtomicUsize::new(0);
+/* hart id of every secondary core, indexed by core id */
+#[link_section = ".data"]
+static HART_IDS: [AtomicUsize; CONFIG_MAX_NUM_NODES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+/* set by the boot hart once the images are loaded and the page table is ready */
+#[link_section = ".data"]
+static KERNEL_RELEASE: AtomicBool = AtomicBool::new(false);
+#[link_section = ".data"]
+static KERNEL_ENTRY: AtomicUsize = AtomicUsize::new(0);
+#[link_section = ".data"]
+static HANDOFF: AtomicUsize = AtomicUsize::new(0);
+
+/// 通过SBI HSM启动其它处于停止状态的hart，返回启动的hart数
+fn start_secondary_harts(boot_hart_id: usize) -> usize {
+    extern "C" {
+        fn secondary_harts();
+    }
+    let mut started = 0;
+    for hart_id in 0..MAX_HART_ID {
+        if started == CONFIG_MAX_NUM_NODES - 1 {
+            break;
+        }
+        if hart_id == boot_hart_id || sbi_get_hart_status(hart_id) != Some(HSM_STATUS_STOPPED) {
+            continue;
+        }
+        /* harts that cannot run in S-mode, e.g. monitor cores, fail to start */
+        if sbi_hart_start(hart_id, secondary_harts as usize, 0) == 0 {
+            started += 1;
+        }
+    }
+    started
+}
+
+/// 启动从核并等待它们就绪，把每个核的hart id填入handoff
+pub fn boot_secondary_harts(handoff: &mut BootHandoff, boot_hart_id: usize) {
+    if sbi_hsm_available() {
+        let started = start_secondary_harts(boot_hart_id);
+        while CORES_UP.load(Ordering::Acquire) < started {
+            core::hint::spin_loop();
+        }
+    } else {
+        /* without HSM the firmware either sent all harts to _start at once or
+         * keeps them to itself, there is no way to tell how many will show up */
+        for _ in 0..LEGACY_HART_WAIT_LOOPS {
+            core::hint::spin_loop();
+        }
+    }
+
+    let cores_up = CORES_UP.load(Ordering::Acquire);
+    handoff.num_nodes = 1 + cores_up;
+    handoff.hart_ids[0] = boot_hart_id;
+    for core_id in 1..handoff.num_nodes {
+        handoff.hart_ids[core_id] = HART_IDS[core_id].load(Ordering::Relaxed);
+    }
+    println!(
+        "{} secondary hart(s) up: {:?}",
+        cores_up,
+        &handoff.hart_ids()[1..]
+    );
+}
+
+/// 让从核跳转到内核，之后从核不再访问elfloader的数据
+pub fn release_secondary_harts(kernel_entry: usize, handoff: &BootHandoff) {
+    KERNEL_ENTRY.store(kernel_entry, Ordering::Relaxed);
+    HANDOFF.store(handoff as *const _ as usize, Ordering::Relaxed);
+    KERNEL_RELEASE.store(true, Ordering::Release);
+}
+
+/// 从核的入口，core_id由crt0.S分配，不会超过CONFIG_MAX_NUM_NODES - 1
+#[no_mangle]
+pub fn secondary_main(hart_id: usize, core_id: usize) -> ! {
+    HART_IDS[core_id].store(hart_id, Ordering::Relaxed);
+    CORES_UP.fetch_add(1, Ordering::Release);
+
+    while !KERNEL_RELEASE.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    /* a hart that came up after the boot hart counted the cores is not in the
+     * handoff, keep it out of the kernel */
+    let handoff = HANDOFF.load(Ordering::Relaxed) as *const BootHandoff;
+    if core_id >= unsafe { (*handoff).num_nodes } {
+        extern "C" {
+            fn hart_park() -> !;
+        }
+        unsafe { hart_park() };
+    }
+
+    enable_virtual_memory();
+    let kernel_entry = unsafe {
+        /* the kernel image was written by the boot hart */
+        core::arch::asm!("fence.i");
+        core::mem::transmute::<_, InitRiscvKernelFn>(KERNEL_ENTRY.load(Ordering::Relaxed))
+    };
+    kernel_entry(handoff, hart_id, core_id);
+    panic!("Shouldn't reach here!");
+}
@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    /* LZ4 frames with the content size, decompressed by the elfloader */
+    Lz4,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Lz4
+    }
+}
+
+/// 系统镜像的描述文件，相对路径都相对于描述文件所在的目录
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageConfig {
+    pub kernel: PathBuf,
+    pub rootserver: PathBuf,
+    /* extra component ELF files, packed after the root server under their file names */
+    #[serde(default)]
+    pub components: Vec<PathBuf>,
+    #[serde(default)]
+    pub compression: Compression,
+    /* Ed25519 private key in PEM format to sign the manifest with */
+    pub signing_key: Option<PathBuf>,
+    /* elfloader crate the archive is linked into, tools/elfloader by default */
+    pub elfloader: Option<PathBuf>,
+    /* flat binary, the firmware may load it at any 4K aligned address */
+    pub output: PathBuf,
+}
+
+impl ImageConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+        let mut config: ImageConfig =
+            toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        let resolve = |p: &mut PathBuf| *p = base.join(&*p);
+        resolve(&mut config.kernel);
+        resolve(&mut config.rootserver);
+        config.components.iter_mut().for_each(resolve);
+        config.signing_key.iter_mut().for_each(resolve);
+        config.elfloader.iter_mut().for_each(resolve);
+        resolve(&mut config.output);
+        Ok(config)
+    }
+}
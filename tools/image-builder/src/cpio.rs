@@ -0,0 +1,48 @@
+/* "newc" format, see cpio(5). Every field that is not needed by the elfloader
+ * is fixed, so that the same inputs always give the same archive */
+const NEWC_MAGIC: &str = "070701";
+const NEWC_TRAILER: &str = "TRAILER!!!";
+const MODE_REGULAR_FILE: u32 = 0o100644;
+
+fn pad4(buf: &mut Vec<u8>) {
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+fn write_entry(buf: &mut Vec<u8>, ino: u32, mode: u32, name: &str, data: &[u8]) {
+    let fields = [
+        ino,
+        mode,
+        0, /* uid */
+        0, /* gid */
+        1, /* nlink */
+        0, /* mtime */
+        data.len() as u32,
+        0, /* devmajor */
+        0, /* devminor */
+        0, /* rdevmajor */
+        0, /* rdevminor */
+        name.len() as u32 + 1,
+        0, /* check */
+    ];
+    buf.extend_from_slice(NEWC_MAGIC.as_bytes());
+    for field in fields {
+        buf.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    buf.extend_from_slice(name.as_bytes());
+    buf.push(0);
+    pad4(buf);
+    buf.extend_from_slice(data);
+    pad4(buf);
+}
+
+/// 按给定顺序把文件打包成cpio归档
+pub fn archive<'a>(files: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (ino, (name, data)) in (1..).zip(files) {
+        write_entry(&mut buf, ino, MODE_REGULAR_FILE, name, data);
+    }
+    write_entry(&mut buf, 0, 0, NEWC_TRAILER, &[]);
+    buf
+}
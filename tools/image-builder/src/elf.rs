@@ -0,0 +1,80 @@
+use xmas_elf::{
+    header::{Class, Data, Type},
+    program::{self, ProgramHeader},
+    ElfFile,
+};
+
+use crate::boot_handoff::IMAGE_MAX_SEGMENTS;
+
+const EM_RISCV: u16 = 243;
+/* e_machine in the ELF header */
+const E_MACHINE_OFFSET: usize = 18;
+
+fn load_segments<'a>(elf: &'a ElfFile) -> impl Iterator<Item = ProgramHeader<'a>> {
+    elf.program_iter()
+        .filter(|header| header.get_type() == Ok(program::Type::Load))
+}
+
+/// 检查elfloader能否加载这个ELF文件，和elfloader中unpack_elf所做的检查一致
+pub fn validate(data: &[u8]) -> Result<(), String> {
+    let elf = ElfFile::new(data)?;
+    if elf.header.pt1.class() != Class::SixtyFour || elf.header.pt1.data() != Data::LittleEndian {
+        return Err("not a little endian ELF64 file".into());
+    }
+    let machine = u16::from_le_bytes([data[E_MACHINE_OFFSET], data[E_MACHINE_OFFSET + 1]]);
+    if machine != EM_RISCV {
+        return Err(format!("not a RISC-V ELF file (e_machine {})", machine));
+    }
+    if elf.header.pt2.type_().as_type() != Type::Executable {
+        return Err("not an executable".into());
+    }
+
+    let mut segments: Vec<(u64, u64)> = Vec::new();
+    let mut entry_is_executable = false;
+    let entry = elf.header.pt2.entry_point();
+    for header in load_segments(&elf) {
+        let (start, end) = (
+            header.virtual_addr(),
+            header.virtual_addr() + header.mem_size(),
+        );
+        if header.file_size() > header.mem_size() {
+            return Err(format!(
+                "segment at {:#x} is larger in the file than in memory",
+                start
+            ));
+        }
+        if header.offset() + header.file_size() > data.len() as u64 {
+            return Err(format!("segment at {:#x} is truncated", start));
+        }
+        if let Some(&(other_start, other_end)) = segments
+            .iter()
+            .find(|&&(other_start, other_end)| start < other_end && other_start < end)
+        {
+            return Err(format!(
+                "segment [{:#x}..{:#x}) overlaps segment [{:#x}..{:#x})",
+                start, end, other_start, other_end
+            ));
+        }
+        if (start..end).contains(&entry) && header.flags().is_execute() {
+            entry_is_executable = true;
+        }
+        segments.push((start, end));
+    }
+    if segments.is_empty() {
+        return Err("no loadable segments".into());
+    }
+    if segments.len() > IMAGE_MAX_SEGMENTS {
+        return Err(format!(
+            "{} loadable segments, the elfloader supports at most {}",
+            segments.len(),
+            IMAGE_MAX_SEGMENTS
+        ));
+    }
+    if !entry_is_executable {
+        return Err(format!(
+            "entry point {:#x} is not in an executable segment",
+            entry
+        ));
+    }
+    Ok(())
+}
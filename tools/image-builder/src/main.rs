@@ -0,0 +1,266 @@
+mod config;
+mod cpio;
+mod elf;
+
+/* shared with the elfloader and the kernel, it has no dependencies */
+#[path = "../../../sel4-common/src/boot_handoff.rs"]
+#[allow(dead_code)]
+mod boot_handoff;
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use boot_handoff::{BOOT_HANDOFF_MAX_USER_IMAGES, IMAGE_NAME_LEN};
+use config::{Compression, ImageConfig};
+use sha2::{Digest, Sha256};
+
+/* cpio entry names the elfloader looks for, see tools/elfloader/src/common.rs and manifest.rs */
+const KERNEL_NAME: &str = "kernel";
+const ROOTSERVER_NAME: &str = "app";
+const MANIFEST_NAME: &str = "manifest";
+const MANIFEST_SIG_NAME: &str = "manifest.sig";
+
+/* the elfloader includes the archive from here, see tools/elfloader/src/archive.S */
+const ARCHIVE_PATH: &str = "target/archive.archive.o.cpio";
+const ELFLOADER_TARGET: &str = "riscv64gc-unknown-none-elf";
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+struct Image {
+    name: String,
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 运行外部命令，返回它的标准输出
+fn run(cmd: &mut Command) -> Result<Vec<u8>, String> {
+    let output = cmd
+        .output()
+        .map_err(|err| format!("cannot run {:?}: {}", cmd, err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn read_image(name: &str, path: &Path) -> Result<Image, String> {
+    let data =
+        std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    elf::validate(&data).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(Image {
+        name: name.into(),
+        path: path.into(),
+        data,
+    })
+}
+
+/// 按elfloader的加载顺序读取并检查所有镜像：内核、rootserver、其它组件
+fn read_images(config: &ImageConfig) -> Result<Vec<Image>, String> {
+    let mut images = vec![
+        read_image(KERNEL_NAME, &config.kernel)?,
+        read_image(ROOTSERVER_NAME, &config.rootserver)?,
+    ];
+    for path in &config.components {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{}: not a file name", path.display()))?;
+        if [
+            KERNEL_NAME,
+            ROOTSERVER_NAME,
+            MANIFEST_NAME,
+            MANIFEST_SIG_NAME,
+        ]
+        .contains(&name)
+        {
+            return Err(format!(
+                "{}: file name {:?} is reserved",
+                path.display(),
+                name
+            ));
+        }
+        if name.len() > IMAGE_NAME_LEN {
+            return Err(format!(
+                "{}: file name longer than {} bytes",
+                path.display(),
+                IMAGE_NAME_LEN
+            ));
+        }
+        if images.iter().any(|image| image.name == name) {
+            return Err(format!(
+                "{}: duplicate component name {:?}",
+                path.display(),
+                name
+            ));
+        }
+        images.push(read_image(name, path)?);
+    }
+    if images.len() - 1 > BOOT_HANDOFF_MAX_USER_IMAGES {
+        return Err(format!(
+            "{} user images, the elfloader supports at most {}",
+            images.len() - 1,
+            BOOT_HANDOFF_MAX_USER_IMAGES
+        ));
+    }
+    Ok(images)
+}
+
+fn lz4_compress(work_dir: &Path, image: &Image) -> Result<Vec<u8>, String> {
+    let input = work_dir.join(&image.name);
+    let output = work_dir.join(format!("{}.lz4", image.name));
+    std::fs::write(&input, &image.data).map_err(|err| err.to_string())?;
+    /* the elfloader needs the content size to place the decompressed image */
+    run(Command::new("lz4")
+        .args(["-9", "-q", "-f", "--content-size"])
+        .arg(&input)
+        .arg(&output))?;
+    std::fs::read(&output).map_err(|err| err.to_string())
+}
+
+/// 与sha256sum的输出格式相同
+fn manifest(files: &[(String, Vec<u8>)]) -> String {
+    files
+        .iter()
+        .map(|(name, data)| format!("{}  {}\n", hex(&Sha256::digest(data)), name))
+        .collect()
+}
+
+/// 用Ed25519私钥签名manifest，返回签名和十六进制的公钥
+fn sign(work_dir: &Path, key: &Path, manifest: &str) -> Result<(Vec<u8>, String), String> {
+    let manifest_path = work_dir.join(MANIFEST_NAME);
+    let sig_path = work_dir.join(MANIFEST_SIG_NAME);
+    std::fs::write(&manifest_path, manifest).map_err(|err| err.to_string())?;
+    run(Command::new("openssl")
+        .args(["pkeyutl", "-sign", "-rawin", "-inkey"])
+        .arg(key)
+        .arg("-in")
+        .arg(&manifest_path)
+        .arg("-out")
+        .arg(&sig_path))?;
+    let signature = std::fs::read(&sig_path).map_err(|err| err.to_string())?;
+
+    /* the raw key is at the end of the DER encoded SubjectPublicKeyInfo */
+    let der = run(Command::new("openssl")
+        .args(["pkey", "-pubout", "-outform", "DER", "-in"])
+        .arg(key))?;
+    if der.len() < ED25519_PUBLIC_KEY_LEN {
+        return Err(format!("{}: not an Ed25519 key", key.display()));
+    }
+    Ok((signature, hex(&der[der.len() - ED25519_PUBLIC_KEY_LEN..])))
+}
+
+/// 用新的归档重新编译elfloader，并生成可以直接加载的二进制镜像
+fn link_elfloader(
+    elfloader: &Path,
+    archive: &[u8],
+    public_key: Option<&str>,
+    output: &Path,
+) -> Result<(), String> {
+    let archive_path = elfloader.join(ARCHIVE_PATH);
+    std::fs::write(&archive_path, archive)
+        .map_err(|err| format!("cannot write {}: {}", archive_path.display(), err))?;
+
+    /* cargo does not know about the .incbin in archive.S */
+    run(Command::new("cargo").current_dir(elfloader).args([
+        "clean",
+        "--release",
+        "-p",
+        "elfloader",
+    ]))?;
+    let mut build = Command::new("cargo");
+    build.current_dir(elfloader).args(["build", "--release"]);
+    match public_key {
+        Some(key) => build.env("MANIFEST_PUBLIC_KEY", key),
+        None => build.env_remove("MANIFEST_PUBLIC_KEY"),
+    };
+    run(&mut build)?;
+
+    let elf = elfloader
+        .join("target")
+        .join(ELFLOADER_TARGET)
+        .join("release")
+        .join("elfloader");
+    run(Command::new("rust-objcopy")
+        .args([
+            "--binary-architecture=riscv64",
+            "--strip-all",
+            "-O",
+            "binary",
+        ])
+        .arg(&elf)
+        .arg(output))?;
+    Ok(())
+}
+
+fn build(config: &ImageConfig) -> Result<(), String> {
+    let elfloader = config
+        .elfloader
+        .clone()
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("../elfloader"));
+    let work_dir = elfloader.join("target/image-builder");
+    std::fs::create_dir_all(&work_dir)
+        .map_err(|err| format!("cannot create {}: {}", work_dir.display(), err))?;
+
+    let images = read_images(config)?;
+    let mut files = Vec::new();
+    for image in &images {
+        let data = match config.compression {
+            Compression::None => image.data.clone(),
+            Compression::Lz4 => lz4_compress(&work_dir, image)?,
+        };
+        println!(
+            "{:<16} {:>10} -> {:>10} bytes  {}",
+            image.name,
+            image.data.len(),
+            data.len(),
+            image.path.display()
+        );
+        files.push((image.name.clone(), data));
+    }
+
+    let manifest = manifest(&files);
+    let mut public_key = None;
+    let mut signature = None;
+    if let Some(key) = &config.signing_key {
+        let (sig, key) = sign(&work_dir, key, &manifest)?;
+        println!("manifest signed, public key {}", key);
+        signature = Some(sig);
+        public_key = Some(key);
+    }
+
+    let mut entries: Vec<(&str, &[u8])> = files
+        .iter()
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .collect();
+    entries.push((MANIFEST_NAME, manifest.as_bytes()));
+    if let Some(sig) = &signature {
+        entries.push((MANIFEST_SIG_NAME, sig));
+    }
+    let archive = cpio::archive(entries);
+
+    link_elfloader(&elfloader, &archive, public_key.as_deref(), &config.output)?;
+    println!("image written to {}", config.output.display());
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <image.toml>", args[0]);
+        std::process::exit(2);
+    }
+    let result = ImageConfig::load(Path::new(&args[1])).and_then(|config| build(&config));
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,222 @@
+/* shared with the kernel and the user library, they have no dependencies */
+#[path = "../../../sel4-common/src/syscall_ids.rs"]
+#[allow(non_upper_case_globals)]
+mod syscall_ids;
+#[path = "../../../sel4-common/src/trace.rs"]
+#[allow(dead_code)]
+mod trace;
+
+use std::{fmt::Write, mem::size_of, path::Path};
+
+use syscall_ids::*;
+use trace::{
+    TraceEntry, TraceLogHeader, TRACE_LOG_MAGIC, TRACE_PATH_EXCEPTION, TRACE_PATH_INTERRUPT,
+    TRACE_PATH_SYSCALL,
+};
+
+/* timebase of QEMU virt, used when neither the log nor the command line has one */
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+const SCAUSE_INTERRUPT: u64 = 1 << 63;
+
+fn u32_at(buf: &[u8], off: usize) -> Result<u32, String> {
+    let b = buf.get(off..off + 4).ok_or("trace log truncated")?;
+    Ok(u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn u64_at(buf: &[u8], off: usize) -> Result<u64, String> {
+    let b = buf.get(off..off + 8).ok_or("trace log truncated")?;
+    Ok(u64::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn read_header(buf: &[u8]) -> Result<TraceLogHeader, String> {
+    let header = TraceLogHeader {
+        magic: u32_at(buf, 0)?,
+        entry_size: u32_at(buf, 4)?,
+        capacity: u64_at(buf, 8)?,
+        count: u64_at(buf, 16)?,
+        timebase_frequency: u64_at(buf, 24)?,
+    };
+    if header.magic != TRACE_LOG_MAGIC {
+        return Err(format!(
+            "bad magic {:#x}, not a kernel trace log",
+            header.magic
+        ));
+    }
+    if header.entry_size as usize != size_of::<TraceEntry>() {
+        return Err(format!(
+            "entry size {} does not match this decoder ({})",
+            header.entry_size,
+            size_of::<TraceEntry>()
+        ));
+    }
+    Ok(header)
+}
+
+fn read_entry(buf: &[u8], index: u64) -> Result<TraceEntry, String> {
+    let off = size_of::<TraceLogHeader>() + index as usize * size_of::<TraceEntry>();
+    Ok(TraceEntry {
+        path: u32_at(buf, off)?,
+        core: u32_at(buf, off + 4)?,
+        cause: u64_at(buf, off + 8)?,
+        tcb: u64_at(buf, off + 16)?,
+        start: u64_at(buf, off + 24)?,
+        end: u64_at(buf, off + 32)?,
+    })
+}
+
+/// 按写入顺序读出环形缓冲区中的记录，写满后最旧的在count % capacity处
+fn read_entries(buf: &[u8], header: &TraceLogHeader) -> Result<Vec<TraceEntry>, String> {
+    if header.capacity == 0 {
+        return Ok(Vec::new());
+    }
+    let (first, len) = if header.count > header.capacity {
+        (header.count % header.capacity, header.capacity)
+    } else {
+        (0, header.count)
+    };
+    (0..len)
+        .map(|i| read_entry(buf, (first + i) % header.capacity))
+        .collect()
+}
+
+#[allow(non_upper_case_globals)]
+fn syscall_name(syscall: u64) -> String {
+    let name = match syscall as usize {
+        seL4_SysCall => "Call",
+        seL4_SysReplyRecv => "ReplyRecv",
+        seL4_SysSend => "Send",
+        seL4_SysNBSend => "NBSend",
+        seL4_SysRecv => "Recv",
+        seL4_SysReply => "Reply",
+        seL4_SysYield => "Yield",
+        seL4_SysNBRecv => "NBRecv",
+        seL4_SysDebugPutChar => "DebugPutChar",
+        seL4_SysDebugDumpScheduler => "DebugDumpScheduler",
+        seL4_SysBenchmarkResetLog => "BenchmarkResetLog",
+        seL4_SysBenchmarkFinalizeLog => "BenchmarkFinalizeLog",
+        seL4_SysBenchmarkSetLogBuffer => "BenchmarkSetLogBuffer",
+        _ => return format!("syscall {}", syscall as i64),
+    };
+    name.into()
+}
+
+/// scause的名字，见RISC-V特权级手册中的scause表
+fn cause_name(scause: u64) -> String {
+    let code = scause & !SCAUSE_INTERRUPT;
+    let name = if scause & SCAUSE_INTERRUPT != 0 {
+        match code {
+            1 => "supervisor software interrupt",
+            5 => "supervisor timer interrupt",
+            9 => "supervisor external interrupt",
+            _ => return format!("interrupt {}", code),
+        }
+    } else {
+        match code {
+            0 => "instruction address misaligned",
+            1 => "instruction access fault",
+            2 => "illegal instruction",
+            3 => "breakpoint",
+            4 => "load address misaligned",
+            5 => "load access fault",
+            6 => "store address misaligned",
+            7 => "store access fault",
+            8 => "user ecall",
+            12 => "instruction page fault",
+            13 => "load page fault",
+            15 => "store page fault",
+            _ => return format!("exception {}", code),
+        }
+    };
+    name.into()
+}
+
+/// 生成Chrome trace（chrome://tracing、Perfetto）格式的JSON，每个核一条轨道
+fn to_chrome_trace(entries: &[TraceEntry], timebase_frequency: u64) -> String {
+    let base = entries.iter().map(|e| e.start).min().unwrap_or(0);
+    let to_us = |ticks: u64| ticks as f64 * 1_000_000.0 / timebase_frequency as f64;
+    let mut cores: Vec<u32> = entries.iter().map(|e| e.core).collect();
+    cores.sort_unstable();
+    cores.dedup();
+
+    let mut events = Vec::new();
+    for core in cores {
+        events.push(format!(
+            r#"{{"name":"thread_name","ph":"M","pid":0,"tid":{},"args":{{"name":"core {}"}}}}"#,
+            core, core
+        ));
+    }
+    for e in entries {
+        let (cat, name) = match e.path {
+            TRACE_PATH_SYSCALL => ("syscall", syscall_name(e.cause)),
+            TRACE_PATH_INTERRUPT => ("interrupt", cause_name(e.cause)),
+            TRACE_PATH_EXCEPTION => ("exception", cause_name(e.cause)),
+            _ => ("unknown", format!("path {}", e.path)),
+        };
+        let mut event = String::new();
+        write!(
+            event,
+            r#"{{"name":"{}","cat":"{}","ph":"X","ts":{:.3},"dur":{:.3},"pid":0,"tid":{},"args":{{"tcb":"{:#x}","cause":"{:#x}"}}}}"#,
+            name,
+            cat,
+            to_us(e.start - base),
+            to_us(e.end.saturating_sub(e.start)),
+            e.core,
+            e.tcb,
+            e.cause
+        )
+        .unwrap();
+        events.push(event);
+    }
+    format!(
+        "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n{}\n]}}\n",
+        events.join(",\n")
+    )
+}
+
+fn decode(path: &Path, timebase_frequency: Option<u64>) -> Result<String, String> {
+    let buf =
+        std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    let header = read_header(&buf)?;
+    let entries = read_entries(&buf, &header)?;
+    if header.count > header.capacity {
+        eprintln!(
+            "log wrapped, only the last {} of {} entries are kept",
+            header.capacity, header.count
+        );
+    }
+    let frequency = match timebase_frequency {
+        Some(frequency) => frequency,
+        None if header.timebase_frequency != 0 => header.timebase_frequency,
+        None => {
+            eprintln!(
+                "no timebase frequency in the log, assuming {} Hz",
+                DEFAULT_TIMEBASE_FREQUENCY
+            );
+            DEFAULT_TIMEBASE_FREQUENCY
+        }
+    };
+    Ok(to_chrome_trace(&entries, frequency))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("usage: {} <trace.bin> [timebase-frequency]", args[0]);
+        std::process::exit(2);
+    }
+    let timebase_frequency = match args.get(2).map(|arg| arg.parse::<u64>()) {
+        Some(Ok(0)) | Some(Err(_)) => {
+            eprintln!("error: invalid timebase frequency {}", args[2]);
+            std::process::exit(2);
+        }
+        Some(Ok(frequency)) => Some(frequency),
+        None => None,
+    };
+    match decode(Path::new(&args[1]), timebase_frequency) {
+        Ok(json) => print!("{}", json),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}